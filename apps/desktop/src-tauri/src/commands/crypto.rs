@@ -1,25 +1,44 @@
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use chacha20poly1305::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     ChaCha20Poly1305, Nonce,
 };
-use ed25519_dalek::SigningKey;
+use chrono::Utc;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+pub mod backup;
+pub mod batch;
+pub mod channel_keys;
+pub mod devices;
+pub mod envelope;
+pub mod handles;
+pub mod hpke;
+pub mod media;
+pub mod padding;
+pub mod pq;
+pub mod ratchet;
+pub mod safety;
+pub mod sas;
+pub mod sender_keys;
+pub mod stream;
+pub mod trust;
+pub mod x3dh;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeyPair {
+    /// Opaque handle for the private keys, held in the Rust key registry
+    pub handle: String,
     /// X25519 public key for key exchange (base64)
     pub x25519_public: String,
-    /// X25519 private key for key exchange (base64)
-    pub x25519_private: String,
     /// Ed25519 public key for signing (base64)
     pub ed25519_public: String,
-    /// Ed25519 private key for signing (base64)
-    pub ed25519_private: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,9 +47,95 @@ pub struct EncryptedPayload {
     pub ciphertext: String,
     /// Base64-encoded nonce (12 bytes)
     pub nonce: String,
+    /// The plaintext was padded before encryption; see [`padding`]
+    #[serde(default)]
+    pub padded: bool,
+}
+
+/// Current message protocol version, bound into [`AssociatedData`].
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Message context bound to a ciphertext as AEAD associated data.
+///
+/// Decryption only succeeds with exactly the context used for encryption, so
+/// a ciphertext replayed into another channel, under another author or in
+/// place of another message is rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssociatedData {
+    pub channel_id: String,
+    pub message_id: String,
+    pub author_id: String,
+    #[serde(default = "default_protocol_version")]
+    pub version: u32,
+}
+
+fn default_protocol_version() -> u32 {
+    PROTOCOL_VERSION
+}
+
+impl AssociatedData {
+    /// Unambiguous encoding: a domain tag, the version, then each field as a
+    /// 4-byte big-endian length and its contents.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = b"zeusix-ad".to_vec();
+        out.extend_from_slice(&self.version.to_be_bytes());
+        for field in [&self.channel_id, &self.message_id, &self.author_id] {
+            out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            out.extend_from_slice(field.as_bytes());
+        }
+        out
+    }
+}
+
+/// Encode optional associated data; no context means empty AAD.
+fn encode_aad(associated_data: Option<&AssociatedData>) -> Vec<u8> {
+    associated_data.map(AssociatedData::encode).unwrap_or_default()
+}
+
+/// AAD for `encrypt_message`. Padded messages carry an extra tag, so the
+/// `padded` flag cannot be flipped without failing authentication.
+fn message_aad(associated_data: Option<&AssociatedData>, padded: bool) -> Vec<u8> {
+    let mut aad = encode_aad(associated_data);
+    if padded {
+        aad.extend_from_slice(b"zeusix-padded");
+    }
+    aad
+}
+
+/// Wipe every secret held outside the keystore: key handles, ratchet
+/// sessions, sender keys, media keys and in-progress verifications and
+/// device links. Called when the keystore locks.
+pub(crate) fn wipe_live_keys() {
+    handles::wipe();
+    ratchet::wipe();
+    sender_keys::wipe();
+    media::wipe();
+    sas::wipe();
+    devices::wipe();
+}
+
+/// Ciphertext plus the message context it was sent in, signed by the author's
+/// Ed25519 identity key.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedEnvelope {
+    /// Base64-encoded ciphertext
+    pub ciphertext: String,
+    /// Base64-encoded nonce (12 bytes)
+    pub nonce: String,
+    #[serde(default)]
+    pub padded: bool,
+    pub channel_id: String,
+    pub author_id: String,
+    /// RFC 3339 timestamp set by the author
+    pub timestamp: String,
+    /// Ed25519 signature over all fields above (base64)
+    pub signature: String,
 }
 
 /// Generate a new X25519 + Ed25519 keypair for encryption and signing.
+///
+/// The private keys stay in the key registry; only the public keys and a
+/// handle are returned.
 #[tauri::command]
 pub fn generate_keypair() -> Result<KeyPair, String> {
     // Generate X25519 keypair for Diffie-Hellman key exchange
@@ -41,78 +146,71 @@ pub fn generate_keypair() -> Result<KeyPair, String> {
     let ed25519_signing = SigningKey::generate(&mut OsRng);
     let ed25519_verifying = ed25519_signing.verifying_key();
 
+    let handle = handles::insert(handles::SecretKey::Identity {
+        x25519: x25519_secret,
+        ed25519: Box::new(ed25519_signing),
+    })?;
+
     Ok(KeyPair {
+        handle,
         x25519_public: BASE64.encode(x25519_public.as_bytes()),
-        x25519_private: BASE64.encode(x25519_secret.to_bytes()),
         ed25519_public: BASE64.encode(ed25519_verifying.as_bytes()),
-        ed25519_private: BASE64.encode(ed25519_signing.to_bytes()),
     })
 }
 
-/// Encrypt a plaintext message using ChaCha20-Poly1305.
-///
-/// `key_b64` must be a 32-byte key encoded as base64.
-/// Returns the ciphertext and nonce, both base64-encoded.
-#[tauri::command]
-pub fn encrypt_message(plaintext: String, key_b64: String) -> Result<EncryptedPayload, String> {
-    let key_bytes = BASE64
-        .decode(&key_b64)
-        .map_err(|e| format!("Invalid base64 key: {}", e))?;
-
-    if key_bytes.len() != 32 {
-        return Err(format!(
-            "Key must be 32 bytes, got {}",
-            key_bytes.len()
-        ));
-    }
-
-    let cipher = ChaCha20Poly1305::new_from_slice(&key_bytes)
-        .map_err(|e| format!("Failed to create cipher: {}", e))?;
-
+/// Encrypt one message under `cipher` with a fresh random nonce, padding it
+/// under `policy`.
+pub(crate) fn seal_message(
+    cipher: &ChaCha20Poly1305,
+    plaintext: &[u8],
+    associated_data: Option<&AssociatedData>,
+    policy: padding::PaddingPolicy,
+) -> Result<EncryptedPayload, String> {
     // Generate a random 12-byte nonce
     let mut nonce_bytes = [0u8; 12];
     use rand::RngCore;
     OsRng.fill_bytes(&mut nonce_bytes);
     let nonce = Nonce::from_slice(&nonce_bytes);
 
+    let padded = policy.pads();
+    let msg = if padded {
+        Zeroizing::new(padding::pad(plaintext, policy))
+    } else {
+        Zeroizing::new(plaintext.to_vec())
+    };
+
+    let aad = message_aad(associated_data, padded);
     let ciphertext = cipher
-        .encrypt(nonce, plaintext.as_bytes())
+        .encrypt(
+            nonce,
+            Payload {
+                msg: &msg,
+                aad: &aad,
+            },
+        )
         .map_err(|e| format!("Encryption failed: {}", e))?;
 
     Ok(EncryptedPayload {
         ciphertext: BASE64.encode(&ciphertext),
         nonce: BASE64.encode(nonce_bytes),
+        padded,
     })
 }
 
-/// Decrypt a ciphertext using ChaCha20-Poly1305.
-///
-/// `key_b64` must be a 32-byte key encoded as base64.
-/// `ciphertext_b64` is the base64-encoded ciphertext.
-/// `nonce_b64` is the base64-encoded 12-byte nonce.
-#[tauri::command]
-pub fn decrypt_message(
-    ciphertext_b64: String,
-    nonce_b64: String,
-    key_b64: String,
+/// Decrypt one base64 ciphertext/nonce pair under `cipher`.
+pub(crate) fn open_message(
+    cipher: &ChaCha20Poly1305,
+    ciphertext_b64: &str,
+    nonce_b64: &str,
+    associated_data: Option<&AssociatedData>,
+    padded: bool,
 ) -> Result<String, String> {
-    let key_bytes = BASE64
-        .decode(&key_b64)
-        .map_err(|e| format!("Invalid base64 key: {}", e))?;
-
-    if key_bytes.len() != 32 {
-        return Err(format!(
-            "Key must be 32 bytes, got {}",
-            key_bytes.len()
-        ));
-    }
-
     let ciphertext = BASE64
-        .decode(&ciphertext_b64)
+        .decode(ciphertext_b64)
         .map_err(|e| format!("Invalid base64 ciphertext: {}", e))?;
 
     let nonce_bytes = BASE64
-        .decode(&nonce_b64)
+        .decode(nonce_b64)
         .map_err(|e| format!("Invalid base64 nonce: {}", e))?;
 
     if nonce_bytes.len() != 12 {
@@ -122,64 +220,415 @@ pub fn decrypt_message(
         ));
     }
 
-    let cipher = ChaCha20Poly1305::new_from_slice(&key_bytes)
-        .map_err(|e| format!("Failed to create cipher: {}", e))?;
-
     let nonce = Nonce::from_slice(&nonce_bytes);
 
-    let plaintext = cipher
-        .decrypt(nonce, ciphertext.as_ref())
+    let aad = message_aad(associated_data, padded);
+    let mut plaintext = cipher
+        .decrypt(
+            nonce,
+            Payload {
+                msg: &ciphertext,
+                aad: &aad,
+            },
+        )
         .map_err(|e| format!("Decryption failed: {}", e))?;
+    if padded {
+        plaintext = padding::unpad(plaintext)?;
+    }
 
     String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8 in decrypted message: {}", e))
 }
 
+/// Encrypt a plaintext message using ChaCha20-Poly1305.
+///
+/// `key_handle` must refer to a symmetric key in the key registry.
+/// `associated_data` is the message context to bind to the ciphertext; the
+/// same context must be passed to `decrypt_message`.
+/// The plaintext is padded under the current padding policy first.
+/// Returns the ciphertext and nonce, both base64-encoded.
+#[tauri::command]
+pub fn encrypt_message(
+    plaintext: String,
+    key_handle: String,
+    associated_data: Option<AssociatedData>,
+) -> Result<EncryptedPayload, String> {
+    let key = handles::symmetric(&key_handle)?;
+    let cipher = ChaCha20Poly1305::new(key.as_ref().into());
+
+    seal_message(
+        &cipher,
+        plaintext.as_bytes(),
+        associated_data.as_ref(),
+        padding::policy()?,
+    )
+}
+
+/// Decrypt a ciphertext using ChaCha20-Poly1305.
+///
+/// `key_handle` must refer to a symmetric key in the key registry.
+/// `ciphertext_b64` is the base64-encoded ciphertext.
+/// `nonce_b64` is the base64-encoded 12-byte nonce.
+/// `associated_data` must match the context given to `encrypt_message`.
+/// `padded` is the payload's `padded` flag; absent means unpadded.
+#[tauri::command]
+pub fn decrypt_message(
+    ciphertext_b64: String,
+    nonce_b64: String,
+    key_handle: String,
+    associated_data: Option<AssociatedData>,
+    padded: Option<bool>,
+) -> Result<String, String> {
+    let key = handles::symmetric(&key_handle)?;
+    let cipher = ChaCha20Poly1305::new(key.as_ref().into());
+
+    open_message(
+        &cipher,
+        &ciphertext_b64,
+        &nonce_b64,
+        associated_data.as_ref(),
+        padded.unwrap_or(false),
+    )
+}
+
 /// Derive a shared secret from a local X25519 private key and a remote X25519 public key
 /// using ECDH, then expand it with HKDF-SHA256 to produce a 32-byte symmetric key.
 ///
-/// Both keys are expected as base64-encoded 32-byte values.
+/// `private_handle` refers to an identity or X25519 key in the key registry.
+/// `public_key_b64` is the remote base64-encoded 32-byte public key.
+/// Returns a handle for the derived key.
 #[tauri::command]
 pub fn derive_shared_secret(
-    private_key_b64: String,
+    private_handle: String,
     public_key_b64: String,
 ) -> Result<String, String> {
-    let private_bytes = BASE64
-        .decode(&private_key_b64)
-        .map_err(|e| format!("Invalid base64 private key: {}", e))?;
+    let secret = handles::x25519(&private_handle)?;
+    let their_public = PublicKey::from(decode_key(&public_key_b64, "public key")?);
 
-    let public_bytes = BASE64
-        .decode(&public_key_b64)
-        .map_err(|e| format!("Invalid base64 public key: {}", e))?;
+    // Perform X25519 ECDH
+    let shared_secret = secret.diffie_hellman(&their_public);
 
-    if private_bytes.len() != 32 {
-        return Err(format!(
-            "Private key must be 32 bytes, got {}",
-            private_bytes.len()
-        ));
+    // Derive a symmetric key using HKDF-SHA256
+    let hk = Hkdf::<Sha256>::new(None, shared_secret.as_bytes());
+    let mut derived_key = Zeroizing::new([0u8; 32]);
+    hk.expand(b"zeusix-e2ee-v1", derived_key.as_mut())
+        .map_err(|e| format!("HKDF expansion failed: {}", e))?;
+
+    handles::insert(handles::SecretKey::Symmetric(derived_key))
+}
+
+const SIGNATURE_MISMATCH: &str = "Signature does not match";
+
+/// Sign arbitrary data with the Ed25519 key of an identity handle.
+///
+/// `payload_b64` is the base64-encoded data. Returns the base64-encoded signature.
+#[tauri::command]
+pub fn sign_payload(identity_handle: String, payload_b64: String) -> Result<String, String> {
+    let signing = handles::ed25519(&identity_handle)?;
+    let payload = BASE64
+        .decode(&payload_b64)
+        .map_err(|e| format!("Invalid base64 payload: {}", e))?;
+
+    Ok(BASE64.encode(signing.sign(&payload).to_bytes()))
+}
+
+/// Verify an Ed25519 signature over base64-encoded data.
+///
+/// Returns `false` for a well-formed signature that does not match, and an
+/// error for malformed keys or signatures.
+#[tauri::command]
+pub fn verify_payload(
+    ed25519_public_b64: String,
+    payload_b64: String,
+    signature_b64: String,
+) -> Result<bool, String> {
+    let payload = BASE64
+        .decode(&payload_b64)
+        .map_err(|e| format!("Invalid base64 payload: {}", e))?;
+
+    match verify_signature(&ed25519_public_b64, &payload, &signature_b64) {
+        Ok(()) => Ok(true),
+        Err(e) if e == SIGNATURE_MISMATCH => Ok(false),
+        Err(e) => Err(e),
     }
-    if public_bytes.len() != 32 {
-        return Err(format!(
-            "Public key must be 32 bytes, got {}",
-            public_bytes.len()
-        ));
+}
+
+/// Check `signature_b64` over `message` against an Ed25519 public key.
+pub(crate) fn verify_signature(
+    ed25519_public_b64: &str,
+    message: &[u8],
+    signature_b64: &str,
+) -> Result<(), String> {
+    let verifying = VerifyingKey::from_bytes(&decode_key(ed25519_public_b64, "Ed25519 public key")?)
+        .map_err(|e| format!("Invalid Ed25519 public key: {}", e))?;
+    let sig_bytes = BASE64
+        .decode(signature_b64)
+        .map_err(|e| format!("Invalid base64 signature: {}", e))?;
+    let signature =
+        Signature::from_slice(&sig_bytes).map_err(|e| format!("Invalid signature: {}", e))?;
+
+    verifying
+        .verify(message, &signature)
+        .map_err(|_| SIGNATURE_MISMATCH.to_string())
+}
+
+/// Canonical bytes signed for an envelope: a domain tag followed by each
+/// field as a 4-byte big-endian length and its contents.
+fn envelope_signing_bytes(
+    ciphertext: &[u8],
+    nonce: &[u8],
+    channel_id: &str,
+    author_id: &str,
+    timestamp: &str,
+) -> Vec<u8> {
+    let mut out = b"zeusix-envelope-v1".to_vec();
+    for field in [
+        ciphertext,
+        nonce,
+        channel_id.as_bytes(),
+        author_id.as_bytes(),
+        timestamp.as_bytes(),
+    ] {
+        out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        out.extend_from_slice(field);
     }
+    out
+}
 
-    let mut private_arr = [0u8; 32];
-    private_arr.copy_from_slice(&private_bytes);
-    let secret = StaticSecret::from(private_arr);
+/// Envelopes carry no separate message id, so the author's timestamp stands in for it.
+fn envelope_context(channel_id: &str, author_id: &str, timestamp: &str) -> AssociatedData {
+    AssociatedData {
+        channel_id: channel_id.to_string(),
+        message_id: timestamp.to_string(),
+        author_id: author_id.to_string(),
+        version: PROTOCOL_VERSION,
+    }
+}
 
-    let mut public_arr = [0u8; 32];
-    public_arr.copy_from_slice(&public_bytes);
-    let their_public = PublicKey::from(public_arr);
+/// Encrypt a message and sign it together with its channel, author and timestamp.
+///
+/// `identity_handle` is the author's identity handle from `generate_keypair`.
+#[tauri::command]
+pub fn seal_envelope(
+    plaintext: String,
+    key_handle: String,
+    channel_id: String,
+    author_id: String,
+    identity_handle: String,
+) -> Result<SignedEnvelope, String> {
+    let signing = handles::ed25519(&identity_handle)?;
+    let timestamp = Utc::now().to_rfc3339();
+    let payload = encrypt_message(
+        plaintext,
+        key_handle,
+        Some(envelope_context(&channel_id, &author_id, &timestamp)),
+    )?;
 
-    // Perform X25519 ECDH
-    let shared_secret = secret.diffie_hellman(&their_public);
+    let ciphertext = BASE64.decode(&payload.ciphertext).map_err(|e| e.to_string())?;
+    let nonce = BASE64.decode(&payload.nonce).map_err(|e| e.to_string())?;
+    let signature = signing.sign(&envelope_signing_bytes(
+        &ciphertext,
+        &nonce,
+        &channel_id,
+        &author_id,
+        &timestamp,
+    ));
 
-    // Derive a symmetric key using HKDF-SHA256
-    let hk = Hkdf::<Sha256>::new(None, shared_secret.as_bytes());
-    let mut derived_key = [0u8; 32];
-    hk.expand(b"zeusix-e2ee-v1", &mut derived_key)
-        .map_err(|e| format!("HKDF expansion failed: {}", e))?;
+    Ok(SignedEnvelope {
+        ciphertext: payload.ciphertext,
+        nonce: payload.nonce,
+        padded: payload.padded,
+        channel_id,
+        author_id,
+        timestamp,
+        signature: BASE64.encode(signature.to_bytes()),
+    })
+}
+
+/// Verify an envelope against the author's known identity key, then decrypt it.
+///
+/// Fails closed: nothing is decrypted unless the signature matches
+/// `author_ed25519_public_b64`.
+#[tauri::command]
+pub fn open_envelope(
+    envelope: SignedEnvelope,
+    key_handle: String,
+    author_ed25519_public_b64: String,
+) -> Result<String, String> {
+    let ciphertext = BASE64
+        .decode(&envelope.ciphertext)
+        .map_err(|e| format!("Invalid base64 ciphertext: {}", e))?;
+    let nonce = BASE64
+        .decode(&envelope.nonce)
+        .map_err(|e| format!("Invalid base64 nonce: {}", e))?;
+
+    let signed = envelope_signing_bytes(
+        &ciphertext,
+        &nonce,
+        &envelope.channel_id,
+        &envelope.author_id,
+        &envelope.timestamp,
+    );
+    verify_signature(&author_ed25519_public_b64, &signed, &envelope.signature)
+        .map_err(|e| format!("Envelope rejected: {}", e))?;
+
+    let context = envelope_context(&envelope.channel_id, &envelope.author_id, &envelope.timestamp);
+    decrypt_message(
+        envelope.ciphertext,
+        envelope.nonce,
+        key_handle,
+        Some(context),
+        Some(envelope.padded),
+    )
+}
+
+/// Decode a base64-encoded 32-byte key, naming it `what` in error messages.
+pub(crate) fn decode_key(b64: &str, what: &str) -> Result<[u8; 32], String> {
+    let bytes = BASE64
+        .decode(b64)
+        .map_err(|e| format!("Invalid base64 {}: {}", what, e))?;
+
+    bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| format!("{} must be 32 bytes, got {}", what, bytes.len()))
+}
+
+/// Advance a symmetric chain key, returning (next chain key, message key).
+pub(crate) fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let step = |byte: u8| {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(chain_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(&[byte]);
+        let out: [u8; 32] = mac.finalize().into_bytes().into();
+        out
+    };
+    (step(0x02), step(0x01))
+}
 
-    Ok(BASE64.encode(derived_key))
+/// Expand a single-use message key into a ChaCha20-Poly1305 cipher and nonce.
+///
+/// Only safe because every message key encrypts exactly one message.
+pub(crate) fn message_cipher(message_key: &[u8; 32], info: &[u8]) -> (ChaCha20Poly1305, [u8; 12]) {
+    let hk = Hkdf::<Sha256>::new(None, message_key);
+    let mut okm = [0u8; 44];
+    hk.expand(info, &mut okm)
+        .expect("44 bytes is a valid HKDF-SHA256 output length");
+
+    let cipher = ChaCha20Poly1305::new_from_slice(&okm[..32]).expect("key is 32 bytes");
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&okm[32..]);
+    (cipher, nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::keystore;
+
+    fn context() -> AssociatedData {
+        AssociatedData {
+            channel_id: "general".into(),
+            message_id: "m1".into(),
+            author_id: "alice".into(),
+            version: PROTOCOL_VERSION,
+        }
+    }
+
+    /// Flip a bit in the first byte of base64-encoded data.
+    fn flip(b64: &str) -> String {
+        let mut bytes = BASE64.decode(b64).unwrap();
+        bytes[0] ^= 0x01;
+        BASE64.encode(bytes)
+    }
+
+    #[test]
+    fn message_is_bound_to_its_context() {
+        // Lock tests wipe the key registry
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let key = handles::insert_symmetric([5u8; 32]).unwrap();
+        let payload = encrypt_message("hello".into(), key.clone(), Some(context())).unwrap();
+        let decrypt = |associated_data: AssociatedData| {
+            decrypt_message(
+                payload.ciphertext.clone(),
+                payload.nonce.clone(),
+                key.clone(),
+                Some(associated_data),
+                Some(payload.padded),
+            )
+        };
+
+        let changes: [fn(&mut AssociatedData); 4] = [
+            |ad| ad.channel_id = "random".into(),
+            |ad| ad.message_id = "m2".into(),
+            |ad| ad.author_id = "mallory".into(),
+            |ad| ad.version += 1,
+        ];
+        for change in changes {
+            let mut associated_data = context();
+            change(&mut associated_data);
+            assert!(decrypt(associated_data).is_err());
+        }
+        assert_eq!(decrypt(context()).unwrap(), "hello");
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        // Lock tests wipe the key registry
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let alice = generate_keypair().unwrap();
+        let mallory = generate_keypair().unwrap();
+        let payload = BASE64.encode(b"payload");
+
+        let signature = sign_payload(alice.handle, payload.clone()).unwrap();
+        assert!(verify_payload(alice.ed25519_public, payload.clone(), signature.clone()).unwrap());
+        assert!(!verify_payload(mallory.ed25519_public, payload, signature).unwrap());
+    }
+
+    #[test]
+    fn reject_tampered_envelope() {
+        // Lock tests wipe the key registry
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let alice = generate_keypair().unwrap();
+        let key = handles::insert_symmetric([6u8; 32]).unwrap();
+        let sealed = seal_envelope(
+            "hello".into(),
+            key.clone(),
+            "general".into(),
+            "alice".into(),
+            alice.handle,
+        )
+        .unwrap();
+        let open = |envelope: SignedEnvelope| {
+            open_envelope(envelope, key.clone(), alice.ed25519_public.clone())
+        };
+        let copy = || SignedEnvelope {
+            ciphertext: sealed.ciphertext.clone(),
+            nonce: sealed.nonce.clone(),
+            padded: sealed.padded,
+            channel_id: sealed.channel_id.clone(),
+            author_id: sealed.author_id.clone(),
+            timestamp: sealed.timestamp.clone(),
+            signature: sealed.signature.clone(),
+        };
+
+        let changes: [fn(&mut SignedEnvelope); 5] = [
+            |envelope| envelope.ciphertext = flip(&envelope.ciphertext),
+            |envelope| envelope.nonce = flip(&envelope.nonce),
+            |envelope| envelope.channel_id = "random".into(),
+            |envelope| envelope.author_id = "mallory".into(),
+            |envelope| envelope.timestamp = "2000-01-01T00:00:00+00:00".into(),
+        ];
+        for change in changes {
+            let mut envelope = copy();
+            change(&mut envelope);
+            assert!(open(envelope).unwrap_err().starts_with("Envelope rejected"));
+        }
+        assert_eq!(open(copy()).unwrap(), "hello");
+    }
 }
@@ -0,0 +1,274 @@
+//! Encrypted backup of the keystore, protected by a recovery phrase.
+//!
+//! A backup holds the keystore entries: keys stored over IPC plus the
+//! Rust-side secrets such as identity keys saved with `persist_key`. It is
+//! encrypted under a key derived from 256 bits
+//! of fresh entropy, which the user writes down as a 24-word BIP-39 phrase.
+//!
+//! Blob layout (base64-encoded):
+//!
+//! ```text
+//! magic "ZXBK" (4) | version (1) | salt (16) | nonce (24) | ciphertext + tag
+//! ```
+//!
+//! The header is bound as associated data, so the Poly1305 tag doubles as
+//! the integrity check for the whole blob. The BIP-39 checksum catches
+//! mistyped phrases before any decryption is attempted.
+//!
+//! Ratchet sessions and sender keys are left out. Their chains only move
+//! forward, and restoring an older copy would derive message keys, and so
+//! nonces, that were already used. After a restore on a new device, those
+//! sessions are set up again.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bip39::Mnemonic;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use chrono::Utc;
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use super::{ratchet, sender_keys};
+use crate::commands::keystore::{self, KeystoreEntries};
+
+const BACKUP_MAGIC: &[u8; 4] = b"ZXBK";
+const BACKUP_VERSION: u8 = 1;
+const BACKUP_INFO: &[u8] = b"zeusix-backup-v1";
+
+const ENTROPY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = 4 + 1 + SALT_LEN + NONCE_LEN;
+
+/// Backup plaintext.
+#[derive(Serialize, Deserialize)]
+struct BackupContents {
+    created_at: String,
+    #[serde(flatten)]
+    entries: KeystoreEntries,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupExport {
+    /// 24-word recovery phrase; shown to the user once and never stored
+    pub recovery_phrase: String,
+    /// Base64-encoded encrypted backup blob
+    pub backup: String,
+    pub created_at: String,
+    pub entry_count: usize,
+}
+
+fn backup_key(entropy: &[u8], salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, String> {
+    let hk = Hkdf::<Sha256>::new(Some(salt), entropy);
+    let mut key = Zeroizing::new([0u8; 32]);
+    hk.expand(BACKUP_INFO, key.as_mut())
+        .map_err(|e| format!("HKDF expansion failed: {}", e))?;
+    Ok(key)
+}
+
+/// Drop ratchet and sender key state, which must never be restored.
+fn without_chain_state(mut entries: KeystoreEntries) -> KeystoreEntries {
+    entries.secrets.retain(|key_id, _| {
+        !key_id.starts_with(ratchet::SESSION_KEY_PREFIX)
+            && !key_id.starts_with(sender_keys::SENDER_KEYS_PREFIX)
+    });
+    entries
+}
+
+/// Export the keystore entries into an encrypted backup.
+///
+/// Returns a freshly generated recovery phrase together with the blob. Keys
+/// that only live in the key registry are not included; save them with
+/// `persist_key` first.
+#[tauri::command]
+pub fn export_backup() -> Result<BackupExport, String> {
+    seal(without_chain_state(keystore::export_entries()?))
+}
+
+/// Encrypt `entries` under a fresh recovery phrase.
+fn seal(entries: KeystoreEntries) -> Result<BackupExport, String> {
+    let entry_count = entries.len();
+    let created_at = Utc::now().to_rfc3339();
+
+    let contents = BackupContents {
+        created_at: created_at.clone(),
+        entries,
+    };
+    let plaintext = Zeroizing::new(
+        serde_json::to_vec(&contents).map_err(|e| format!("Failed to serialize backup: {}", e))?,
+    );
+
+    let mut entropy = Zeroizing::new([0u8; ENTROPY_LEN]);
+    OsRng.fill_bytes(entropy.as_mut());
+    let mnemonic = Mnemonic::from_entropy(entropy.as_ref())
+        .map_err(|e| format!("Failed to generate recovery phrase: {}", e))?;
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(BACKUP_MAGIC);
+    header.push(BACKUP_VERSION);
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    header.extend_from_slice(&salt);
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    header.extend_from_slice(&nonce);
+
+    let key = backup_key(entropy.as_ref(), &salt)?;
+    let ciphertext = XChaCha20Poly1305::new(key.as_ref().into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: &plaintext,
+                aad: &header,
+            },
+        )
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+
+    let mut blob = header;
+    blob.extend_from_slice(&ciphertext);
+
+    Ok(BackupExport {
+        recovery_phrase: mnemonic.to_string(),
+        backup: BASE64.encode(blob),
+        created_at,
+        entry_count,
+    })
+}
+
+/// Decrypt a backup from `export_backup` and restore it into the keystore.
+///
+/// Only entries missing from the keystore are restored: a backup is an
+/// older snapshot, so an entry that is still present is at least as new.
+/// Ratchet and sender key state in backups made by older versions is
+/// ignored. Nothing is written unless the phrase is valid and the blob
+/// decrypts intact. Returns the number of entries restored.
+#[tauri::command]
+pub fn import_backup(backup_b64: String, recovery_phrase: String) -> Result<usize, String> {
+    let mnemonic = Mnemonic::parse(recovery_phrase.trim().to_lowercase())
+        .map_err(|e| format!("Invalid recovery phrase: {}", e))?;
+    let entropy = Zeroizing::new(mnemonic.to_entropy());
+    if entropy.len() != ENTROPY_LEN {
+        return Err("Recovery phrase must be 24 words".to_string());
+    }
+
+    let blob = BASE64
+        .decode(&backup_b64)
+        .map_err(|e| format!("Invalid base64 backup: {}", e))?;
+    if blob.len() < HEADER_LEN || &blob[..4] != BACKUP_MAGIC {
+        return Err("Not a ZeusIX backup".to_string());
+    }
+    if blob[4] != BACKUP_VERSION {
+        return Err(format!("Unsupported backup version {}", blob[4]));
+    }
+
+    let (header, ciphertext) = blob.split_at(HEADER_LEN);
+    let salt = &header[5..5 + SALT_LEN];
+    let nonce = &header[5 + SALT_LEN..];
+
+    let key = backup_key(&entropy, salt)?;
+    let plaintext = Zeroizing::new(
+        XChaCha20Poly1305::new(key.as_ref().into())
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| "Wrong recovery phrase or corrupted backup".to_string())?,
+    );
+
+    let contents: BackupContents = serde_json::from_slice(&plaintext)
+        .map_err(|e| format!("Corrupt backup contents: {}", e))?;
+
+    keystore::import_entries(without_chain_state(contents.entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::handles::{self, SecretKey};
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    /// Start a ratchet session from "alice" to "bob".
+    fn start_session() {
+        let shared = handles::insert_symmetric([9u8; 32]).unwrap();
+        let prekey = StaticSecret::random_from_rng(OsRng);
+        let prekey_public = BASE64.encode(PublicKey::from(&prekey).as_bytes());
+        let prekey = handles::insert(SecretKey::X25519(prekey)).unwrap();
+
+        ratchet::ratchet_init_initiator("alice".into(), shared.clone(), prekey_public, None)
+            .unwrap();
+        ratchet::ratchet_init_responder("bob".into(), shared, prekey, None).unwrap();
+    }
+
+    #[test]
+    fn restore_never_rewinds_a_chain() {
+        let _keystore = keystore::test_keystore();
+        start_session();
+
+        let first = ratchet::ratchet_encrypt("alice".into(), "one".into()).unwrap();
+        let backup = export_backup().unwrap();
+        let second = ratchet::ratchet_encrypt("alice".into(), "two".into()).unwrap();
+        let live = keystore::load_secret("session:alice").unwrap();
+
+        import_backup(backup.backup, backup.recovery_phrase).unwrap();
+        assert_eq!(keystore::load_secret("session:alice").unwrap(), live);
+
+        // Backups made before chain state was left out still carry it
+        let mut stale = keystore::export_entries().unwrap();
+        stale.keys.clear();
+        stale
+            .secrets
+            .insert("session:alice".into(), "c3RhbGU=".into());
+        stale
+            .secrets
+            .insert("session:carol".into(), "c3RhbGU=".into());
+        let stale = seal(stale).unwrap();
+        assert_eq!(
+            import_backup(stale.backup, stale.recovery_phrase).unwrap(),
+            0
+        );
+        assert_eq!(keystore::load_secret("session:alice").unwrap(), live);
+        assert!(keystore::load_secret("session:carol").is_err());
+
+        let third = ratchet::ratchet_encrypt("alice".into(), "three".into()).unwrap();
+        assert_eq!([first.header.n, second.header.n, third.header.n], [0, 1, 2]);
+        for (message, plaintext) in [(first, "one"), (second, "two"), (third, "three")] {
+            assert_eq!(
+                ratchet::ratchet_decrypt("bob".into(), message).unwrap(),
+                plaintext
+            );
+        }
+    }
+
+    #[test]
+    fn restore_keeps_live_entries() {
+        let _keystore = keystore::test_keystore();
+        let (old, new) = (BASE64.encode([1u8; 32]), BASE64.encode([2u8; 32]));
+        keystore::store_key("kept".into(), old.clone(), None, None).unwrap();
+        keystore::store_key("deleted".into(), old.clone(), None, None).unwrap();
+        let backup = export_backup().unwrap();
+        assert_eq!(backup.entry_count, 2);
+
+        keystore::store_key("kept".into(), new.clone(), None, Some(true)).unwrap();
+        keystore::delete_key("deleted".into()).unwrap();
+
+        let words: Vec<&str> = backup.recovery_phrase.split(' ').collect();
+        let mut wrong = words.clone();
+        wrong.swap(0, 1);
+        if wrong != words {
+            assert!(import_backup(backup.backup.clone(), wrong.join(" ")).is_err());
+        }
+
+        let phrase = format!(" {} ", backup.recovery_phrase.to_uppercase());
+        assert_eq!(import_backup(backup.backup, phrase).unwrap(), 1);
+        assert_eq!(keystore::get_key("kept".into()).unwrap(), new);
+        assert_eq!(keystore::get_key("deleted".into()).unwrap(), old);
+    }
+}
@@ -0,0 +1,180 @@
+//! Batched message encryption and decryption.
+//!
+//! Opening a channel decrypts dozens of messages at once. These commands
+//! take the whole list in one IPC call, look up the key and set up the
+//! cipher once, and run on the blocking thread pool so scrolling stays
+//! smooth. A bad item gets an error in its own slot; the rest of the batch is
+//! unaffected.
+
+use chacha20poly1305::{aead::KeyInit, ChaCha20Poly1305};
+use serde::{Deserialize, Serialize};
+
+use super::{handles, open_message, padding, seal_message, AssociatedData, EncryptedPayload};
+
+/// Largest number of items accepted in one call.
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EncryptBatchItem {
+    pub plaintext: String,
+    pub associated_data: Option<AssociatedData>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DecryptBatchItem {
+    /// Base64-encoded ciphertext
+    pub ciphertext: String,
+    /// Base64-encoded nonce (12 bytes)
+    pub nonce: String,
+    #[serde(default)]
+    pub padded: bool,
+    pub associated_data: Option<AssociatedData>,
+}
+
+/// Outcome of one item, serialized as `{"ok": ...}` or `{"error": "..."}`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchResult<T> {
+    Ok(T),
+    Error(String),
+}
+
+impl<T> From<Result<T, String>> for BatchResult<T> {
+    fn from(result: Result<T, String>) -> Self {
+        match result {
+            Ok(value) => BatchResult::Ok(value),
+            Err(e) => BatchResult::Error(e),
+        }
+    }
+}
+
+fn check_batch_size(len: usize) -> Result<(), String> {
+    if len > MAX_BATCH_SIZE {
+        return Err(format!(
+            "Batch of {} items exceeds the limit of {}",
+            len, MAX_BATCH_SIZE
+        ));
+    }
+    Ok(())
+}
+
+/// Encrypt each item like `encrypt_message`, all under `key_handle`.
+///
+/// Results come back in the order of `items`.
+#[tauri::command]
+pub async fn encrypt_batch(
+    key_handle: String,
+    items: Vec<EncryptBatchItem>,
+) -> Result<Vec<BatchResult<EncryptedPayload>>, String> {
+    check_batch_size(items.len())?;
+    let key = handles::symmetric(&key_handle)?;
+    let policy = padding::policy()?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let cipher = ChaCha20Poly1305::new(key.as_ref().into());
+        items
+            .iter()
+            .map(|item| {
+                seal_message(
+                    &cipher,
+                    item.plaintext.as_bytes(),
+                    item.associated_data.as_ref(),
+                    policy,
+                )
+                .into()
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| format!("Encryption task failed: {}", e))
+}
+
+/// Decrypt each item like `decrypt_message`, all under `key_handle`.
+///
+/// Results come back in the order of `items`. A tampered or malformed item
+/// only fails its own slot.
+#[tauri::command]
+pub async fn decrypt_batch(
+    key_handle: String,
+    items: Vec<DecryptBatchItem>,
+) -> Result<Vec<BatchResult<String>>, String> {
+    check_batch_size(items.len())?;
+    let key = handles::symmetric(&key_handle)?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let cipher = ChaCha20Poly1305::new(key.as_ref().into());
+        items
+            .iter()
+            .map(|item| {
+                open_message(
+                    &cipher,
+                    &item.ciphertext,
+                    &item.nonce,
+                    item.associated_data.as_ref(),
+                    item.padded,
+                )
+                .into()
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| format!("Decryption task failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::PROTOCOL_VERSION;
+    use crate::commands::keystore;
+
+    const IDS: [&str; 4] = ["m1", "m2", "m3", "m4"];
+
+    fn context(message_id: &str) -> Option<AssociatedData> {
+        Some(AssociatedData {
+            channel_id: "general".into(),
+            message_id: message_id.into(),
+            author_id: "alice".into(),
+            version: PROTOCOL_VERSION,
+        })
+    }
+
+    #[test]
+    fn bad_items_only_fail_their_own_slot() {
+        // Lock tests wipe the key registry
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let key = handles::insert_symmetric([8u8; 32]).unwrap();
+        let items = IDS
+            .map(|id| EncryptBatchItem {
+                plaintext: format!("text {}", id),
+                associated_data: context(id),
+            })
+            .into();
+        let sealed = tauri::async_runtime::block_on(encrypt_batch(key.clone(), items)).unwrap();
+
+        let mut items: Vec<DecryptBatchItem> = sealed
+            .into_iter()
+            .zip(IDS)
+            .map(|(result, id)| match result {
+                BatchResult::Ok(payload) => DecryptBatchItem {
+                    ciphertext: payload.ciphertext,
+                    nonce: payload.nonce,
+                    padded: payload.padded,
+                    associated_data: context(id),
+                },
+                BatchResult::Error(e) => panic!("{}", e),
+            })
+            .collect();
+        // A corrupt ciphertext and one replayed under another message id
+        items[1].ciphertext = "AAAA".into();
+        items[2].associated_data = context("m1");
+
+        let opened = tauri::async_runtime::block_on(decrypt_batch(key, items)).unwrap();
+        assert_eq!(opened.len(), 4);
+        assert!(matches!(&opened[0], BatchResult::Ok(text) if text == "text m1"));
+        assert!(matches!(opened[1], BatchResult::Error(_)));
+        assert!(matches!(opened[2], BatchResult::Error(_)));
+        assert!(matches!(&opened[3], BatchResult::Ok(text) if text == "text m4"));
+    }
+}
@@ -0,0 +1,657 @@
+//! Versioned channel key epochs.
+//!
+//! Every channel key belongs to an epoch, and the epoch number is the
+//! `key_id` of each envelope encrypted under it. Rotating a channel, for
+//! example after a member is kicked, adds a new epoch and retires the
+//! previous one. Older epochs stay available for decrypting history until
+//! they are pruned.
+//!
+//! Keys live in the `key_cache` table. Each one is wrapped with
+//! XChaCha20-Poly1305 under a cache key held in the keystore, with the
+//! channel id and epoch bound as associated data so rows cannot be swapped.
+//!
+//! Members receive a new epoch key sealed to their X25519 key with HPKE in
+//! auth mode (`seal_channel_key`), again bound to the channel id and epoch.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use chrono::Utc;
+use rand::{rngs::OsRng, RngCore};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use x25519_dalek::PublicKey;
+use zeroize::Zeroizing;
+
+use super::envelope::{Envelope, DEFAULT_SUITE};
+use super::hpke::{self, HpkeCiphertext, HpkeMode};
+use super::{decode_key, handles, padding, AssociatedData};
+use crate::commands::{keystore, storage};
+
+/// Keystore entry holding the key that wraps cached channel keys.
+const CACHE_KEY_ID: &str = "channel-key-cache";
+
+/// Domain separation prefix for the wrapping associated data.
+const WRAP_CONTEXT: &[u8] = b"zeusix-key-cache-v1";
+
+/// HPKE info for epoch keys sealed to a member.
+const SEAL_INFO: &[u8] = b"zeusix-channel-key-v1";
+
+const WRAP_NONCE_LEN: usize = 24;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelKeyEpoch {
+    pub channel_id: String,
+    /// Epoch number, carried as the envelope `key_id`
+    pub key_id: u32,
+    /// Key registry handle for the epoch key
+    pub handle: String,
+    pub created_at: String,
+    /// When a newer epoch replaced this one
+    pub retired_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelKeyEpochInfo {
+    pub key_id: u32,
+    pub created_at: String,
+    pub retired_at: Option<String>,
+}
+
+fn cache_key() -> Result<Zeroizing<[u8; 32]>, String> {
+    let stored = Zeroizing::new(keystore::load_or_create_secret(CACHE_KEY_ID, || {
+        let mut key = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(key.as_mut());
+        BASE64.encode(key.as_ref())
+    })?);
+    decode_key(&stored, "key cache key").map(Zeroizing::new)
+}
+
+/// `context`, then the channel id and epoch, so a key cannot be moved to
+/// another channel or epoch.
+fn epoch_aad(context: &[u8], channel_id: &str, key_id: u32) -> Vec<u8> {
+    let mut aad = context.to_vec();
+    aad.extend_from_slice(&(channel_id.len() as u32).to_be_bytes());
+    aad.extend_from_slice(channel_id.as_bytes());
+    aad.extend_from_slice(&key_id.to_be_bytes());
+    aad
+}
+
+/// nonce (24) || ciphertext + tag
+fn wrap(channel_id: &str, key_id: u32, key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    let mut nonce = [0u8; WRAP_NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = XChaCha20Poly1305::new(cache_key()?.as_ref().into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: key,
+                aad: &epoch_aad(WRAP_CONTEXT, channel_id, key_id),
+            },
+        )
+        .map_err(|e| format!("Failed to wrap channel key: {}", e))?;
+
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn unwrap(channel_id: &str, key_id: u32, wrapped: &[u8]) -> Result<Zeroizing<[u8; 32]>, String> {
+    if wrapped.len() < WRAP_NONCE_LEN {
+        return Err("Cached channel key is truncated".to_string());
+    }
+    let (nonce, ciphertext) = wrapped.split_at(WRAP_NONCE_LEN);
+
+    let key = Zeroizing::new(
+        XChaCha20Poly1305::new(cache_key()?.as_ref().into())
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &epoch_aad(WRAP_CONTEXT, channel_id, key_id),
+                },
+            )
+            .map_err(|_| format!("Failed to unwrap key epoch {} of channel", key_id))?,
+    );
+
+    let mut out = Zeroizing::new([0u8; 32]);
+    if key.len() != out.len() {
+        return Err("Cached channel key has the wrong length".to_string());
+    }
+    out.copy_from_slice(&key);
+    Ok(out)
+}
+
+/// Store `key` as epoch `key_id` and retire every earlier epoch.
+fn insert_epoch(
+    conn: &mut Connection,
+    channel_id: &str,
+    key_id: Option<u32>,
+    key: &[u8; 32],
+) -> Result<(u32, String), String> {
+    let now = Utc::now().to_rfc3339();
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Failed to start transaction: {}", e))?;
+
+    let key_id = match key_id {
+        Some(key_id) => key_id,
+        None => {
+            let latest: Option<u32> = tx
+                .query_row(
+                    "SELECT MAX(key_id) FROM key_cache WHERE channel_id = ?1",
+                    params![channel_id],
+                    |row| row.get(0),
+                )
+                .map_err(|e| format!("Failed to query key epochs: {}", e))?;
+            match latest {
+                Some(latest) => latest
+                    .checked_add(1)
+                    .ok_or_else(|| "Channel key epochs exhausted".to_string())?,
+                None => 1,
+            }
+        }
+    };
+
+    tx.execute(
+        "INSERT INTO key_cache
+             (channel_id, key_id, encrypted_key, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?4)",
+        params![channel_id, key_id, wrap(channel_id, key_id, key)?, now],
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::SqliteFailure(err, _)
+            if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            format!("Key epoch {} already exists for this channel", key_id)
+        }
+        e => format!("Failed to store channel key: {}", e),
+    })?;
+
+    tx.execute(
+        "UPDATE key_cache SET retired_at = ?3, updated_at = ?3
+         WHERE channel_id = ?1 AND key_id < ?2 AND retired_at IS NULL",
+        params![channel_id, key_id, now],
+    )
+    .map_err(|e| format!("Failed to retire old key epochs: {}", e))?;
+
+    tx.commit()
+        .map_err(|e| format!("Failed to commit transaction: {}", e))?;
+    Ok((key_id, now))
+}
+
+/// Unwrap epoch `key_id` of the channel, or the newest one when `None`.
+pub(crate) fn load_epoch(
+    conn: &Connection,
+    channel_id: &str,
+    key_id: Option<u32>,
+) -> Result<(ChannelKeyEpochInfo, Zeroizing<[u8; 32]>), String> {
+    let row = conn
+        .query_row(
+            "SELECT key_id, encrypted_key, created_at, retired_at FROM key_cache
+             WHERE channel_id = ?1 AND (?2 IS NULL OR key_id = ?2)
+             ORDER BY key_id DESC LIMIT 1",
+            params![channel_id, key_id],
+            |row| {
+                Ok((
+                    ChannelKeyEpochInfo {
+                        key_id: row.get(0)?,
+                        created_at: row.get(2)?,
+                        retired_at: row.get(3)?,
+                    },
+                    row.get::<_, Vec<u8>>(1)?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(|e| format!("Failed to query key epochs: {}", e))?;
+
+    let (info, wrapped) = row.ok_or_else(|| match key_id {
+        Some(key_id) => format!("Unknown key epoch {} for channel '{}'", key_id, channel_id),
+        None => format!("No key for channel '{}'", channel_id),
+    })?;
+    let key = unwrap(channel_id, info.key_id, &wrapped)?;
+    Ok((info, key))
+}
+
+fn epoch_handle(
+    channel_id: String,
+    info: ChannelKeyEpochInfo,
+    key: &[u8; 32],
+) -> Result<ChannelKeyEpoch, String> {
+    Ok(ChannelKeyEpoch {
+        channel_id,
+        key_id: info.key_id,
+        handle: handles::insert_symmetric(*key)?,
+        created_at: info.created_at,
+        retired_at: info.retired_at,
+    })
+}
+
+/// Start a new key epoch for `channel_id` with a fresh random key.
+///
+/// The first rotation of a channel creates epoch 1. Seal the new epoch to
+/// each remaining member with `seal_channel_key`; they add it with
+/// `add_channel_key`.
+#[tauri::command]
+pub fn rotate_channel_key(
+    db_path: String,
+    passphrase: String,
+    channel_id: String,
+) -> Result<ChannelKeyEpoch, String> {
+    let mut conn = storage::open_connection(&db_path, &passphrase)?;
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(key.as_mut());
+    let (key_id, created_at) = insert_epoch(&mut conn, &channel_id, None, &key)?;
+
+    epoch_handle(
+        channel_id,
+        ChannelKeyEpochInfo {
+            key_id,
+            created_at,
+            retired_at: None,
+        },
+        &key,
+    )
+}
+
+/// Seal epoch `key_id` of `channel_id` to a member's X25519 public key
+/// (base64), authenticated with our identity key `sender_handle`.
+///
+/// The channel id and epoch are bound as associated data, so the sealed key
+/// cannot be replayed into another channel or epoch.
+#[tauri::command]
+pub fn seal_channel_key(
+    db_path: String,
+    passphrase: String,
+    channel_id: String,
+    key_id: u32,
+    sender_handle: String,
+    recipient_public: String,
+) -> Result<HpkeCiphertext, String> {
+    let recipient = PublicKey::from(decode_key(&recipient_public, "recipient public key")?);
+    let sender = handles::x25519(&sender_handle)?;
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+    let (_, key) = load_epoch(&conn, &channel_id, Some(key_id))?;
+
+    let (enc, ciphertext) = hpke::seal(
+        &recipient,
+        Some(&sender),
+        SEAL_INFO,
+        &epoch_aad(SEAL_INFO, &channel_id, key_id),
+        &key[..],
+    )?;
+    Ok(HpkeCiphertext {
+        mode: HpkeMode::Auth,
+        enc: BASE64.encode(enc),
+        ciphertext: BASE64.encode(ciphertext),
+    })
+}
+
+/// Open a key from `seal_channel_key` and store it as epoch `key_id`.
+///
+/// `recipient_handle` is our identity key and `sender_public` the X25519
+/// public key (base64) of the member who sealed it; keys sealed by anyone
+/// else, or for another channel or epoch, are refused. Adding an epoch we
+/// already hold is a no-op, unless its key differs; that conflict is an
+/// error and the stored key is kept.
+#[tauri::command]
+pub fn add_channel_key(
+    db_path: String,
+    passphrase: String,
+    channel_id: String,
+    key_id: u32,
+    sealed: HpkeCiphertext,
+    recipient_handle: String,
+    sender_public: String,
+) -> Result<(), String> {
+    if sealed.mode != HpkeMode::Auth {
+        return Err("Channel keys must be sealed in auth mode".to_string());
+    }
+    let sender = PublicKey::from(decode_key(&sender_public, "sender public key")?);
+    let enc = decode_key(&sealed.enc, "encapsulated key")?;
+    let ciphertext = BASE64
+        .decode(&sealed.ciphertext)
+        .map_err(|e| format!("Invalid base64 ciphertext: {}", e))?;
+
+    let opened = Zeroizing::new(
+        hpke::open(
+            &handles::x25519(&recipient_handle)?,
+            Some(&sender),
+            &enc,
+            SEAL_INFO,
+            &epoch_aad(SEAL_INFO, &channel_id, key_id),
+            &ciphertext,
+        )
+        .map_err(|_| "Channel key was not sealed for this channel and epoch".to_string())?,
+    );
+    let mut key = Zeroizing::new([0u8; 32]);
+    if opened.len() != key.len() {
+        return Err("Sealed channel key has the wrong length".to_string());
+    }
+    key.copy_from_slice(&opened);
+
+    let mut conn = storage::open_connection(&db_path, &passphrase)?;
+    let existing: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT encrypted_key FROM key_cache WHERE channel_id = ?1 AND key_id = ?2",
+            params![channel_id, key_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("Failed to query key epochs: {}", e))?;
+    if let Some(wrapped) = existing {
+        // Two members rotated to the same epoch concurrently
+        if *unwrap(&channel_id, key_id, &wrapped)? != *key {
+            return Err(format!(
+                "Conflict: key epoch {} of this channel already has a different key",
+                key_id
+            ));
+        }
+        return Ok(());
+    }
+
+    insert_epoch(&mut conn, &channel_id, Some(key_id), &key).map(|_| ())
+}
+
+/// Load the key for epoch `key_id` of `channel_id`, or the current epoch
+/// when `key_id` is absent, and return a handle for it.
+///
+/// `key_id` comes from the envelope header (`inspect_envelope`).
+#[tauri::command]
+pub fn get_channel_key(
+    db_path: String,
+    passphrase: String,
+    channel_id: String,
+    key_id: Option<u32>,
+) -> Result<ChannelKeyEpoch, String> {
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+    let (info, key) = load_epoch(&conn, &channel_id, key_id)?;
+    epoch_handle(channel_id, info, &key)
+}
+
+/// List the stored key epochs of `channel_id`, newest first.
+#[tauri::command]
+pub fn list_channel_key_epochs(
+    db_path: String,
+    passphrase: String,
+    channel_id: String,
+) -> Result<Vec<ChannelKeyEpochInfo>, String> {
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT key_id, created_at, retired_at FROM key_cache
+             WHERE channel_id = ?1 ORDER BY key_id DESC",
+        )
+        .map_err(|e| format!("Failed to prepare query: {}", e))?;
+
+    let epochs = stmt
+        .query_map(params![channel_id], |row| {
+            Ok(ChannelKeyEpochInfo {
+                key_id: row.get(0)?,
+                created_at: row.get(1)?,
+                retired_at: row.get(2)?,
+            })
+        })
+        .map_err(|e| format!("Failed to query key epochs: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read key epoch: {}", e))?;
+
+    Ok(epochs)
+}
+
+/// Delete all but the `keep` newest key epochs of `channel_id`.
+///
+/// Messages under a pruned epoch can no longer be decrypted, which is the
+/// point: a later device compromise does not expose that history. At least
+/// the current epoch is always kept. Returns the number of epochs deleted.
+#[tauri::command]
+pub fn prune_channel_key_epochs(
+    db_path: String,
+    passphrase: String,
+    channel_id: String,
+    keep: u32,
+) -> Result<usize, String> {
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+
+    // Overwrite deleted rows instead of leaving them in free pages.
+    conn.pragma_update(None, "secure_delete", true)
+        .map_err(|e| format!("Failed to enable secure delete: {}", e))?;
+
+    conn.execute(
+        "DELETE FROM key_cache
+         WHERE channel_id = ?1 AND key_id NOT IN (
+             SELECT key_id FROM key_cache WHERE channel_id = ?1
+             ORDER BY key_id DESC LIMIT ?2
+         )",
+        params![channel_id, keep.max(1)],
+    )
+    .map_err(|e| format!("Failed to prune key epochs: {}", e))
+}
+
+/// Encrypt a channel message into an envelope under the current epoch key.
+///
+/// The envelope's `key_id` records the epoch, so the message stays
+/// decryptable after later rotations.
+#[tauri::command]
+pub fn encrypt_channel_message(
+    db_path: String,
+    passphrase: String,
+    channel_id: String,
+    plaintext: String,
+    associated_data: Option<AssociatedData>,
+) -> Result<String, String> {
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+    let (info, key) = load_epoch(&conn, &channel_id, None)?;
+
+    let envelope = Envelope::seal(
+        &key,
+        DEFAULT_SUITE,
+        info.key_id,
+        plaintext.as_bytes(),
+        associated_data.as_ref(),
+        padding::policy()?,
+    )?;
+    Ok(BASE64.encode(envelope.to_bytes()))
+}
+
+/// Decrypt a channel envelope with the epoch key named by its `key_id`.
+#[tauri::command]
+pub fn decrypt_channel_message(
+    db_path: String,
+    passphrase: String,
+    channel_id: String,
+    envelope_b64: String,
+    associated_data: Option<AssociatedData>,
+) -> Result<String, String> {
+    let envelope = Envelope::from_base64(&envelope_b64)?;
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+    let (_, key) = load_epoch(&conn, &channel_id, Some(envelope.header.key_id))?;
+
+    let plaintext = envelope.open(&key, associated_data.as_ref())?;
+    String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8 in decrypted message: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::generate_keypair;
+
+    fn encrypt(db_path: &str, channel_id: &str, plaintext: &str) -> String {
+        encrypt_channel_message(
+            db_path.into(),
+            String::new(),
+            channel_id.into(),
+            plaintext.into(),
+            None,
+        )
+        .unwrap()
+    }
+
+    fn decrypt(db_path: &str, channel_id: &str, envelope: &str) -> Result<String, String> {
+        decrypt_channel_message(
+            db_path.into(),
+            String::new(),
+            channel_id.into(),
+            envelope.into(),
+            None,
+        )
+    }
+
+    #[test]
+    fn rotate_look_up_and_prune() {
+        let _keystore = keystore::test_keystore();
+        let dir = tempfile::tempdir().unwrap();
+        let db = storage::test_db(&dir);
+        let rotate = || rotate_channel_key(db.clone(), String::new(), "general".into()).unwrap();
+
+        let first = rotate();
+        let old = encrypt(&db, "general", "before");
+        let second = rotate();
+        let new = encrypt(&db, "general", "after");
+        assert_eq!((first.key_id, second.key_id), (1, 2));
+
+        // Each message is opened with the epoch named in its header
+        assert_eq!(Envelope::from_base64(&old).unwrap().header.key_id, 1);
+        assert_eq!(Envelope::from_base64(&new).unwrap().header.key_id, 2);
+        assert_eq!(decrypt(&db, "general", &old).unwrap(), "before");
+        assert_eq!(decrypt(&db, "general", &new).unwrap(), "after");
+        assert!(decrypt(&db, "random", &new).is_err());
+
+        let epoch = get_channel_key(db.clone(), String::new(), "general".into(), Some(1)).unwrap();
+        assert_eq!(
+            *handles::symmetric(&epoch.handle).unwrap(),
+            *handles::symmetric(&first.handle).unwrap()
+        );
+        assert!(epoch.retired_at.is_some());
+        assert!(get_channel_key(db.clone(), String::new(), "general".into(), Some(3)).is_err());
+
+        let epochs = list_channel_key_epochs(db.clone(), String::new(), "general".into()).unwrap();
+        let key_ids: Vec<u32> = epochs.iter().map(|epoch| epoch.key_id).collect();
+        assert_eq!(key_ids, [2, 1]);
+        assert!(epochs[0].retired_at.is_none());
+
+        // Pruning always keeps the current epoch
+        rotate();
+        assert_eq!(
+            prune_channel_key_epochs(db.clone(), String::new(), "general".into(), 0).unwrap(),
+            2
+        );
+        assert!(decrypt(&db, "general", &old).is_err());
+        assert!(decrypt(&db, "general", &new).is_err());
+        let current = get_channel_key(db.clone(), String::new(), "general".into(), None).unwrap();
+        assert_eq!(current.key_id, 3);
+    }
+
+    #[test]
+    fn sealed_epoch_key_reaches_member() {
+        let _keystore = keystore::test_keystore();
+        let (alice_dir, bob_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (alice_db, bob_db) = (storage::test_db(&alice_dir), storage::test_db(&bob_dir));
+        let alice = generate_keypair().unwrap();
+        let bob = generate_keypair().unwrap();
+        let mallory = generate_keypair().unwrap();
+
+        let epoch = rotate_channel_key(alice_db.clone(), String::new(), "general".into()).unwrap();
+        let sealed = seal_channel_key(
+            alice_db.clone(),
+            String::new(),
+            "general".into(),
+            epoch.key_id,
+            alice.handle.clone(),
+            bob.x25519_public.clone(),
+        )
+        .unwrap();
+
+        let add = |channel_id: &str, key_id: u32, sender_public: &str| {
+            add_channel_key(
+                bob_db.clone(),
+                String::new(),
+                channel_id.into(),
+                key_id,
+                sealed.clone(),
+                bob.handle.clone(),
+                sender_public.into(),
+            )
+        };
+        // Bound to the channel, the epoch and the sealing member
+        assert!(add("random", epoch.key_id, &alice.x25519_public).is_err());
+        assert!(add("general", epoch.key_id + 1, &alice.x25519_public).is_err());
+        assert!(add("general", epoch.key_id, &mallory.x25519_public).is_err());
+        add("general", epoch.key_id, &alice.x25519_public).unwrap();
+
+        let envelope = encrypt(&alice_db, "general", "hello bob");
+        assert_eq!(decrypt(&bob_db, "general", &envelope).unwrap(), "hello bob");
+    }
+
+    #[test]
+    fn conflicting_epoch_is_rejected() {
+        let _keystore = keystore::test_keystore();
+        let (alice_dir, bob_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (alice_db, bob_db) = (storage::test_db(&alice_dir), storage::test_db(&bob_dir));
+        let alice = generate_keypair().unwrap();
+        let bob = generate_keypair().unwrap();
+
+        // Both rotate to epoch 1 before hearing from each other
+        let epoch = rotate_channel_key(alice_db.clone(), String::new(), "general".into()).unwrap();
+        let own = rotate_channel_key(bob_db.clone(), String::new(), "general".into()).unwrap();
+        assert_eq!((epoch.key_id, own.key_id), (1, 1));
+
+        let sealed = seal_channel_key(
+            alice_db.clone(),
+            String::new(),
+            "general".into(),
+            epoch.key_id,
+            alice.handle.clone(),
+            bob.x25519_public.clone(),
+        )
+        .unwrap();
+        let add = || {
+            add_channel_key(
+                bob_db.clone(),
+                String::new(),
+                "general".into(),
+                epoch.key_id,
+                sealed.clone(),
+                bob.handle.clone(),
+                alice.x25519_public.clone(),
+            )
+        };
+        assert!(add().unwrap_err().starts_with("Conflict"));
+        let stored = get_channel_key(bob_db.clone(), String::new(), "general".into(), Some(1));
+        assert_eq!(
+            *handles::symmetric(&stored.unwrap().handle).unwrap(),
+            *handles::symmetric(&own.handle).unwrap()
+        );
+
+        // The same key again is accepted
+        let (carol_dir, carol) = (tempfile::tempdir().unwrap(), generate_keypair().unwrap());
+        let carol_db = storage::test_db(&carol_dir);
+        let sealed = seal_channel_key(
+            alice_db.clone(),
+            String::new(),
+            "general".into(),
+            epoch.key_id,
+            alice.handle.clone(),
+            carol.x25519_public.clone(),
+        )
+        .unwrap();
+        for _ in 0..2 {
+            add_channel_key(
+                carol_db.clone(),
+                String::new(),
+                "general".into(),
+                epoch.key_id,
+                sealed.clone(),
+                carol.handle.clone(),
+                alice.x25519_public.clone(),
+            )
+            .unwrap();
+        }
+    }
+}
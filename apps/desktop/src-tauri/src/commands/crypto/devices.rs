@@ -0,0 +1,645 @@
+//! Multi-device identity: a per-user master key cross-signs device keys.
+//!
+//! Every install still has its own identity keypair (`generate_keypair`).
+//! The user's master Ed25519 key signs a certificate for each device, and
+//! contacts pin the master key on first use. Any device carrying a valid
+//! certificate under the pinned master key is then trusted, until the
+//! master key signs a revocation for it.
+//!
+//! Linking a new device:
+//! 1. The new device calls `start_device_link` and shows the offer as a QR
+//!    code.
+//! 2. A device holding the master key scans it and calls
+//!    `approve_device_link`. It sends back the certificate, sealed with HPKE
+//!    to the new device's X25519 key, under a one-time secret from the
+//!    offer.
+//! 3. The new device opens the response with `complete_device_link`.
+//!
+//! When the offer travels over the network instead of a QR code, both
+//! devices show a six-digit verification code, and the user must check that
+//! they match.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::Utc;
+use ed25519_dalek::Signer;
+use rand::{rngs::OsRng, RngCore};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+use x25519_dalek::PublicKey;
+use zeroize::Zeroizing;
+
+use super::hpke::{self, HpkeCiphertext, HpkeMode};
+use super::safety::IdentityPublicKeys;
+use super::{decode_key, handles, verify_signature};
+use crate::commands::storage;
+
+const CERTIFICATE_CONTEXT: &[u8] = b"zeusix-device-cert-v1";
+const REVOCATION_CONTEXT: &[u8] = b"zeusix-device-revoke-v1";
+const LINK_INFO: &[u8] = b"zeusix-device-link-v1";
+const LINK_CODE_CONTEXT: &[u8] = b"zeusix-device-link-code-v1";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MasterKey {
+    /// Opaque handle for the master signing key
+    pub handle: String,
+    /// Ed25519 master public key (base64)
+    pub ed25519_public: String,
+}
+
+/// One device's identity public keys.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceKeys {
+    #[serde(flatten)]
+    pub identity: IdentityPublicKeys,
+    pub device_id: String,
+}
+
+/// A device's keys signed by its user's master key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceCertificate {
+    #[serde(flatten)]
+    pub device: DeviceKeys,
+    /// Ed25519 master public key (base64)
+    pub master_public: String,
+    pub issued_at: String,
+    /// Ed25519 signature by the master key (base64)
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceRevocation {
+    pub user_id: String,
+    pub device_id: String,
+    /// Ed25519 master public key (base64)
+    pub master_public: String,
+    pub revoked_at: String,
+    /// Ed25519 signature by the master key (base64)
+    pub signature: String,
+}
+
+/// Shown by a new device as a QR code.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceLinkOffer {
+    pub link_id: String,
+    #[serde(flatten)]
+    pub device: DeviceKeys,
+    /// One-time link secret (base64); the response is sealed under it
+    pub secret: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceLinkResponse {
+    pub link_id: String,
+    /// Sealed `DeviceCertificate`
+    pub sealed: HpkeCiphertext,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceLinkApproval {
+    pub response: DeviceLinkResponse,
+    pub verification_code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceLinkResult {
+    pub certificate: DeviceCertificate,
+    pub verification_code: String,
+}
+
+struct PendingLink {
+    device_handle: String,
+    offer: DeviceLinkOffer,
+}
+
+/// Links started on this device: link_id -> pending link.
+static LINKS: std::sync::LazyLock<Mutex<HashMap<String, PendingLink>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Abandon pending links when the keystore locks; their device key handles
+/// are wiped with the rest of the registry.
+pub(crate) fn wipe() {
+    if let Ok(mut links) = LINKS.lock() {
+        links.clear();
+    }
+}
+
+/// A domain tag followed by each field as a 4-byte big-endian length and its
+/// contents.
+fn signing_bytes(context: &[u8], fields: &[&[u8]]) -> Vec<u8> {
+    let mut out = context.to_vec();
+    for field in fields {
+        out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        out.extend_from_slice(field);
+    }
+    out
+}
+
+fn certificate_bytes(device: &DeviceKeys, issued_at: &str) -> Result<Vec<u8>, String> {
+    Ok(signing_bytes(
+        CERTIFICATE_CONTEXT,
+        &[
+            device.identity.user_id.as_bytes(),
+            device.device_id.as_bytes(),
+            &device.identity.key_bytes()?,
+            issued_at.as_bytes(),
+        ],
+    ))
+}
+
+fn revocation_bytes(user_id: &str, device_id: &str, revoked_at: &str) -> Vec<u8> {
+    signing_bytes(
+        REVOCATION_CONTEXT,
+        &[
+            user_id.as_bytes(),
+            device_id.as_bytes(),
+            revoked_at.as_bytes(),
+        ],
+    )
+}
+
+impl DeviceCertificate {
+    /// Check the signature against the certificate's own master key.
+    fn verify(&self) -> Result<(), String> {
+        verify_signature(
+            &self.master_public,
+            &certificate_bytes(&self.device, &self.issued_at)?,
+            &self.signature,
+        )
+        .map_err(|e| format!("Invalid device certificate: {}", e))
+    }
+}
+
+/// Six decimal digits over the link, the device keys and the master key.
+fn verification_code(offer: &DeviceLinkOffer, master_public: &str) -> Result<String, String> {
+    let digest = Sha256::digest(signing_bytes(
+        LINK_CODE_CONTEXT,
+        &[
+            offer.link_id.as_bytes(),
+            &offer.device.identity.key_bytes()?,
+            &decode_key(master_public, "master public key")?,
+        ],
+    ));
+    let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    Ok(format!("{:06}", value % 1_000_000))
+}
+
+fn link_info(secret: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    let mut info = Zeroizing::new(LINK_INFO.to_vec());
+    info.extend_from_slice(&*Zeroizing::new(decode_key(secret, "link secret")?));
+    Ok(info)
+}
+
+/// Check `master_public` against the master key pinned for `user_id`,
+/// pinning it if none is known yet. A different key is refused.
+fn check_master_key(
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    master_public: &str,
+) -> Result<(), String> {
+    let pinned: Option<String> = conn
+        .query_row(
+            "SELECT ed25519_public FROM master_keys WHERE user_id = ?1",
+            params![user_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("Failed to query master keys: {}", e))?;
+
+    match pinned {
+        Some(pinned) if pinned == master_public => Ok(()),
+        Some(_) => Err(format!("Master key of user '{}' has changed", user_id)),
+        None => conn
+            .execute(
+                "INSERT INTO master_keys (user_id, ed25519_public, first_seen_at)
+                 VALUES (?1, ?2, ?3)",
+                params![user_id, master_public, Utc::now().to_rfc3339()],
+            )
+            .map(|_| ())
+            .map_err(|e| format!("Failed to pin master key: {}", e)),
+    }
+}
+
+/// Generate a new master signing key for this user.
+///
+/// Persist it with `persist_key` on the device that keeps it.
+#[tauri::command]
+pub fn generate_master_key() -> Result<MasterKey, String> {
+    let signing = ed25519_dalek::SigningKey::generate(&mut OsRng);
+    let ed25519_public = BASE64.encode(signing.verifying_key().as_bytes());
+
+    Ok(MasterKey {
+        handle: handles::insert(handles::SecretKey::Signing(Box::new(signing)))?,
+        ed25519_public,
+    })
+}
+
+/// Sign a certificate for `device` with the master key behind `master_handle`.
+#[tauri::command]
+pub fn certify_device(
+    master_handle: String,
+    device: DeviceKeys,
+) -> Result<DeviceCertificate, String> {
+    let signing = handles::ed25519(&master_handle)?;
+    let issued_at = Utc::now().to_rfc3339();
+    let signature = signing.sign(&certificate_bytes(&device, &issued_at)?);
+
+    Ok(DeviceCertificate {
+        device,
+        master_public: BASE64.encode(signing.verifying_key().as_bytes()),
+        issued_at,
+        signature: BASE64.encode(signature.to_bytes()),
+    })
+}
+
+/// Sign a revocation of `device_id` with the master key.
+///
+/// Publish it to contacts, who apply it with `apply_device_revocation`.
+#[tauri::command]
+pub fn revoke_device(
+    master_handle: String,
+    user_id: String,
+    device_id: String,
+) -> Result<DeviceRevocation, String> {
+    let signing = handles::ed25519(&master_handle)?;
+    let revoked_at = Utc::now().to_rfc3339();
+    let signature = signing.sign(&revocation_bytes(&user_id, &device_id, &revoked_at));
+
+    Ok(DeviceRevocation {
+        user_id,
+        device_id,
+        master_public: BASE64.encode(signing.verifying_key().as_bytes()),
+        revoked_at,
+        signature: BASE64.encode(signature.to_bytes()),
+    })
+}
+
+/// Begin linking this device, whose identity is `device_handle`, to the
+/// account of `user_id`. Returns the offer to show as a QR code.
+#[tauri::command]
+pub fn start_device_link(
+    device_handle: String,
+    user_id: String,
+) -> Result<DeviceLinkOffer, String> {
+    let x25519 = handles::x25519(&device_handle)?;
+    let ed25519 = handles::ed25519(&device_handle)?;
+
+    let mut secret = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(secret.as_mut());
+
+    let offer = DeviceLinkOffer {
+        link_id: Uuid::new_v4().to_string(),
+        device: DeviceKeys {
+            identity: IdentityPublicKeys {
+                user_id,
+                x25519_public: BASE64.encode(PublicKey::from(&x25519).as_bytes()),
+                ed25519_public: BASE64.encode(ed25519.verifying_key().as_bytes()),
+            },
+            device_id: Uuid::new_v4().to_string(),
+        },
+        secret: BASE64.encode(secret.as_ref()),
+    };
+
+    LINKS
+        .lock()
+        .map_err(|e| format!("Failed to lock device links: {}", e))?
+        .insert(
+            offer.link_id.clone(),
+            PendingLink {
+                device_handle,
+                offer: offer.clone(),
+            },
+        );
+    Ok(offer)
+}
+
+/// Certify the device in a scanned `offer` and seal the certificate back to it.
+///
+/// If the offer did not come from a QR code, show `verification_code` and
+/// have the user compare it with the one on the new device.
+#[tauri::command]
+pub fn approve_device_link(
+    master_handle: String,
+    offer: DeviceLinkOffer,
+) -> Result<DeviceLinkApproval, String> {
+    offer.device.identity.validate()?;
+    let recipient = PublicKey::from(decode_key(
+        &offer.device.identity.x25519_public,
+        "device X25519 key",
+    )?);
+
+    let certificate = certify_device(master_handle, offer.device.clone())?;
+    let plaintext = serde_json::to_vec(&certificate)
+        .map_err(|e| format!("Failed to encode device certificate: {}", e))?;
+    let (enc, ciphertext) = hpke::seal(
+        &recipient,
+        None,
+        &link_info(&offer.secret)?,
+        offer.link_id.as_bytes(),
+        &plaintext,
+    )?;
+
+    Ok(DeviceLinkApproval {
+        verification_code: verification_code(&offer, &certificate.master_public)?,
+        response: DeviceLinkResponse {
+            link_id: offer.link_id,
+            sealed: HpkeCiphertext {
+                mode: HpkeMode::Base,
+                enc: BASE64.encode(enc),
+                ciphertext: BASE64.encode(ciphertext),
+            },
+        },
+    })
+}
+
+/// Open the approval for a link started with `start_device_link` and return
+/// this device's certificate.
+#[tauri::command]
+pub fn complete_device_link(response: DeviceLinkResponse) -> Result<DeviceLinkResult, String> {
+    let pending = LINKS
+        .lock()
+        .map_err(|e| format!("Failed to lock device links: {}", e))?
+        .remove(&response.link_id)
+        .ok_or_else(|| format!("Unknown device link '{}'", response.link_id))?;
+
+    if response.sealed.mode != HpkeMode::Base {
+        return Err("Device link response must be sealed in base mode".to_string());
+    }
+    let enc = decode_key(&response.sealed.enc, "encapsulated key")?;
+    let ciphertext = BASE64
+        .decode(&response.sealed.ciphertext)
+        .map_err(|e| format!("Invalid base64 ciphertext: {}", e))?;
+
+    let plaintext = hpke::open(
+        &handles::x25519(&pending.device_handle)?,
+        None,
+        &enc,
+        &link_info(&pending.offer.secret)?,
+        response.link_id.as_bytes(),
+        &ciphertext,
+    )
+    .map_err(|_| "Device link response was not sealed for this link".to_string())?;
+    let certificate: DeviceCertificate = serde_json::from_slice(&plaintext)
+        .map_err(|e| format!("Invalid device certificate: {}", e))?;
+
+    let ours = &pending.offer.device;
+    if certificate.device.device_id != ours.device_id
+        || certificate.device.identity.user_id != ours.identity.user_id
+        || !certificate
+            .device
+            .identity
+            .same_keys(&ours.identity.x25519_public, &ours.identity.ed25519_public)
+    {
+        return Err("Device certificate is for a different device".to_string());
+    }
+    certificate.verify()?;
+
+    Ok(DeviceLinkResult {
+        verification_code: verification_code(&pending.offer, &certificate.master_public)?,
+        certificate,
+    })
+}
+
+/// Verify and store a contact's device certificate.
+///
+/// The master key is pinned on first use; certificates under any other
+/// master key, or for a revoked device, are refused.
+#[tauri::command]
+pub fn add_device_certificate(
+    db_path: String,
+    passphrase: String,
+    certificate: DeviceCertificate,
+) -> Result<(), String> {
+    certificate.verify()?;
+    let device = &certificate.device;
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+    check_master_key(&conn, &device.identity.user_id, &certificate.master_public)?;
+
+    let revoked = conn
+        .prepare("SELECT 1 FROM device_revocations WHERE user_id = ?1 AND device_id = ?2")
+        .and_then(|mut stmt| stmt.exists(params![device.identity.user_id, device.device_id]))
+        .map_err(|e| format!("Failed to query device revocations: {}", e))?;
+    if revoked {
+        return Err(format!("Device '{}' has been revoked", device.device_id));
+    }
+
+    conn.execute(
+        "INSERT INTO device_keys
+             (user_id, device_id, x25519_public, ed25519_public, issued_at, signature)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT (user_id, device_id) DO UPDATE SET
+             x25519_public = excluded.x25519_public,
+             ed25519_public = excluded.ed25519_public,
+             issued_at = excluded.issued_at,
+             signature = excluded.signature
+         WHERE excluded.issued_at > device_keys.issued_at",
+        params![
+            device.identity.user_id,
+            device.device_id,
+            device.identity.x25519_public,
+            device.identity.ed25519_public,
+            certificate.issued_at,
+            certificate.signature
+        ],
+    )
+    .map_err(|e| format!("Failed to store device certificate: {}", e))?;
+
+    Ok(())
+}
+
+/// Verify a revocation against the pinned master key and stop trusting the
+/// device.
+#[tauri::command]
+pub fn apply_device_revocation(
+    db_path: String,
+    passphrase: String,
+    revocation: DeviceRevocation,
+) -> Result<(), String> {
+    verify_signature(
+        &revocation.master_public,
+        &revocation_bytes(
+            &revocation.user_id,
+            &revocation.device_id,
+            &revocation.revoked_at,
+        ),
+        &revocation.signature,
+    )
+    .map_err(|e| format!("Invalid device revocation: {}", e))?;
+
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+    check_master_key(&conn, &revocation.user_id, &revocation.master_public)?;
+
+    conn.execute(
+        "INSERT OR IGNORE INTO device_revocations (user_id, device_id, revoked_at, signature)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+            revocation.user_id,
+            revocation.device_id,
+            revocation.revoked_at,
+            revocation.signature
+        ],
+    )
+    .map_err(|e| format!("Failed to store device revocation: {}", e))?;
+
+    Ok(())
+}
+
+/// List the certified, unrevoked devices of `user_id`.
+#[tauri::command]
+pub fn list_trusted_devices(
+    db_path: String,
+    passphrase: String,
+    user_id: String,
+) -> Result<Vec<DeviceCertificate>, String> {
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT d.device_id, d.x25519_public, d.ed25519_public, d.issued_at, d.signature,
+                    m.ed25519_public
+             FROM device_keys d
+             JOIN master_keys m ON m.user_id = d.user_id
+             WHERE d.user_id = ?1 AND NOT EXISTS (
+                 SELECT 1 FROM device_revocations r
+                 WHERE r.user_id = d.user_id AND r.device_id = d.device_id
+             )
+             ORDER BY d.issued_at",
+        )
+        .map_err(|e| format!("Failed to prepare query: {}", e))?;
+
+    let devices = stmt
+        .query_map(params![user_id], |row| {
+            Ok(DeviceCertificate {
+                device: DeviceKeys {
+                    identity: IdentityPublicKeys {
+                        user_id: user_id.clone(),
+                        x25519_public: row.get(1)?,
+                        ed25519_public: row.get(2)?,
+                    },
+                    device_id: row.get(0)?,
+                },
+                issued_at: row.get(3)?,
+                signature: row.get(4)?,
+                master_public: row.get(5)?,
+            })
+        })
+        .map_err(|e| format!("Failed to query devices: {}", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read device: {}", e))?;
+
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::generate_keypair;
+    use crate::commands::keystore;
+
+    fn device(device_id: &str) -> DeviceKeys {
+        let keys = generate_keypair().unwrap();
+        DeviceKeys {
+            identity: IdentityPublicKeys {
+                user_id: "alice".to_string(),
+                x25519_public: keys.x25519_public,
+                ed25519_public: keys.ed25519_public,
+            },
+            device_id: device_id.to_string(),
+        }
+    }
+
+    fn trusted(db_path: &str) -> Vec<String> {
+        list_trusted_devices(db_path.into(), String::new(), "alice".into())
+            .unwrap()
+            .into_iter()
+            .map(|certificate| certificate.device.device_id)
+            .collect()
+    }
+
+    #[test]
+    fn certify_and_revoke() {
+        // Lock tests wipe the key registry
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = storage::test_db(&dir);
+        let master = generate_master_key().unwrap();
+        let add = |certificate: DeviceCertificate| {
+            add_device_certificate(db_path.clone(), String::new(), certificate)
+        };
+
+        let laptop = certify_device(master.handle.clone(), device("laptop")).unwrap();
+        let phone = certify_device(master.handle.clone(), device("phone")).unwrap();
+        add(laptop.clone()).unwrap();
+        add(phone).unwrap();
+        assert_eq!(trusted(&db_path), ["laptop", "phone"]);
+
+        let mut tampered = laptop.clone();
+        tampered.device.device_id = "tablet".to_string();
+        assert!(add(tampered)
+            .unwrap_err()
+            .contains("Invalid device certificate"));
+
+        let revocation =
+            revoke_device(master.handle.clone(), "alice".into(), "laptop".into()).unwrap();
+        let mut forged = revocation.clone();
+        forged.device_id = "phone".to_string();
+        assert!(apply_device_revocation(db_path.clone(), String::new(), forged).is_err());
+        apply_device_revocation(db_path.clone(), String::new(), revocation).unwrap();
+        assert_eq!(trusted(&db_path), ["phone"]);
+
+        // A revoked device cannot come back with a new certificate
+        let reissued = certify_device(master.handle.clone(), laptop.device).unwrap();
+        assert!(add(reissued).unwrap_err().contains("has been revoked"));
+    }
+
+    #[test]
+    fn reject_other_master_key() {
+        // Lock tests wipe the key registry
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = storage::test_db(&dir);
+        let master = generate_master_key().unwrap();
+        let impostor = generate_master_key().unwrap();
+
+        let laptop = certify_device(master.handle.clone(), device("laptop")).unwrap();
+        add_device_certificate(db_path.clone(), String::new(), laptop).unwrap();
+
+        let rogue = certify_device(impostor.handle.clone(), device("rogue")).unwrap();
+        let err = add_device_certificate(db_path.clone(), String::new(), rogue).unwrap_err();
+        assert!(
+            err.contains("Master key of user 'alice' has changed"),
+            "{}",
+            err
+        );
+
+        let revocation = revoke_device(impostor.handle, "alice".into(), "laptop".into()).unwrap();
+        assert!(apply_device_revocation(db_path.clone(), String::new(), revocation).is_err());
+        assert_eq!(trusted(&db_path), ["laptop"]);
+    }
+
+    #[test]
+    fn link_a_new_device() {
+        // Lock tests wipe the key registry
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let master = generate_master_key().unwrap();
+        let new_device = generate_keypair().unwrap();
+
+        let offer = start_device_link(new_device.handle, "alice".into()).unwrap();
+        let approval = approve_device_link(master.handle, offer.clone()).unwrap();
+        let result = complete_device_link(approval.response).unwrap();
+
+        assert_eq!(result.verification_code, approval.verification_code);
+        assert_eq!(result.certificate.device.device_id, offer.device.device_id);
+        assert_eq!(result.certificate.master_public, master.ed25519_public);
+        result.certificate.verify().unwrap();
+    }
+}
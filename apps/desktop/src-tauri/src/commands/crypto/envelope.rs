@@ -0,0 +1,377 @@
+//! Versioned, self-describing ciphertext envelope.
+//!
+//! Binary layout (base64-encoded for transport and storage):
+//!
+//! ```text
+//! version (1) | suite (1) | flags (1) | key id (4, big-endian) | nonce (12 or 24) | ciphertext + tag
+//! ```
+//!
+//! Everything before the ciphertext is bound as associated data, followed by
+//! the caller's [`AssociatedData`] if any. Decryption dispatches on the suite
+//! byte, so ciphertext written under an older suite keeps decrypting after
+//! the default changes.
+//!
+//! Version 1 envelopes have no flags byte and are never padded; they are
+//! still read. Version 2 sets [`FLAG_PADDED`] when the plaintext was padded
+//! under a [`PaddingPolicy`].
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce, XChaCha20Poly1305, XNonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::padding::{self, PaddingPolicy};
+use super::{encode_aad, handles, AssociatedData};
+
+/// Current envelope format version.
+pub const ENVELOPE_VERSION: u8 = 2;
+
+/// First version, without a flags byte.
+const ENVELOPE_VERSION_1: u8 = 1;
+
+/// The plaintext is padded and must be unpadded after decryption.
+pub const FLAG_PADDED: u8 = 0x01;
+
+/// Length of the header in front of the nonce for each version.
+fn fixed_header_len(version: u8) -> usize {
+    if version == ENVELOPE_VERSION_1 {
+        6
+    } else {
+        7
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CipherSuite {
+    /// ChaCha20-Poly1305 with a random 12-byte nonce
+    #[serde(rename = "chacha20poly1305")]
+    ChaCha20Poly1305,
+    /// XChaCha20-Poly1305 with a random 24-byte nonce
+    #[serde(rename = "xchacha20poly1305")]
+    XChaCha20Poly1305,
+}
+
+impl CipherSuite {
+    fn id(self) -> u8 {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => 1,
+            CipherSuite::XChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, String> {
+        match id {
+            1 => Ok(CipherSuite::ChaCha20Poly1305),
+            2 => Ok(CipherSuite::XChaCha20Poly1305),
+            _ => Err(format!("Unknown cipher suite {}", id)),
+        }
+    }
+
+    fn nonce_len(self) -> usize {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => 12,
+            CipherSuite::XChaCha20Poly1305 => 24,
+        }
+    }
+}
+
+/// Suite used for new envelopes.
+pub const DEFAULT_SUITE: CipherSuite = CipherSuite::XChaCha20Poly1305;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnvelopeHeader {
+    pub version: u8,
+    pub suite: CipherSuite,
+    /// Id or epoch of the key the envelope was encrypted under
+    pub key_id: u32,
+    #[serde(default)]
+    pub padded: bool,
+}
+
+/// A parsed envelope.
+pub struct Envelope {
+    pub header: EnvelopeHeader,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl Envelope {
+    /// Header bytes, which double as the envelope's own associated data.
+    fn header_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(fixed_header_len(self.header.version) + self.nonce.len());
+        out.push(self.header.version);
+        out.push(self.header.suite.id());
+        if self.header.version != ENVELOPE_VERSION_1 {
+            out.push(if self.header.padded { FLAG_PADDED } else { 0 });
+        }
+        out.extend_from_slice(&self.header.key_id.to_be_bytes());
+        out.extend_from_slice(&self.nonce);
+        out
+    }
+
+    /// Serialize to the binary envelope format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.header_bytes();
+        out.extend_from_slice(&self.ciphertext);
+        out
+    }
+
+    /// Parse the binary envelope format.
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let version = *bytes.first().ok_or("Envelope is truncated")?;
+        if version != ENVELOPE_VERSION_1 && version != ENVELOPE_VERSION {
+            return Err(format!("Unsupported envelope version {}", version));
+        }
+
+        let header_len = fixed_header_len(version);
+        if bytes.len() < header_len {
+            return Err("Envelope is truncated".to_string());
+        }
+
+        let suite = CipherSuite::from_id(bytes[1])?;
+        let flags = if version == ENVELOPE_VERSION_1 {
+            0
+        } else {
+            bytes[2]
+        };
+        if flags & !FLAG_PADDED != 0 {
+            return Err(format!("Unknown envelope flags {:#04x}", flags));
+        }
+        let key_id = u32::from_be_bytes(
+            bytes[header_len - 4..header_len]
+                .try_into()
+                .map_err(|_| "Envelope is truncated")?,
+        );
+
+        let body = &bytes[header_len..];
+        if body.len() < suite.nonce_len() {
+            return Err("Envelope is truncated".to_string());
+        }
+        let (nonce, ciphertext) = body.split_at(suite.nonce_len());
+
+        Ok(Envelope {
+            header: EnvelopeHeader {
+                version,
+                suite,
+                key_id,
+                padded: flags & FLAG_PADDED != 0,
+            },
+            nonce: nonce.to_vec(),
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+
+    /// Decode a base64 envelope string.
+    pub fn from_base64(envelope_b64: &str) -> Result<Self, String> {
+        let bytes = BASE64
+            .decode(envelope_b64)
+            .map_err(|e| format!("Invalid base64 envelope: {}", e))?;
+        Self::parse(&bytes)
+    }
+
+    /// Encrypt `plaintext` under `key` into a new envelope, padded under `padding`.
+    pub fn seal(
+        key: &[u8; 32],
+        suite: CipherSuite,
+        key_id: u32,
+        plaintext: &[u8],
+        associated_data: Option<&AssociatedData>,
+        padding: PaddingPolicy,
+    ) -> Result<Self, String> {
+        let mut nonce = vec![0u8; suite.nonce_len()];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut envelope = Envelope {
+            header: EnvelopeHeader {
+                version: ENVELOPE_VERSION,
+                suite,
+                key_id,
+                padded: padding.pads(),
+            },
+            nonce,
+            ciphertext: Vec::new(),
+        };
+
+        let padded = padding
+            .pads()
+            .then(|| Zeroizing::new(padding::pad(plaintext, padding)));
+        let aad = envelope.aad(associated_data);
+        let payload = Payload {
+            msg: padded.as_deref().map_or(plaintext, |p| p.as_slice()),
+            aad: &aad,
+        };
+        envelope.ciphertext = match suite {
+            CipherSuite::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into())
+                .encrypt(Nonce::from_slice(&envelope.nonce), payload),
+            CipherSuite::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into())
+                .encrypt(XNonce::from_slice(&envelope.nonce), payload),
+        }
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+
+        Ok(envelope)
+    }
+
+    /// Decrypt the envelope with `key`, dispatching on its suite, and strip
+    /// any padding.
+    pub fn open(
+        &self,
+        key: &[u8; 32],
+        associated_data: Option<&AssociatedData>,
+    ) -> Result<Vec<u8>, String> {
+        let aad = self.aad(associated_data);
+        let payload = Payload {
+            msg: &self.ciphertext,
+            aad: &aad,
+        };
+        let plaintext = match self.header.suite {
+            CipherSuite::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into())
+                .decrypt(Nonce::from_slice(&self.nonce), payload),
+            CipherSuite::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into())
+                .decrypt(XNonce::from_slice(&self.nonce), payload),
+        }
+        .map_err(|e| format!("Decryption failed: {}", e))?;
+
+        if self.header.padded {
+            padding::unpad(plaintext)
+        } else {
+            Ok(plaintext)
+        }
+    }
+
+    fn aad(&self, associated_data: Option<&AssociatedData>) -> Vec<u8> {
+        let mut aad = self.header_bytes();
+        aad.extend_from_slice(&encode_aad(associated_data));
+        aad
+    }
+}
+
+/// Encrypt a message into a versioned envelope.
+///
+/// `key_id` identifies the key (or key epoch) so the receiver can pick the
+/// right one. `suite` defaults to XChaCha20-Poly1305. The plaintext is padded
+/// under the current padding policy.
+/// Returns the base64-encoded envelope.
+#[tauri::command]
+pub fn encrypt_envelope(
+    plaintext: String,
+    key_handle: String,
+    key_id: u32,
+    suite: Option<CipherSuite>,
+    associated_data: Option<AssociatedData>,
+) -> Result<String, String> {
+    let key = handles::symmetric(&key_handle)?;
+    let envelope = Envelope::seal(
+        &key,
+        suite.unwrap_or(DEFAULT_SUITE),
+        key_id,
+        plaintext.as_bytes(),
+        associated_data.as_ref(),
+        padding::policy()?,
+    )?;
+
+    Ok(BASE64.encode(envelope.to_bytes()))
+}
+
+/// Decrypt a base64-encoded envelope produced by `encrypt_envelope`.
+#[tauri::command]
+pub fn decrypt_envelope(
+    envelope_b64: String,
+    key_handle: String,
+    associated_data: Option<AssociatedData>,
+) -> Result<String, String> {
+    let key = handles::symmetric(&key_handle)?;
+    let plaintext = Envelope::from_base64(&envelope_b64)?.open(&key, associated_data.as_ref())?;
+
+    String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8 in decrypted message: {}", e))
+}
+
+/// Read an envelope's header without decrypting it, e.g. to look up the key by id.
+#[tauri::command]
+pub fn inspect_envelope(envelope_b64: String) -> Result<EnvelopeHeader, String> {
+    Envelope::from_base64(&envelope_b64).map(|envelope| envelope.header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [9u8; 32];
+    const SUITES: [CipherSuite; 2] = [
+        CipherSuite::ChaCha20Poly1305,
+        CipherSuite::XChaCha20Poly1305,
+    ];
+
+    fn seal(suite: CipherSuite) -> Vec<u8> {
+        Envelope::seal(&KEY, suite, 7, b"hello", None, PaddingPolicy::Padme)
+            .unwrap()
+            .to_bytes()
+    }
+
+    fn open(bytes: &[u8]) -> Result<Vec<u8>, String> {
+        Envelope::parse(bytes)?.open(&KEY, None)
+    }
+
+    #[test]
+    fn round_trip_both_suites() {
+        for (suite, nonce_len) in SUITES.into_iter().zip([12, 24]) {
+            let bytes = seal(suite);
+            let envelope = Envelope::parse(&bytes).unwrap();
+            assert_eq!(envelope.header.suite, suite);
+            assert_eq!(envelope.header.key_id, 7);
+            assert!(envelope.header.padded);
+            assert_eq!(envelope.nonce.len(), nonce_len);
+            assert_eq!(envelope.open(&KEY, None).unwrap(), b"hello");
+        }
+    }
+
+    #[test]
+    fn decryption_follows_the_suite_byte() {
+        for (suite, other) in SUITES.into_iter().zip([2, 1]) {
+            let mut bytes = seal(suite);
+            assert_eq!(bytes[1], suite.id());
+            bytes[1] = other;
+            assert!(open(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn reject_unknown_version_suite_and_truncation() {
+        let bytes = seal(DEFAULT_SUITE);
+
+        let mut version = bytes.clone();
+        version[0] = 3;
+        assert_eq!(
+            open(&version).unwrap_err(),
+            "Unsupported envelope version 3"
+        );
+        let mut suite = bytes.clone();
+        suite[1] = 9;
+        assert_eq!(open(&suite).unwrap_err(), "Unknown cipher suite 9");
+
+        for len in [0, 1, 6, 7 + 23] {
+            assert_eq!(
+                open(&bytes[..len]).unwrap_err(),
+                "Envelope is truncated",
+                "{}",
+                len
+            );
+        }
+        assert!(open(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn every_header_byte_is_authenticated() {
+        for suite in SUITES {
+            let bytes = seal(suite);
+            for i in 0..7 + suite.nonce_len() {
+                let mut flipped = bytes.clone();
+                flipped[i] ^= 0x01;
+                assert!(open(&flipped).is_err(), "{:?} byte {}", suite, i);
+            }
+        }
+    }
+}
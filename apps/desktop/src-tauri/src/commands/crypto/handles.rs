@@ -0,0 +1,207 @@
+//! In-process registry of private and symmetric keys.
+//!
+//! Secret key material never crosses the IPC boundary. Commands that create
+//! keys return an opaque handle id plus any public keys, and every command
+//! that needs a secret takes a handle instead of raw bytes. Even script
+//! injected into the webview can only use keys through the crypto commands,
+//! never read them out.
+//!
+//! All secret types zeroize their memory when dropped, so releasing a handle
+//! or overwriting an entry wipes the key.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ed25519_dalek::SigningKey;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+use x25519_dalek::StaticSecret;
+use zeroize::Zeroizing;
+
+use crate::commands::keystore;
+
+/// Secret key material held by the registry.
+#[derive(Clone)]
+pub(crate) enum SecretKey {
+    /// Long-term identity: X25519 for key agreement, Ed25519 for signing
+    Identity {
+        x25519: StaticSecret,
+        ed25519: Box<SigningKey>,
+    },
+    /// Standalone X25519 private key, e.g. a prekey
+    X25519(StaticSecret),
+    /// 32-byte symmetric key, e.g. a derived shared secret
+    Symmetric(Zeroizing<[u8; 32]>),
+    /// Encoded ML-KEM-768 decapsulation key, e.g. a post-quantum prekey
+    MlKem768(Zeroizing<Vec<u8>>),
+    /// Standalone Ed25519 signing key, e.g. a user's master key
+    Signing(Box<SigningKey>),
+}
+
+const TAG_IDENTITY: u8 = 1;
+const TAG_X25519: u8 = 2;
+const TAG_SYMMETRIC: u8 = 3;
+const TAG_ML_KEM_768: u8 = 4;
+const TAG_SIGNING: u8 = 5;
+
+impl SecretKey {
+    fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut out = Zeroizing::new(Vec::with_capacity(65));
+        match self {
+            SecretKey::Identity { x25519, ed25519 } => {
+                out.push(TAG_IDENTITY);
+                out.extend_from_slice(x25519.as_bytes());
+                out.extend_from_slice(ed25519.as_bytes());
+            }
+            SecretKey::X25519(secret) => {
+                out.push(TAG_X25519);
+                out.extend_from_slice(secret.as_bytes());
+            }
+            SecretKey::Symmetric(key) => {
+                out.push(TAG_SYMMETRIC);
+                out.extend_from_slice(key.as_ref());
+            }
+            SecretKey::MlKem768(key) => {
+                out.push(TAG_ML_KEM_768);
+                out.extend_from_slice(key);
+            }
+            SecretKey::Signing(key) => {
+                out.push(TAG_SIGNING);
+                out.extend_from_slice(key.as_bytes());
+            }
+        }
+        out
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let key32 = |slice: &[u8]| -> Result<Zeroizing<[u8; 32]>, String> {
+            let mut out = Zeroizing::new([0u8; 32]);
+            if slice.len() != 32 {
+                return Err("Corrupt stored key".to_string());
+            }
+            out.copy_from_slice(slice);
+            Ok(out)
+        };
+
+        match bytes.split_first() {
+            Some((&TAG_IDENTITY, rest)) if rest.len() == 64 => Ok(SecretKey::Identity {
+                x25519: StaticSecret::from(*key32(&rest[..32])?),
+                ed25519: Box::new(SigningKey::from_bytes(&*key32(&rest[32..])?)),
+            }),
+            Some((&TAG_X25519, rest)) => Ok(SecretKey::X25519(StaticSecret::from(*key32(rest)?))),
+            Some((&TAG_SYMMETRIC, rest)) => Ok(SecretKey::Symmetric(key32(rest)?)),
+            Some((&TAG_ML_KEM_768, rest)) => Ok(SecretKey::MlKem768(Zeroizing::new(rest.to_vec()))),
+            Some((&TAG_SIGNING, rest)) => Ok(SecretKey::Signing(Box::new(SigningKey::from_bytes(
+                &*key32(rest)?,
+            )))),
+            _ => Err("Corrupt stored key".to_string()),
+        }
+    }
+}
+
+/// Live keys: handle -> secret.
+static REGISTRY: std::sync::LazyLock<Mutex<HashMap<String, SecretKey>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Drop every live key, wiping it from memory. Called when the keystore
+/// locks; persisted keys must be reloaded with `load_key` after unlocking.
+pub(crate) fn wipe() {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.clear();
+    }
+}
+
+fn stored_key_id(key_id: &str) -> String {
+    format!("handle:{}", key_id)
+}
+
+/// Register a secret and return its new handle.
+pub(crate) fn insert(key: SecretKey) -> Result<String, String> {
+    let handle = Uuid::new_v4().to_string();
+    REGISTRY
+        .lock()
+        .map_err(|e| format!("Failed to lock key registry: {}", e))?
+        .insert(handle.clone(), key);
+    Ok(handle)
+}
+
+/// Register a symmetric key and return its new handle.
+pub(crate) fn insert_symmetric(key: [u8; 32]) -> Result<String, String> {
+    insert(SecretKey::Symmetric(Zeroizing::new(key)))
+}
+
+/// Clone the secret behind `handle`.
+pub(crate) fn get(handle: &str) -> Result<SecretKey, String> {
+    REGISTRY
+        .lock()
+        .map_err(|e| format!("Failed to lock key registry: {}", e))?
+        .get(handle)
+        .cloned()
+        .ok_or_else(|| format!("Unknown key handle '{}'", handle))
+}
+
+/// The symmetric key behind `handle`.
+pub(crate) fn symmetric(handle: &str) -> Result<Zeroizing<[u8; 32]>, String> {
+    match get(handle)? {
+        SecretKey::Symmetric(key) => Ok(key),
+        _ => Err("Key handle is not a symmetric key".to_string()),
+    }
+}
+
+/// The X25519 private key behind `handle`: an identity or a standalone key.
+pub(crate) fn x25519(handle: &str) -> Result<StaticSecret, String> {
+    match get(handle)? {
+        SecretKey::Identity { x25519, .. } | SecretKey::X25519(x25519) => Ok(x25519),
+        _ => Err("Key handle is not an X25519 key".to_string()),
+    }
+}
+
+/// The encoded ML-KEM-768 decapsulation key behind `handle`.
+pub(crate) fn ml_kem(handle: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    match get(handle)? {
+        SecretKey::MlKem768(key) => Ok(key),
+        _ => Err("Key handle is not an ML-KEM key".to_string()),
+    }
+}
+
+/// The Ed25519 signing key behind an identity or signing key `handle`.
+pub(crate) fn ed25519(handle: &str) -> Result<SigningKey, String> {
+    match get(handle)? {
+        SecretKey::Identity { ed25519, .. } | SecretKey::Signing(ed25519) => Ok(*ed25519),
+        _ => Err("Key handle is not a signing key".to_string()),
+    }
+}
+
+/// Drop a key from the registry, wiping it from memory.
+///
+/// Copies persisted with `persist_key` are not affected.
+#[tauri::command]
+pub fn release_key(handle: String) -> Result<(), String> {
+    REGISTRY
+        .lock()
+        .map_err(|e| format!("Failed to lock key registry: {}", e))?
+        .remove(&handle)
+        .map(|_| ())
+        .ok_or_else(|| format!("Unknown key handle '{}'", handle))
+}
+
+/// Save the key behind `handle` in the keystore under `key_id`.
+///
+/// The key is stored in the keystore's internal namespace and cannot be read
+/// back with `get_key`; use `load_key` to get a new handle for it.
+#[tauri::command]
+pub fn persist_key(handle: String, key_id: String) -> Result<(), String> {
+    let bytes = get(&handle)?.to_bytes();
+    keystore::store_secret(&stored_key_id(&key_id), BASE64.encode(bytes.as_slice()))
+}
+
+/// Load a key saved with `persist_key` and return a handle for it.
+#[tauri::command]
+pub fn load_key(key_id: String) -> Result<String, String> {
+    let stored = Zeroizing::new(keystore::load_secret(&stored_key_id(&key_id))?);
+    let bytes = Zeroizing::new(
+        BASE64
+            .decode(stored.as_bytes())
+            .map_err(|e| format!("Corrupt stored key: {}", e))?,
+    );
+    insert(SecretKey::from_bytes(&bytes)?)
+}
@@ -0,0 +1,508 @@
+//! HPKE (RFC 9180) single-shot encryption to an X25519 public key.
+//!
+//! Cipher suite: DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, ChaCha20-Poly1305.
+//! Base mode encrypts anonymously; auth mode additionally proves that the
+//! sender holds a given X25519 private key, without a signature.
+//!
+//! Unlike a raw `derive_shared_secret`, every message uses a fresh ephemeral
+//! key and the derived key is bound to both public keys, the mode and the
+//! caller's `info` string, so keys are never reused across messages or
+//! contexts.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use super::{decode_key, encode_aad, handles, AssociatedData};
+
+const KEM_ID: u16 = 0x0020;
+const KDF_ID: u16 = 0x0001;
+const AEAD_ID: u16 = 0x0003;
+
+const N_SECRET: usize = 32;
+const N_KEY: usize = 32;
+const N_NONCE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HpkeMode {
+    Base,
+    Auth,
+}
+
+impl HpkeMode {
+    fn id(self) -> u8 {
+        match self {
+            HpkeMode::Base => 0x00,
+            HpkeMode::Auth => 0x02,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HpkeCiphertext {
+    pub mode: HpkeMode,
+    /// Encapsulated ephemeral public key (base64)
+    pub enc: String,
+    /// Base64-encoded ciphertext
+    pub ciphertext: String,
+}
+
+fn kem_suite_id() -> Vec<u8> {
+    let mut id = b"KEM".to_vec();
+    id.extend_from_slice(&KEM_ID.to_be_bytes());
+    id
+}
+
+fn hpke_suite_id() -> Vec<u8> {
+    let mut id = b"HPKE".to_vec();
+    id.extend_from_slice(&KEM_ID.to_be_bytes());
+    id.extend_from_slice(&KDF_ID.to_be_bytes());
+    id.extend_from_slice(&AEAD_ID.to_be_bytes());
+    id
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut labeled_ikm = Zeroizing::new(b"HPKE-v1".to_vec());
+    labeled_ikm.extend_from_slice(suite_id);
+    labeled_ikm.extend_from_slice(label);
+    labeled_ikm.extend_from_slice(ikm);
+
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), &labeled_ikm);
+    Zeroizing::new(prk.to_vec())
+}
+
+fn labeled_expand(
+    suite_id: &[u8],
+    prk: &[u8],
+    label: &[u8],
+    info: &[u8],
+    len: usize,
+) -> Result<Zeroizing<Vec<u8>>, String> {
+    let mut labeled_info = (len as u16).to_be_bytes().to_vec();
+    labeled_info.extend_from_slice(b"HPKE-v1");
+    labeled_info.extend_from_slice(suite_id);
+    labeled_info.extend_from_slice(label);
+    labeled_info.extend_from_slice(info);
+
+    let hk = Hkdf::<Sha256>::from_prk(prk).map_err(|e| format!("Invalid HKDF PRK: {}", e))?;
+    let mut out = Zeroizing::new(vec![0u8; len]);
+    hk.expand(&labeled_info, &mut out)
+        .map_err(|e| format!("HKDF expansion failed: {}", e))?;
+    Ok(out)
+}
+
+fn dh(secret: &StaticSecret, public: &PublicKey) -> Result<[u8; 32], String> {
+    let shared = secret.diffie_hellman(public);
+    if !shared.was_contributory() {
+        return Err("Diffie-Hellman produced a non-contributory result".to_string());
+    }
+    Ok(shared.to_bytes())
+}
+
+/// DHKEM ExtractAndExpand over the concatenated DH outputs.
+fn extract_and_expand(dh: &[u8], kem_context: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    let suite_id = kem_suite_id();
+    let eae_prk = labeled_extract(&suite_id, b"", b"eae_prk", dh);
+    labeled_expand(&suite_id, &eae_prk, b"shared_secret", kem_context, N_SECRET)
+}
+
+/// Encap / AuthEncap. Returns (shared secret, enc).
+fn encap(
+    ephemeral: &StaticSecret,
+    recipient: &PublicKey,
+    sender: Option<&StaticSecret>,
+) -> Result<(Zeroizing<Vec<u8>>, [u8; 32]), String> {
+    let enc = PublicKey::from(ephemeral).to_bytes();
+
+    let mut dh_out = Zeroizing::new(dh(ephemeral, recipient)?.to_vec());
+    let mut kem_context = enc.to_vec();
+    kem_context.extend_from_slice(recipient.as_bytes());
+
+    if let Some(sender) = sender {
+        dh_out.extend_from_slice(&dh(sender, recipient)?);
+        kem_context.extend_from_slice(PublicKey::from(sender).as_bytes());
+    }
+
+    Ok((extract_and_expand(&dh_out, &kem_context)?, enc))
+}
+
+/// Decap / AuthDecap.
+fn decap(
+    enc: &[u8; 32],
+    recipient: &StaticSecret,
+    sender: Option<&PublicKey>,
+) -> Result<Zeroizing<Vec<u8>>, String> {
+    let ephemeral = PublicKey::from(*enc);
+
+    let mut dh_out = Zeroizing::new(dh(recipient, &ephemeral)?.to_vec());
+    let mut kem_context = enc.to_vec();
+    kem_context.extend_from_slice(PublicKey::from(recipient).as_bytes());
+
+    if let Some(sender) = sender {
+        dh_out.extend_from_slice(&dh(recipient, sender)?);
+        kem_context.extend_from_slice(sender.as_bytes());
+    }
+
+    extract_and_expand(&dh_out, &kem_context)
+}
+
+/// AEAD key and base nonce from the key schedule.
+struct Context {
+    key: Zeroizing<Vec<u8>>,
+    base_nonce: Zeroizing<Vec<u8>>,
+}
+
+/// KeySchedule without a PSK.
+fn key_schedule(mode: HpkeMode, shared_secret: &[u8], info: &[u8]) -> Result<Context, String> {
+    let suite_id = hpke_suite_id();
+
+    let psk_id_hash = labeled_extract(&suite_id, b"", b"psk_id_hash", b"");
+    let info_hash = labeled_extract(&suite_id, b"", b"info_hash", info);
+    let mut context = vec![mode.id()];
+    context.extend_from_slice(&psk_id_hash);
+    context.extend_from_slice(&info_hash);
+
+    let secret = labeled_extract(&suite_id, shared_secret, b"secret", b"");
+    let key = labeled_expand(&suite_id, &secret, b"key", &context, N_KEY)?;
+    let base_nonce = labeled_expand(&suite_id, &secret, b"base_nonce", &context, N_NONCE)?;
+    Ok(Context { key, base_nonce })
+}
+
+/// Single-shot HPKE seal to `recipient`; auth mode when `sender` is given.
+/// Returns (enc, ciphertext).
+pub(crate) fn seal(
+    recipient: &PublicKey,
+    sender: Option<&StaticSecret>,
+    info: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<([u8; 32], Vec<u8>), String> {
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    seal_with(&ephemeral, recipient, sender, info, aad, plaintext)
+}
+
+/// `seal` with a caller-chosen ephemeral key, so the RFC vectors can be
+/// reproduced.
+fn seal_with(
+    ephemeral: &StaticSecret,
+    recipient: &PublicKey,
+    sender: Option<&StaticSecret>,
+    info: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<([u8; 32], Vec<u8>), String> {
+    let mode = if sender.is_some() {
+        HpkeMode::Auth
+    } else {
+        HpkeMode::Base
+    };
+
+    let (shared_secret, enc) = encap(ephemeral, recipient, sender)?;
+    let context = key_schedule(mode, &shared_secret, info)?;
+
+    // Single-shot: sequence number 0, so the nonce is the base nonce itself.
+    let ciphertext = ChaCha20Poly1305::new_from_slice(&context.key)
+        .map_err(|e| format!("Invalid key: {}", e))?
+        .encrypt(
+            Nonce::from_slice(&context.base_nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+
+    Ok((enc, ciphertext))
+}
+
+/// Single-shot HPKE open; auth mode when `sender` is given.
+pub(crate) fn open(
+    recipient: &StaticSecret,
+    sender: Option<&PublicKey>,
+    enc: &[u8; 32],
+    info: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, String> {
+    let mode = if sender.is_some() {
+        HpkeMode::Auth
+    } else {
+        HpkeMode::Base
+    };
+
+    let shared_secret = decap(enc, recipient, sender)?;
+    let context = key_schedule(mode, &shared_secret, info)?;
+
+    ChaCha20Poly1305::new_from_slice(&context.key)
+        .map_err(|e| format!("Invalid key: {}", e))?
+        .decrypt(
+            Nonce::from_slice(&context.base_nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|e| format!("Decryption failed: {}", e))
+}
+
+/// Encrypt `plaintext` to the holder of `recipient_public` (X25519, base64).
+///
+/// `info` names the use, e.g. "friend-request", and must match on open.
+/// With `sender_handle` (an identity or X25519 key handle) the message is
+/// sent in auth mode and can only be opened against the sender's public key.
+#[tauri::command]
+pub fn hpke_seal(
+    recipient_public: String,
+    plaintext: String,
+    info: String,
+    associated_data: Option<AssociatedData>,
+    sender_handle: Option<String>,
+) -> Result<HpkeCiphertext, String> {
+    let recipient = PublicKey::from(decode_key(&recipient_public, "recipient public key")?);
+    let sender = sender_handle.as_deref().map(handles::x25519).transpose()?;
+
+    let (enc, ciphertext) = seal(
+        &recipient,
+        sender.as_ref(),
+        info.as_bytes(),
+        &encode_aad(associated_data.as_ref()),
+        plaintext.as_bytes(),
+    )?;
+
+    Ok(HpkeCiphertext {
+        mode: if sender.is_some() {
+            HpkeMode::Auth
+        } else {
+            HpkeMode::Base
+        },
+        enc: BASE64.encode(enc),
+        ciphertext: BASE64.encode(ciphertext),
+    })
+}
+
+/// Decrypt an `hpke_seal` ciphertext with the recipient's key handle.
+///
+/// Auth-mode ciphertexts require `sender_public`, the sender's X25519 public
+/// key (base64); opening fails if it was sealed by anyone else.
+#[tauri::command]
+pub fn hpke_open(
+    recipient_handle: String,
+    sealed: HpkeCiphertext,
+    info: String,
+    associated_data: Option<AssociatedData>,
+    sender_public: Option<String>,
+) -> Result<String, String> {
+    let sender = match (sealed.mode, sender_public) {
+        (HpkeMode::Base, None) => None,
+        (HpkeMode::Auth, Some(public)) => {
+            Some(PublicKey::from(decode_key(&public, "sender public key")?))
+        }
+        (HpkeMode::Base, Some(_)) => {
+            return Err("Ciphertext was sealed in base mode and is not authenticated".to_string())
+        }
+        (HpkeMode::Auth, None) => {
+            return Err("Sender public key is required to open an auth-mode ciphertext".to_string())
+        }
+    };
+
+    let recipient = handles::x25519(&recipient_handle)?;
+    let enc = decode_key(&sealed.enc, "encapsulated key")?;
+    let ciphertext = BASE64
+        .decode(&sealed.ciphertext)
+        .map_err(|e| format!("Invalid base64 ciphertext: {}", e))?;
+
+    let plaintext = open(
+        &recipient,
+        sender.as_ref(),
+        &enc,
+        info.as_bytes(),
+        &encode_aad(associated_data.as_ref()),
+        &ciphertext,
+    )?;
+
+    String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8 in decrypted message: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::generate_keypair;
+    use crate::commands::keystore;
+
+    /// One RFC 9180 Appendix A.2 vector, sequence number 0.
+    struct Vector {
+        mode: HpkeMode,
+        sk_em: &'static str,
+        sk_rm: &'static str,
+        sk_sm: Option<&'static str>,
+        enc: &'static str,
+        shared_secret: &'static str,
+        key: &'static str,
+        base_nonce: &'static str,
+        ct: &'static str,
+    }
+
+    // "Ode on a Grecian Urn"
+    const INFO: &str = "4f6465206f6e2061204772656369616e2055726e";
+    // "Beauty is truth, truth beauty"
+    const PT: &str = "4265617574792069732074727574682c20747275746820626561757479";
+    // "Count-0"
+    const AAD: &str = "436f756e742d30";
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn secret(s: &str) -> StaticSecret {
+        StaticSecret::from(<[u8; 32]>::try_from(hex(s)).unwrap())
+    }
+
+    fn check(v: &Vector) {
+        let ephemeral = secret(v.sk_em);
+        let recipient = secret(v.sk_rm);
+        let sender = v.sk_sm.map(secret);
+        let recipient_public = PublicKey::from(&recipient);
+        let sender_public = sender.as_ref().map(PublicKey::from);
+        let info = hex(INFO);
+
+        let (shared_secret, enc) = encap(&ephemeral, &recipient_public, sender.as_ref()).unwrap();
+        assert_eq!(enc.to_vec(), hex(v.enc));
+        assert_eq!(*shared_secret, hex(v.shared_secret));
+        assert_eq!(
+            *decap(&enc, &recipient, sender_public.as_ref()).unwrap(),
+            hex(v.shared_secret)
+        );
+
+        let context = key_schedule(v.mode, &shared_secret, &info).unwrap();
+        assert_eq!(*context.key, hex(v.key));
+        assert_eq!(*context.base_nonce, hex(v.base_nonce));
+
+        let (_, ct) = seal_with(
+            &ephemeral,
+            &recipient_public,
+            sender.as_ref(),
+            &info,
+            &hex(AAD),
+            &hex(PT),
+        )
+        .unwrap();
+        assert_eq!(ct, hex(v.ct));
+        let pt = open(
+            &recipient,
+            sender_public.as_ref(),
+            &enc,
+            &info,
+            &hex(AAD),
+            &ct,
+        )
+        .unwrap();
+        assert_eq!(pt, hex(PT));
+    }
+
+    #[test]
+    fn rfc9180_base_vector() {
+        // A.2.1 DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, ChaCha20Poly1305, Base
+        check(&Vector {
+            mode: HpkeMode::Base,
+            sk_em: "f4ec9b33b792c372c1d2c2063507b684ef925b8c75a42dbcbf57d63ccd381600",
+            sk_rm: "8057991eef8f1f1af18f4a9491d16a1ce333f695d4db8e38da75975c4478e0fb",
+            sk_sm: None,
+            enc: "1afa08d3dec047a643885163f1180476fa7ddb54c6a8029ea33f95796bf2ac4a",
+            shared_secret: "0bbe78490412b4bbea4812666f7916932b828bba79942424abb65244930d69a7",
+            key: "ad2744de8e17f4ebba575b3f5f5a8fa1f69c2a07f6e7500bc60ca6e3e3ec1c91",
+            base_nonce: "5c4d98150661b848853b547f",
+            ct: "1c5250d8034ec2b784ba2cfd69dbdb8af406cfe3ff938e131f0def8c8b60b4db\
+                 21993c62ce81883d2dd1b51a28",
+        });
+    }
+
+    #[test]
+    fn rfc9180_auth_vector() {
+        // A.2.3 DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, ChaCha20Poly1305, Auth
+        check(&Vector {
+            mode: HpkeMode::Auth,
+            sk_em: "ff4442ef24fbc3c1ff86375b0be1e77e88a0de1e79b30896d73411c5ff4c3518",
+            sk_rm: "fdea67cf831f1ca98d8e27b1f6abeb5b7745e9d35348b80fa407ff6958f9137e",
+            sk_sm: Some("dc4a146313cce60a278a5323d321f051c5707e9c45ba21a3479fecdf76fc69dd"),
+            enc: "23fb952571a14a25e3d678140cd0e5eb47a0961bb18afcf85896e5453c312e76",
+            shared_secret: "2d6db4cf719dc7293fcbf3fa64690708e44e2bebc81f84608677958c0d4448a7",
+            key: "ba5e8e15c43dd2752b6bb28c78bb3054b5ce448c00f41006cd2dba0cbee591df",
+            base_nonce: "8e9eb77c2d0ea264bf29e28c",
+            ct: "0772cf9eb2943c74c9a85f9f4d6df3eef2f2618acf9f688cbbccaa3d809ffb78\
+                 46d5a14a38437064fbab295957",
+        });
+    }
+
+    #[test]
+    fn round_trip_and_wrong_context() {
+        // Lock tests wipe the key registry
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let alice = generate_keypair().unwrap();
+        let bob = generate_keypair().unwrap();
+        let mallory = generate_keypair().unwrap();
+        let aad = |message_id: &str| {
+            Some(AssociatedData {
+                channel_id: "c1".to_string(),
+                message_id: message_id.to_string(),
+                author_id: "alice".to_string(),
+                version: crate::commands::crypto::PROTOCOL_VERSION,
+            })
+        };
+        let open_as =
+            |sealed: &HpkeCiphertext, info: &str, message_id: &str, sender: Option<&str>| {
+                hpke_open(
+                    bob.handle.clone(),
+                    sealed.clone(),
+                    info.to_string(),
+                    aad(message_id),
+                    sender.map(str::to_string),
+                )
+            };
+
+        let base = hpke_seal(
+            bob.x25519_public.clone(),
+            "hello".to_string(),
+            "test".to_string(),
+            aad("m1"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(base.mode, HpkeMode::Base);
+        assert_eq!(open_as(&base, "test", "m1", None).unwrap(), "hello");
+        assert!(open_as(&base, "other", "m1", None).is_err());
+        assert!(open_as(&base, "test", "m2", None).is_err());
+        assert!(open_as(&base, "test", "m1", Some(&alice.x25519_public)).is_err());
+
+        let auth = hpke_seal(
+            bob.x25519_public.clone(),
+            "hello".to_string(),
+            "test".to_string(),
+            aad("m1"),
+            Some(alice.handle.clone()),
+        )
+        .unwrap();
+        assert_eq!(auth.mode, HpkeMode::Auth);
+        assert_eq!(
+            open_as(&auth, "test", "m1", Some(&alice.x25519_public)).unwrap(),
+            "hello"
+        );
+        assert!(open_as(&auth, "other", "m1", Some(&alice.x25519_public)).is_err());
+        assert!(open_as(&auth, "test", "m2", Some(&alice.x25519_public)).is_err());
+        assert!(open_as(&auth, "test", "m1", Some(&mallory.x25519_public)).is_err());
+        assert!(open_as(&auth, "test", "m1", None).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+//! Frame keys for end-to-end encrypted voice and screen share.
+//!
+//! Media travels through LiveKit, whose E2EE worker encrypts each frame
+//! SFrame-style under a key taken from a small keyring by key index. The
+//! worker's keys are derived here from the channel's current key epoch
+//! (`channel_keys`), so the frontend only ever holds frame keys, never the
+//! long-term channel key.
+//!
+//! - When a participant joins, the frame key ratchets forward one way. Every
+//!   member applies the same ratchet, and a leaked frame key does not
+//!   expose earlier media.
+//! - When a participant leaves, the channel key must be rotated first. The
+//!   room then re-keys from the new epoch, which the leaver never receives.
+//!
+//! Each rotation moves to the next slot of the keyring. The previous keys
+//! stay exportable so frames already in flight still decrypt.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use super::channel_keys;
+use crate::commands::storage;
+
+/// Number of key slots, matching the LiveKit key provider's default keyring.
+pub const KEYRING_SIZE: usize = 16;
+
+/// Largest ratchet count accepted when catching up with a room.
+const MAX_RATCHET: u32 = 10_000;
+
+const MEDIA_INFO: &[u8] = b"zeusix-media-key-v1";
+const RATCHET_INFO: &[u8] = b"zeusix-media-ratchet-v1";
+
+/// Where a room's key schedule currently stands. Not secret: members share
+/// it with newcomers so they derive the same frame key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaKeyPosition {
+    /// Channel key epoch the frame keys are derived from
+    pub key_id: u32,
+    /// Join ratchets applied since the room keyed from this epoch
+    pub ratchet: u32,
+    /// Keyring slot of the current frame key
+    pub key_index: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MediaKey {
+    #[serde(flatten)]
+    pub position: MediaKeyPosition,
+    /// Base64-encoded frame key material for the E2EE worker
+    pub key: String,
+}
+
+/// A frame key together with where in the key schedule it was derived.
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct Slot {
+    key_id: u32,
+    ratchet: u32,
+    key: [u8; 32],
+}
+
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct RoomKeys {
+    channel_id: String,
+    key_index: u8,
+    keyring: [Option<Slot>; KEYRING_SIZE],
+}
+
+impl RoomKeys {
+    fn current(&self) -> Result<&Slot, String> {
+        self.keyring[self.key_index as usize]
+            .as_ref()
+            .ok_or_else(|| "Media room has no current key".to_string())
+    }
+
+    /// The key in slot `key_index`, with the epoch and ratchet it was
+    /// derived at rather than the room's current ones.
+    fn media_key(&self, key_index: u8) -> Result<MediaKey, String> {
+        let slot = self
+            .keyring
+            .get(key_index as usize)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| format!("No media key at index {}", key_index))?;
+        Ok(MediaKey {
+            position: MediaKeyPosition {
+                key_id: slot.key_id,
+                ratchet: slot.ratchet,
+                key_index,
+            },
+            key: BASE64.encode(slot.key),
+        })
+    }
+
+    /// Make `slot` the current frame key in the next keyring slot.
+    fn advance(&mut self, slot: Slot) {
+        self.key_index = ((self.key_index as usize + 1) % KEYRING_SIZE) as u8;
+        self.keyring[self.key_index as usize] = Some(slot);
+    }
+}
+
+/// Media key state per room: room_id -> keys.
+static ROOMS: std::sync::LazyLock<Mutex<HashMap<String, RoomKeys>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Drop every room's media keys when the keystore locks. Rooms must be
+/// joined again after unlocking.
+pub(crate) fn wipe() {
+    if let Ok(mut rooms) = ROOMS.lock() {
+        rooms.clear();
+    }
+}
+
+fn lock_rooms() -> Result<std::sync::MutexGuard<'static, HashMap<String, RoomKeys>>, String> {
+    ROOMS
+        .lock()
+        .map_err(|e| format!("Failed to lock media rooms: {}", e))
+}
+
+/// First frame key of `room_id` under a channel key epoch.
+fn base_key(channel_key: &[u8; 32], room_id: &str, key_id: u32) -> [u8; 32] {
+    let mut info = MEDIA_INFO.to_vec();
+    info.extend_from_slice(&key_id.to_be_bytes());
+
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(room_id.as_bytes()), channel_key)
+        .expand(&info, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+fn ratchet_key(key: &[u8; 32]) -> [u8; 32] {
+    let mut next = [0u8; 32];
+    Hkdf::<Sha256>::new(None, key)
+        .expand(RATCHET_INFO, &mut next)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    next
+}
+
+/// Start media encryption for `room_id`, keyed from `channel_id`.
+///
+/// The first participant omits `position` and keys from the newest channel
+/// epoch. Later participants pass the position reported by a member already
+/// in the room. Returns the current frame key.
+#[tauri::command]
+pub fn join_media_room(
+    db_path: String,
+    passphrase: String,
+    room_id: String,
+    channel_id: String,
+    position: Option<MediaKeyPosition>,
+) -> Result<MediaKey, String> {
+    if let Some(position) = position {
+        if position.ratchet > MAX_RATCHET {
+            return Err(format!(
+                "Ratchet count {} exceeds the limit of {}",
+                position.ratchet, MAX_RATCHET
+            ));
+        }
+        if position.key_index as usize >= KEYRING_SIZE {
+            return Err(format!("Key index {} is out of range", position.key_index));
+        }
+    }
+
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+    let (info, channel_key) =
+        channel_keys::load_epoch(&conn, &channel_id, position.map(|p| p.key_id))?;
+
+    let mut key = Zeroizing::new(base_key(&channel_key, &room_id, info.key_id));
+    let ratchet = position.map_or(0, |p| p.ratchet);
+    for _ in 0..ratchet {
+        *key = ratchet_key(&key);
+    }
+
+    let key_index = position.map_or(0, |p| p.key_index);
+    let mut keyring: [Option<Slot>; KEYRING_SIZE] = Default::default();
+    keyring[key_index as usize] = Some(Slot {
+        key_id: info.key_id,
+        ratchet,
+        key: *key,
+    });
+    let room = RoomKeys {
+        channel_id,
+        key_index,
+        keyring,
+    };
+    let media_key = room.media_key(key_index)?;
+
+    lock_rooms()?.insert(room_id, room);
+    Ok(media_key)
+}
+
+/// Ratchet the frame key of `room_id` forward after a participant joined.
+///
+/// Every member calls this on the same join event. Returns the new frame key.
+#[tauri::command]
+pub fn media_participant_joined(room_id: String) -> Result<MediaKey, String> {
+    let mut rooms = lock_rooms()?;
+    let room = rooms
+        .get_mut(&room_id)
+        .ok_or_else(|| format!("Not in media room '{}'", room_id))?;
+
+    let current = room.current()?;
+    if current.ratchet >= MAX_RATCHET {
+        return Err("Media key ratchet exhausted; rotate the channel key".to_string());
+    }
+    let next = Slot {
+        key_id: current.key_id,
+        ratchet: current.ratchet + 1,
+        key: ratchet_key(&current.key),
+    };
+    room.advance(next);
+    room.media_key(room.key_index)
+}
+
+/// Re-key `room_id` from the newest channel key epoch after a participant left.
+///
+/// The channel key must already have been rotated (or the new epoch added
+/// with `add_channel_key`); otherwise the leaver could still derive the key.
+/// Returns the new frame key.
+#[tauri::command]
+pub fn media_participant_left(
+    db_path: String,
+    passphrase: String,
+    room_id: String,
+) -> Result<MediaKey, String> {
+    let mut rooms = lock_rooms()?;
+    let room = rooms
+        .get_mut(&room_id)
+        .ok_or_else(|| format!("Not in media room '{}'", room_id))?;
+
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+    let (info, channel_key) = channel_keys::load_epoch(&conn, &room.channel_id, None)?;
+    if info.key_id <= room.current()?.key_id {
+        return Err(format!(
+            "Channel key epoch {} is not newer than the room's; rotate the channel key first",
+            info.key_id
+        ));
+    }
+
+    room.advance(Slot {
+        key_id: info.key_id,
+        ratchet: 0,
+        key: base_key(&channel_key, &room_id, info.key_id),
+    });
+    room.media_key(room.key_index)
+}
+
+/// Export the frame key at `key_index` for the E2EE worker, or the current
+/// one when `key_index` is absent.
+#[tauri::command]
+pub fn export_media_key(room_id: String, key_index: Option<u8>) -> Result<MediaKey, String> {
+    let rooms = lock_rooms()?;
+    let room = rooms
+        .get(&room_id)
+        .ok_or_else(|| format!("Not in media room '{}'", room_id))?;
+    room.media_key(key_index.unwrap_or(room.key_index))
+}
+
+/// Stop media encryption for `room_id` and wipe its frame keys.
+#[tauri::command]
+pub fn leave_media_room(room_id: String) -> Result<(), String> {
+    lock_rooms()?.remove(&room_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::channel_keys::rotate_channel_key;
+    use crate::commands::keystore;
+
+    const ROOM: &str = "voice";
+    const CHANNEL: &str = "general";
+
+    fn rotate(db_path: &str) {
+        rotate_channel_key(db_path.into(), String::new(), CHANNEL.into()).unwrap();
+    }
+
+    fn join(db_path: &str, room_id: &str, position: Option<MediaKeyPosition>) -> MediaKey {
+        join_media_room(
+            db_path.into(),
+            String::new(),
+            room_id.into(),
+            CHANNEL.into(),
+            position,
+        )
+        .unwrap()
+    }
+
+    fn position(key_id: u32, ratchet: u32, key_index: u8) -> MediaKeyPosition {
+        MediaKeyPosition {
+            key_id,
+            ratchet,
+            key_index,
+        }
+    }
+
+    #[test]
+    fn newcomer_derives_the_room_key() {
+        let _keystore = keystore::test_keystore();
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = storage::test_db(&dir);
+        rotate(&db_path);
+
+        let first = join(&db_path, ROOM, None);
+        assert_eq!(first.position, position(1, 0, 0));
+        let joined = media_participant_joined(ROOM.into()).unwrap();
+        assert_eq!(joined.position, position(1, 1, 1));
+        assert_ne!(joined.key, first.key);
+
+        // Members share one process here, so they take turns in the room
+        leave_media_room(ROOM.into()).unwrap();
+        let newcomer = join(&db_path, ROOM, Some(joined.position));
+        assert_eq!(newcomer.position, joined.position);
+        assert_eq!(newcomer.key, joined.key);
+
+        let other_room = join(&db_path, "other", Some(joined.position));
+        assert_ne!(other_room.key, joined.key);
+
+        leave_media_room(ROOM.into()).unwrap();
+        leave_media_room("other".into()).unwrap();
+        assert!(export_media_key(ROOM.into(), None).is_err());
+    }
+
+    #[test]
+    fn old_slots_keep_their_own_position() {
+        let _keystore = keystore::test_keystore();
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = storage::test_db(&dir);
+        rotate(&db_path);
+
+        let first = join(&db_path, ROOM, None);
+        media_participant_joined(ROOM.into()).unwrap();
+        let second = media_participant_joined(ROOM.into()).unwrap();
+
+        let err = media_participant_left(db_path.clone(), String::new(), ROOM.into()).unwrap_err();
+        assert!(err.contains("rotate the channel key first"), "{}", err);
+        rotate(&db_path);
+        let rekeyed = media_participant_left(db_path.clone(), String::new(), ROOM.into()).unwrap();
+        assert_eq!(rekeyed.position, position(2, 0, 3));
+
+        let export = |key_index| export_media_key(ROOM.into(), key_index);
+        let oldest = export(Some(0)).unwrap();
+        assert_eq!(oldest.position, position(1, 0, 0));
+        assert_eq!(oldest.key, first.key);
+        let ratcheted = export(Some(2)).unwrap();
+        assert_eq!(ratcheted.position, position(1, 2, 2));
+        assert_eq!(ratcheted.key, second.key);
+        assert_eq!(export(None).unwrap().position, rekeyed.position);
+        assert!(export(Some(4)).is_err());
+        assert!(export(Some(KEYRING_SIZE as u8)).is_err());
+
+        leave_media_room(ROOM.into()).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+//! Length-hiding padding for message plaintexts.
+//!
+//! Before encryption the plaintext gets a `0x80` marker and zero bytes up to
+//! the length bucket chosen by the policy; decryption strips them again.
+//! Padding happens inside the AEAD, so it is authenticated along with the
+//! message. Every bucket is at least [`MIN_PADDED_LEN`] bytes, so short
+//! replies like "yes" and "no" encrypt to the same size.
+//!
+//! - `padme`: Padmé (Nikitin et al., PETS 2019). Sizes keep at most
+//!   O(log log n) bits of information, with at most ~12% overhead.
+//! - `power_of_two`: next power of two. Fewer buckets, up to 100% overhead.
+//! - `none`: no padding; the ciphertext reveals the exact length.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// Smallest padded plaintext, marker included.
+pub const MIN_PADDED_LEN: usize = 32;
+
+const MARKER: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaddingPolicy {
+    None,
+    PowerOfTwo,
+    #[default]
+    Padme,
+}
+
+impl PaddingPolicy {
+    /// Whether plaintexts are padded at all under this policy.
+    pub fn pads(self) -> bool {
+        self != PaddingPolicy::None
+    }
+
+    /// Padded length of a `len`-byte plaintext, marker included. Unchanged
+    /// under `none`.
+    pub fn padded_len(self, len: usize) -> usize {
+        let bucket = (len + 1).max(MIN_PADDED_LEN);
+        match self {
+            PaddingPolicy::None => len,
+            PaddingPolicy::PowerOfTwo => bucket.next_power_of_two(),
+            PaddingPolicy::Padme => padme(bucket),
+        }
+    }
+}
+
+/// Round `len` up so that only the top ~log2(log2(len)) bits of the
+/// mantissa are significant.
+fn padme(len: usize) -> usize {
+    let exponent = len.ilog2();
+    let exponent_bits = exponent.ilog2() + 1;
+    let mask = (1usize << (exponent - exponent_bits)) - 1;
+    (len + mask) & !mask
+}
+
+/// Policy applied to new messages.
+static POLICY: std::sync::LazyLock<Mutex<PaddingPolicy>> =
+    std::sync::LazyLock::new(|| Mutex::new(PaddingPolicy::default()));
+
+pub(crate) fn policy() -> Result<PaddingPolicy, String> {
+    POLICY
+        .lock()
+        .map(|policy| *policy)
+        .map_err(|e| format!("Failed to lock padding policy: {}", e))
+}
+
+/// Pad `plaintext` to its bucket under `policy`.
+pub(crate) fn pad(plaintext: &[u8], policy: PaddingPolicy) -> Vec<u8> {
+    let mut out = Vec::with_capacity(policy.padded_len(plaintext.len()));
+    out.extend_from_slice(plaintext);
+    out.push(MARKER);
+    out.resize(policy.padded_len(plaintext.len()).max(out.len()), 0);
+    out
+}
+
+/// Strip the padding added by [`pad`].
+pub(crate) fn unpad(mut padded: Vec<u8>) -> Result<Vec<u8>, String> {
+    let end = padded
+        .iter()
+        .rposition(|b| *b != 0)
+        .filter(|i| padded[*i] == MARKER)
+        .ok_or_else(|| "Invalid message padding".to_string())?;
+    padded.truncate(end);
+    Ok(padded)
+}
+
+/// Set the padding policy for messages encrypted from now on.
+///
+/// Decryption does not depend on it: padded messages are flagged as such.
+#[tauri::command]
+pub fn set_padding_policy(policy: PaddingPolicy) -> Result<(), String> {
+    *POLICY
+        .lock()
+        .map_err(|e| format!("Failed to lock padding policy: {}", e))? = policy;
+    Ok(())
+}
+
+/// The padding policy currently applied to new messages.
+#[tauri::command]
+pub fn get_padding_policy() -> Result<PaddingPolicy, String> {
+    policy()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICIES: [PaddingPolicy; 3] = [
+        PaddingPolicy::None,
+        PaddingPolicy::PowerOfTwo,
+        PaddingPolicy::Padme,
+    ];
+
+    #[test]
+    fn round_trips() {
+        for policy in POLICIES {
+            for len in (0..300).chain([4095, 4096, 65_536]) {
+                let plaintext: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+                let padded = pad(&plaintext, policy);
+                assert_eq!(padded.len(), policy.padded_len(len).max(len + 1));
+                assert_eq!(unpad(padded).unwrap(), plaintext, "{:?} {}", policy, len);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_trailing_zeros_and_markers() {
+        for plaintext in [&b"\0\0"[..], b"\x80", b"ab\x80\0", b"\xd0\x80"] {
+            assert_eq!(
+                unpad(pad(plaintext, PaddingPolicy::Padme)).unwrap(),
+                plaintext
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_missing_marker() {
+        assert!(unpad(vec![]).is_err());
+        assert!(unpad(vec![0; 32]).is_err());
+        assert!(unpad(b"abc\0\0".to_vec()).is_err());
+    }
+
+    #[test]
+    fn short_messages_share_a_bucket() {
+        for policy in [PaddingPolicy::PowerOfTwo, PaddingPolicy::Padme] {
+            assert_eq!(pad(b"yes", policy).len(), MIN_PADDED_LEN);
+            assert_eq!(pad(b"no", policy).len(), MIN_PADDED_LEN);
+            assert_eq!(policy.padded_len(0), MIN_PADDED_LEN);
+            assert_eq!(policy.padded_len(MIN_PADDED_LEN - 1), MIN_PADDED_LEN);
+        }
+    }
+
+    #[test]
+    fn power_of_two_boundaries() {
+        let policy = PaddingPolicy::PowerOfTwo;
+        assert_eq!(policy.padded_len(31), 32);
+        assert_eq!(policy.padded_len(32), 64);
+        assert_eq!(policy.padded_len(63), 64);
+        assert_eq!(policy.padded_len(64), 128);
+        assert_eq!(policy.padded_len(1023), 1024);
+        assert_eq!(policy.padded_len(1024), 2048);
+    }
+
+    #[test]
+    fn padme_boundaries() {
+        let policy = PaddingPolicy::Padme;
+        // 33..=36 bytes with the marker share the 36-byte bucket.
+        assert_eq!(policy.padded_len(31), 32);
+        assert_eq!(policy.padded_len(32), 36);
+        assert_eq!(policy.padded_len(35), 36);
+        assert_eq!(policy.padded_len(36), 40);
+        // 2^10 + 1 bytes with the marker: the low 6 bits are rounded away.
+        assert_eq!(policy.padded_len(1023), 1024);
+        assert_eq!(policy.padded_len(1024), 1088);
+        assert_eq!(policy.padded_len(1087), 1088);
+        assert_eq!(policy.padded_len(1088), 1152);
+    }
+
+    #[test]
+    fn buckets_are_monotonic_and_bounded() {
+        for policy in POLICIES {
+            let mut previous = 0;
+            for len in 0..70_000 {
+                let padded = policy.padded_len(len);
+                assert!(padded >= previous);
+                previous = padded;
+                match policy {
+                    PaddingPolicy::None => assert_eq!(padded, len),
+                    PaddingPolicy::PowerOfTwo => assert!(padded <= 2 * (len + 1).max(16)),
+                    PaddingPolicy::Padme => {
+                        assert!(padded > len);
+                        assert!(padded as f64 <= (len + 1).max(MIN_PADDED_LEN) as f64 * 1.12 + 1.0);
+                    }
+                }
+            }
+        }
+    }
+}
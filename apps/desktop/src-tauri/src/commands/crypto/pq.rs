@@ -0,0 +1,56 @@
+//! ML-KEM-768 (FIPS 203) key encapsulation for hybrid session setup.
+//!
+//! Keys and ciphertexts cross module boundaries in their standard byte
+//! encodings. The decapsulation key is only ever held in the key registry.
+
+use ml_kem::kem::{Decapsulate, Encapsulate};
+use ml_kem::{Ciphertext, Encoded, EncodedSizeUser, KemCore, MlKem768};
+use rand::rngs::OsRng;
+use zeroize::Zeroizing;
+
+type DecapsulationKey = <MlKem768 as KemCore>::DecapsulationKey;
+type EncapsulationKey = <MlKem768 as KemCore>::EncapsulationKey;
+
+/// Generate a keypair: (encoded decapsulation key, encoded encapsulation key).
+pub(crate) fn generate() -> (Zeroizing<Vec<u8>>, Vec<u8>) {
+    let (dk, ek) = MlKem768::generate(&mut OsRng);
+    (
+        Zeroizing::new(dk.as_bytes().to_vec()),
+        ek.as_bytes().to_vec(),
+    )
+}
+
+/// Encapsulate to an encoded public key: (ciphertext, shared secret).
+pub(crate) fn encapsulate(public: &[u8]) -> Result<(Vec<u8>, Zeroizing<[u8; 32]>), String> {
+    let encoded = Encoded::<EncapsulationKey>::try_from(public)
+        .map_err(|_| "Invalid ML-KEM-768 public key".to_string())?;
+    let ek = EncapsulationKey::from_bytes(&encoded);
+
+    let (ciphertext, shared) = ek
+        .encapsulate(&mut OsRng)
+        .map_err(|_| "ML-KEM encapsulation failed".to_string())?;
+
+    let mut secret = Zeroizing::new([0u8; 32]);
+    secret.copy_from_slice(&shared);
+    Ok((ciphertext.to_vec(), secret))
+}
+
+/// Decapsulate `ciphertext` with an encoded decapsulation key.
+pub(crate) fn decapsulate(
+    secret_key: &[u8],
+    ciphertext: &[u8],
+) -> Result<Zeroizing<[u8; 32]>, String> {
+    let encoded = Encoded::<DecapsulationKey>::try_from(secret_key)
+        .map_err(|_| "Corrupt ML-KEM-768 key".to_string())?;
+    let dk = DecapsulationKey::from_bytes(&encoded);
+
+    let ciphertext = Ciphertext::<MlKem768>::try_from(ciphertext)
+        .map_err(|_| "Invalid ML-KEM-768 ciphertext".to_string())?;
+    let shared = dk
+        .decapsulate(&ciphertext)
+        .map_err(|_| "ML-KEM decapsulation failed".to_string())?;
+
+    let mut secret = Zeroizing::new([0u8; 32]);
+    secret.copy_from_slice(&shared);
+    Ok(secret)
+}
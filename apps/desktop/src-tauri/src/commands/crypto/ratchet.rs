@@ -0,0 +1,557 @@
+//! Double Ratchet session engine for 1:1 DMs.
+//!
+//! Sessions start from the X3DH shared secret. The initiator uses the
+//! responder's signed prekey as the first remote ratchet key. Every message
+//! gets a fresh key from a symmetric chain, and each reply turn performs a
+//! DH ratchet step, which gives forward secrecy and post-compromise security.
+//!
+//! Session state lives in Rust and is addressed by a session id. The state
+//! is written back to the keystore before every operation returns, so a
+//! restart never rewinds a chain and reuses a message key; without an
+//! unlocked keystore, sessions cannot be used at all.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    Nonce,
+};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::{decode_key, handles, kdf_chain, message_cipher};
+use crate::commands::keystore;
+
+/// Maximum number of message keys skipped within a single receiving chain.
+const MAX_SKIP: u32 = 1000;
+
+/// Maximum number of skipped message keys kept per session.
+const MAX_SKIPPED_KEYS: usize = 2000;
+
+const ROOT_INFO: &[u8] = b"zeusix-ratchet-root-v1";
+const MESSAGE_INFO: &[u8] = b"zeusix-ratchet-message-v1";
+
+/// Serializable Double Ratchet state for one session.
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct RatchetState {
+    dh_self: [u8; 32],
+    dh_remote: Option<[u8; 32]>,
+    root_key: [u8; 32],
+    chain_send: Option<[u8; 32]>,
+    chain_recv: Option<[u8; 32]>,
+    n_send: u32,
+    n_recv: u32,
+    prev_chain_len: u32,
+    skipped: Vec<SkippedKey>,
+    associated_data: Vec<u8>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct SkippedKey {
+    dh: [u8; 32],
+    n: u32,
+    message_key: [u8; 32],
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RatchetHeader {
+    /// Sender's current ratchet public key (base64)
+    pub dh: String,
+    /// Number of messages in the sender's previous sending chain
+    pub pn: u32,
+    /// Message number in the current sending chain
+    pub n: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RatchetMessage {
+    pub header: RatchetHeader,
+    /// Base64-encoded ciphertext
+    pub ciphertext: String,
+}
+
+/// Active sessions: session_id -> ratchet state.
+static SESSIONS: std::sync::LazyLock<Mutex<HashMap<String, RatchetState>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Drop the cached sessions when the keystore locks. They are reloaded from
+/// the keystore on next use.
+pub(crate) fn wipe() {
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        sessions.clear();
+    }
+}
+
+/// Keystore id prefix of persisted sessions.
+pub(crate) const SESSION_KEY_PREFIX: &str = "session:";
+
+fn session_key_id(session_id: &str) -> String {
+    format!("{}{}", SESSION_KEY_PREFIX, session_id)
+}
+
+/// KDF_RK: derive a new root key and chain key from a DH output.
+fn kdf_root(root_key: &[u8; 32], dh_out: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let hk = Hkdf::<Sha256>::new(Some(root_key), dh_out);
+    let mut okm = [0u8; 64];
+    hk.expand(ROOT_INFO, &mut okm)
+        .expect("64 bytes is a valid HKDF-SHA256 output length");
+
+    let mut rk = [0u8; 32];
+    let mut ck = [0u8; 32];
+    rk.copy_from_slice(&okm[..32]);
+    ck.copy_from_slice(&okm[32..]);
+    (rk, ck)
+}
+
+fn dh(secret: &[u8; 32], public: &[u8; 32]) -> [u8; 32] {
+    StaticSecret::from(*secret)
+        .diffie_hellman(&PublicKey::from(*public))
+        .to_bytes()
+}
+
+fn public_of(secret: &[u8; 32]) -> [u8; 32] {
+    PublicKey::from(&StaticSecret::from(*secret)).to_bytes()
+}
+
+/// Session associated data followed by the encoded header.
+fn header_aad(associated_data: &[u8], dh_pub: &[u8; 32], pn: u32, n: u32) -> Vec<u8> {
+    let mut aad = associated_data.to_vec();
+    aad.extend_from_slice(dh_pub);
+    aad.extend_from_slice(&pn.to_be_bytes());
+    aad.extend_from_slice(&n.to_be_bytes());
+    aad
+}
+
+impl RatchetState {
+    /// State for the session initiator, who knows the responder's ratchet key.
+    fn new_initiator(shared_secret: [u8; 32], remote: [u8; 32], associated_data: Vec<u8>) -> Self {
+        let dh_self = StaticSecret::random_from_rng(OsRng).to_bytes();
+        let (root_key, chain_send) = kdf_root(&shared_secret, &dh(&dh_self, &remote));
+
+        RatchetState {
+            dh_self,
+            dh_remote: Some(remote),
+            root_key,
+            chain_send: Some(chain_send),
+            chain_recv: None,
+            n_send: 0,
+            n_recv: 0,
+            prev_chain_len: 0,
+            skipped: Vec::new(),
+            associated_data,
+        }
+    }
+
+    /// State for the responder, whose signed prekey is the first ratchet key.
+    fn new_responder(shared_secret: [u8; 32], dh_self: [u8; 32], associated_data: Vec<u8>) -> Self {
+        RatchetState {
+            dh_self,
+            dh_remote: None,
+            root_key: shared_secret,
+            chain_send: None,
+            chain_recv: None,
+            n_send: 0,
+            n_recv: 0,
+            prev_chain_len: 0,
+            skipped: Vec::new(),
+            associated_data,
+        }
+    }
+
+    fn encrypt(&mut self, plaintext: &[u8]) -> Result<RatchetMessage, String> {
+        let chain = self
+            .chain_send
+            .ok_or_else(|| "Session cannot send until it receives a message".to_string())?;
+        let (next_chain, message_key) = kdf_chain(&chain);
+        self.chain_send = Some(next_chain);
+
+        let dh_pub = public_of(&self.dh_self);
+        let n = self.n_send;
+        self.n_send += 1;
+
+        let (cipher, nonce) = message_cipher(&message_key, MESSAGE_INFO);
+        let aad = header_aad(&self.associated_data, &dh_pub, self.prev_chain_len, n);
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|e| format!("Encryption failed: {}", e))?;
+
+        Ok(RatchetMessage {
+            header: RatchetHeader {
+                dh: BASE64.encode(dh_pub),
+                pn: self.prev_chain_len,
+                n,
+            },
+            ciphertext: BASE64.encode(ciphertext),
+        })
+    }
+
+    fn decrypt(&mut self, message: &RatchetMessage) -> Result<Vec<u8>, String> {
+        let dh_remote = decode_key(&message.header.dh, "ratchet key")?;
+        let ciphertext = BASE64
+            .decode(&message.ciphertext)
+            .map_err(|e| format!("Invalid base64 ciphertext: {}", e))?;
+        let header = &message.header;
+
+        let skipped_idx = self
+            .skipped
+            .iter()
+            .position(|k| k.dh == dh_remote && k.n == header.n);
+        let message_key = if let Some(idx) = skipped_idx {
+            self.skipped.remove(idx).message_key
+        } else {
+            if self.dh_remote != Some(dh_remote) {
+                self.skip_until(header.pn)?;
+                self.dh_ratchet(dh_remote);
+            }
+            self.skip_until(header.n)?;
+
+            let chain = self
+                .chain_recv
+                .ok_or_else(|| "Session has no receiving chain".to_string())?;
+            let (next_chain, message_key) = kdf_chain(&chain);
+            self.chain_recv = Some(next_chain);
+            self.n_recv += 1;
+            message_key
+        };
+
+        let (cipher, nonce) = message_cipher(&message_key, MESSAGE_INFO);
+        let aad = header_aad(&self.associated_data, &dh_remote, header.pn, header.n);
+        cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|e| format!("Decryption failed: {}", e))
+    }
+
+    /// Store message keys of the current receiving chain up to (excluding) `until`.
+    fn skip_until(&mut self, until: u32) -> Result<(), String> {
+        let (Some(mut chain), Some(dh_remote)) = (self.chain_recv, self.dh_remote) else {
+            return Ok(());
+        };
+
+        if until.saturating_sub(self.n_recv) > MAX_SKIP {
+            return Err(format!(
+                "Too many skipped messages ({} > {})",
+                until - self.n_recv,
+                MAX_SKIP
+            ));
+        }
+
+        while self.n_recv < until {
+            let (next_chain, message_key) = kdf_chain(&chain);
+            self.skipped.push(SkippedKey {
+                dh: dh_remote,
+                n: self.n_recv,
+                message_key,
+            });
+            chain = next_chain;
+            self.n_recv += 1;
+        }
+        self.chain_recv = Some(chain);
+
+        // Drop the oldest keys once the store is full
+        if self.skipped.len() > MAX_SKIPPED_KEYS {
+            let excess = self.skipped.len() - MAX_SKIPPED_KEYS;
+            self.skipped.drain(..excess);
+        }
+
+        Ok(())
+    }
+
+    fn dh_ratchet(&mut self, dh_remote: [u8; 32]) {
+        self.prev_chain_len = self.n_send;
+        self.n_send = 0;
+        self.n_recv = 0;
+        self.dh_remote = Some(dh_remote);
+
+        let (root_key, chain_recv) = kdf_root(&self.root_key, &dh(&self.dh_self, &dh_remote));
+        self.root_key = root_key;
+        self.chain_recv = Some(chain_recv);
+
+        self.dh_self = StaticSecret::random_from_rng(OsRng).to_bytes();
+        let (root_key, chain_send) = kdf_root(&self.root_key, &dh(&self.dh_self, &dh_remote));
+        self.root_key = root_key;
+        self.chain_send = Some(chain_send);
+    }
+}
+
+/// Write a session back to the keystore. Fails if the keystore is not
+/// initialized or is locked.
+fn persist(session_id: &str, state: &RatchetState) -> Result<(), String> {
+    let json =
+        serde_json::to_vec(state).map_err(|e| format!("Failed to serialize session: {}", e))?;
+    keystore::store_secret(&session_key_id(session_id), BASE64.encode(json))?;
+    Ok(())
+}
+
+/// Fetch a session from memory, falling back to the keystore.
+fn load(session_id: &str) -> Result<RatchetState, String> {
+    let sessions = SESSIONS
+        .lock()
+        .map_err(|e| format!("Failed to lock sessions: {}", e))?;
+    if let Some(state) = sessions.get(session_id) {
+        return Ok(state.clone());
+    }
+    drop(sessions);
+
+    let stored = keystore::load_secret(&session_key_id(session_id))
+        .map_err(|_| format!("Session '{}' not found", session_id))?;
+    let json = BASE64
+        .decode(&stored)
+        .map_err(|e| format!("Corrupt session state: {}", e))?;
+    serde_json::from_slice(&json).map_err(|e| format!("Corrupt session state: {}", e))
+}
+
+/// Persist `state` first, then make it the live session state.
+fn commit(session_id: &str, state: RatchetState) -> Result<(), String> {
+    persist(session_id, &state)?;
+    SESSIONS
+        .lock()
+        .map_err(|e| format!("Failed to lock sessions: {}", e))?
+        .insert(session_id.to_string(), state);
+    Ok(())
+}
+
+fn decode_associated_data(associated_data: Option<String>) -> Result<Vec<u8>, String> {
+    associated_data
+        .map(|ad| {
+            BASE64
+                .decode(&ad)
+                .map_err(|e| format!("Invalid base64 associated data: {}", e))
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// Start a session as the initiator, after `x3dh_initiate`.
+///
+/// `shared_secret_handle` is the X3DH shared secret handle.
+/// `remote_ratchet_key` is the responder's signed prekey from the bundle.
+/// `associated_data` is the X3DH associated data and is bound to every message.
+#[tauri::command]
+pub fn ratchet_init_initiator(
+    session_id: String,
+    shared_secret_handle: String,
+    remote_ratchet_key: String,
+    associated_data: Option<String>,
+) -> Result<(), String> {
+    let sk = *handles::symmetric(&shared_secret_handle)?;
+    let remote = decode_key(&remote_ratchet_key, "ratchet key")?;
+    let ad = decode_associated_data(associated_data)?;
+
+    commit(&session_id, RatchetState::new_initiator(sk, remote, ad))
+}
+
+/// Start a session as the responder, after `x3dh_respond`.
+///
+/// `signed_prekey_handle` is the handle of the signed prekey the initiator used.
+#[tauri::command]
+pub fn ratchet_init_responder(
+    session_id: String,
+    shared_secret_handle: String,
+    signed_prekey_handle: String,
+    associated_data: Option<String>,
+) -> Result<(), String> {
+    let sk = *handles::symmetric(&shared_secret_handle)?;
+    let dh_self = handles::x25519(&signed_prekey_handle)?.to_bytes();
+    let ad = decode_associated_data(associated_data)?;
+
+    commit(&session_id, RatchetState::new_responder(sk, dh_self, ad))
+}
+
+/// Encrypt a message for the session's peer with the next sending-chain key.
+#[tauri::command]
+pub fn ratchet_encrypt(session_id: String, plaintext: String) -> Result<RatchetMessage, String> {
+    let mut state = load(&session_id)?;
+    let message = state.encrypt(plaintext.as_bytes())?;
+    commit(&session_id, state)?;
+    Ok(message)
+}
+
+/// Decrypt a message from the session's peer.
+///
+/// The session only advances if decryption succeeds, so a forged or corrupt
+/// message leaves it untouched.
+#[tauri::command]
+pub fn ratchet_decrypt(session_id: String, message: RatchetMessage) -> Result<String, String> {
+    let mut state = load(&session_id)?;
+    let plaintext = state.decrypt(&message)?;
+    commit(&session_id, state)?;
+
+    String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8 in decrypted message: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::handles::SecretKey;
+    use uuid::Uuid;
+
+    /// Set up a session pair and return (initiator id, responder id).
+    fn session_pair() -> (String, String) {
+        let (alice, bob) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+        let prekey = StaticSecret::random_from_rng(OsRng);
+        let prekey_public = BASE64.encode(PublicKey::from(&prekey).as_bytes());
+        let shared = || handles::insert_symmetric([7u8; 32]).unwrap();
+
+        ratchet_init_initiator(alice.clone(), shared(), prekey_public, None).unwrap();
+        ratchet_init_responder(
+            bob.clone(),
+            shared(),
+            handles::insert(SecretKey::X25519(prekey)).unwrap(),
+            None,
+        )
+        .unwrap();
+        (alice, bob)
+    }
+
+    fn send(session_id: &str, plaintext: &str) -> RatchetMessage {
+        ratchet_encrypt(session_id.into(), plaintext.into()).unwrap()
+    }
+
+    fn receive(session_id: &str, message: &RatchetMessage) -> Result<String, String> {
+        let message = RatchetMessage {
+            header: message.header.clone(),
+            ciphertext: message.ciphertext.clone(),
+        };
+        ratchet_decrypt(session_id.into(), message)
+    }
+
+    fn stored(session_id: &str) -> String {
+        keystore::load_secret(&session_key_id(session_id)).unwrap()
+    }
+
+    #[test]
+    fn round_trip_in_order() {
+        let _keystore = keystore::test_keystore();
+        let (alice, bob) = session_pair();
+
+        assert!(ratchet_encrypt(bob.clone(), "too early".into()).is_err());
+        for text in ["one", "two", "three"] {
+            assert_eq!(receive(&bob, &send(&alice, text)).unwrap(), text);
+        }
+        assert_eq!(receive(&alice, &send(&bob, "reply")).unwrap(), "reply");
+
+        let next = send(&alice, "four");
+        assert_eq!(next.header.n, 0);
+        assert_eq!(next.header.pn, 3);
+        assert_eq!(receive(&bob, &next).unwrap(), "four");
+
+        // The state survives the in-memory cache being dropped
+        wipe();
+        assert_eq!(receive(&alice, &send(&bob, "again")).unwrap(), "again");
+    }
+
+    #[test]
+    fn out_of_order_and_replay() {
+        let _keystore = keystore::test_keystore();
+        let (alice, bob) = session_pair();
+
+        let messages: Vec<_> = (0..3).map(|i| send(&alice, &i.to_string())).collect();
+        assert_eq!(receive(&bob, &messages[2]).unwrap(), "2");
+        assert_eq!(receive(&bob, &messages[0]).unwrap(), "0");
+        assert!(receive(&bob, &messages[0]).is_err());
+        assert!(receive(&bob, &messages[2]).is_err());
+        assert_eq!(receive(&bob, &messages[1]).unwrap(), "1");
+
+        // Across a DH ratchet step, late messages of the old chain still open
+        let late = send(&alice, "late");
+        assert_eq!(receive(&alice, &send(&bob, "turn")).unwrap(), "turn");
+        assert_eq!(
+            receive(&bob, &send(&alice, "new chain")).unwrap(),
+            "new chain"
+        );
+        assert_eq!(receive(&bob, &late).unwrap(), "late");
+    }
+
+    #[test]
+    fn too_many_skipped_messages() {
+        let _keystore = keystore::test_keystore();
+        let (alice, bob) = session_pair();
+
+        let first = send(&alice, "first");
+        let mut state = load(&alice).unwrap();
+        let mut last = None;
+        for _ in 0..=MAX_SKIP {
+            last = Some(state.encrypt(b"skipped").unwrap());
+        }
+        let last = last.unwrap();
+        assert_eq!(last.header.n, MAX_SKIP + 1);
+
+        let before = stored(&bob);
+        assert!(receive(&bob, &last)
+            .unwrap_err()
+            .contains("Too many skipped"));
+        assert_eq!(stored(&bob), before);
+        assert_eq!(receive(&bob, &first).unwrap(), "first");
+    }
+
+    #[test]
+    fn failed_decrypt_leaves_state_unchanged() {
+        let _keystore = keystore::test_keystore();
+        let (alice, bob) = session_pair();
+        assert_eq!(receive(&bob, &send(&alice, "hello")).unwrap(), "hello");
+
+        let message = send(&alice, "world");
+        let mut forged = RatchetMessage {
+            header: message.header.clone(),
+            ciphertext: message.ciphertext.clone(),
+        };
+        let mut ciphertext = BASE64.decode(&forged.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        forged.ciphertext = BASE64.encode(ciphertext);
+
+        let before = stored(&bob);
+        assert!(receive(&bob, &forged).is_err());
+        // A forged header must not skip keys or step the ratchet either
+        forged.ciphertext = message.ciphertext.clone();
+        forged.header.n = 50;
+        assert!(receive(&bob, &forged).is_err());
+        forged.header = message.header.clone();
+        forged.header.dh =
+            BASE64.encode(PublicKey::from(&StaticSecret::random_from_rng(OsRng)).as_bytes());
+        assert!(receive(&bob, &forged).is_err());
+        assert_eq!(stored(&bob), before);
+
+        assert_eq!(receive(&bob, &message).unwrap(), "world");
+        assert_ne!(stored(&bob), before);
+    }
+
+    #[test]
+    fn sessions_need_the_keystore() {
+        let _keystore = keystore::test_keystore();
+        let (alice, bob) = session_pair();
+        let message = send(&alice, "hello");
+
+        keystore::lock_keystore().unwrap();
+        assert!(ratchet_encrypt(alice.clone(), "not saved".into()).is_err());
+        assert!(receive(&bob, &message).is_err());
+        let prekey = StaticSecret::random_from_rng(OsRng);
+        assert!(ratchet_init_initiator(
+            "unsaved".into(),
+            handles::insert_symmetric([7u8; 32]).unwrap(),
+            BASE64.encode(PublicKey::from(&prekey).as_bytes()),
+            None,
+        )
+        .is_err());
+        assert!(load("unsaved").is_err());
+
+        keystore::unlock_keystore("correct horse".into()).unwrap();
+        assert_eq!(receive(&bob, &message).unwrap(), "hello");
+    }
+}
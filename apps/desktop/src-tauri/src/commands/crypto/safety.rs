@@ -0,0 +1,341 @@
+//! Safety numbers for out-of-band identity verification.
+//!
+//! Each side's fingerprint is an iterated SHA-512 over its identity public
+//! keys and user id. Two contacts compare the combined safety number by
+//! reading it aloud or by scanning each other's QR code. Once a contact has
+//! been checked, the result is saved in the local database together with the
+//! keys that were verified, so a later key change shows up as unverified.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+use super::decode_key;
+use crate::commands::storage;
+
+/// Fingerprint format version, hashed into every fingerprint.
+const FINGERPRINT_VERSION: u16 = 0;
+
+/// Hash iterations per fingerprint, as in Signal's numeric fingerprints.
+const FINGERPRINT_ITERATIONS: u32 = 5200;
+
+/// Version byte in front of a QR payload.
+const QR_VERSION: u8 = 1;
+
+/// Fingerprint bytes carried in the QR payload, per side.
+const QR_FINGERPRINT_LEN: usize = 32;
+
+/// A user's identity public keys as published to the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityPublicKeys {
+    pub user_id: String,
+    /// X25519 identity public key (base64)
+    pub x25519_public: String,
+    /// Ed25519 identity public key (base64)
+    pub ed25519_public: String,
+}
+
+impl IdentityPublicKeys {
+    /// Ed25519 key || X25519 key, after checking both decode to 32 bytes.
+    pub(crate) fn key_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut out = decode_key(&self.ed25519_public, "Ed25519 identity key")?.to_vec();
+        out.extend_from_slice(&decode_key(&self.x25519_public, "X25519 identity key")?);
+        Ok(out)
+    }
+
+    /// Check that both keys are well-formed.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        self.key_bytes().map(|_| ())
+    }
+
+    pub(crate) fn same_keys(&self, x25519_public: &str, ed25519_public: &str) -> bool {
+        self.x25519_public == x25519_public && self.ed25519_public == ed25519_public
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SafetyNumber {
+    /// 60 decimal digits, shown to the user in groups of five
+    pub numeric: String,
+    /// Base64 payload for the local QR code
+    pub qr_payload: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerificationStatus {
+    pub contact_id: String,
+    pub verified: bool,
+    pub verified_at: Option<String>,
+    /// The contact's keys differ from the ones last verified
+    pub key_changed: bool,
+}
+
+/// Iterated SHA-512 over the version, the identity keys and the user id.
+fn fingerprint(identity: &IdentityPublicKeys) -> Result<[u8; 64], String> {
+    let key = identity.key_bytes()?;
+
+    let mut hasher = Sha512::new();
+    hasher.update(FINGERPRINT_VERSION.to_be_bytes());
+    hasher.update(&key);
+    hasher.update(identity.user_id.as_bytes());
+    let mut hash = hasher.finalize();
+
+    for _ in 0..FINGERPRINT_ITERATIONS {
+        let mut hasher = Sha512::new();
+        hasher.update(hash);
+        hasher.update(&key);
+        hash = hasher.finalize();
+    }
+
+    Ok(hash.into())
+}
+
+/// 30 digits from the first 30 fingerprint bytes: six 5-byte chunks, each
+/// reduced mod 100000.
+fn displayable(fingerprint: &[u8; 64]) -> String {
+    fingerprint[..30]
+        .chunks(5)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+            format!("{:05}", value % 100_000)
+        })
+        .collect()
+}
+
+fn qr_payload(local: &[u8; 64], remote: &[u8; 64]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + 2 * QR_FINGERPRINT_LEN);
+    out.push(QR_VERSION);
+    out.extend_from_slice(&local[..QR_FINGERPRINT_LEN]);
+    out.extend_from_slice(&remote[..QR_FINGERPRINT_LEN]);
+    out
+}
+
+/// Compute the safety number between our identity and a contact's.
+///
+/// The numeric form is the same on both devices. The QR payload lists our
+/// fingerprint first and is meant to be scanned by the contact.
+#[tauri::command]
+pub fn compute_safety_number(
+    local: IdentityPublicKeys,
+    remote: IdentityPublicKeys,
+) -> Result<SafetyNumber, String> {
+    let local_fp = fingerprint(&local)?;
+    let remote_fp = fingerprint(&remote)?;
+
+    let mut halves = [displayable(&local_fp), displayable(&remote_fp)];
+    halves.sort();
+
+    Ok(SafetyNumber {
+        numeric: halves.concat(),
+        qr_payload: BASE64.encode(qr_payload(&local_fp, &remote_fp)),
+    })
+}
+
+/// Check a QR payload scanned from the contact's screen.
+///
+/// Returns `true` when the payload matches both identities. A mismatch means
+/// one side holds a different key than the other thinks it does.
+#[tauri::command]
+pub fn verify_safety_number(
+    local: IdentityPublicKeys,
+    remote: IdentityPublicKeys,
+    scanned_payload: String,
+) -> Result<bool, String> {
+    let scanned = BASE64
+        .decode(&scanned_payload)
+        .map_err(|e| format!("Invalid base64 QR payload: {}", e))?;
+    if scanned.len() != 1 + 2 * QR_FINGERPRINT_LEN {
+        return Err("Invalid QR payload length".to_string());
+    }
+    if scanned[0] != QR_VERSION {
+        return Err(format!("Unsupported safety number version {}", scanned[0]));
+    }
+
+    // The contact generated the payload, so their fingerprint comes first.
+    let expected = qr_payload(&fingerprint(&remote)?, &fingerprint(&local)?);
+    Ok(scanned == expected)
+}
+
+/// Save the verification status of `contact` together with its keys.
+pub(crate) fn save_verification(
+    conn: &Connection,
+    contact: &IdentityPublicKeys,
+    verified: bool,
+) -> Result<VerificationStatus, String> {
+    let now = Utc::now().to_rfc3339();
+    let verified_at = verified.then(|| now.clone());
+
+    conn.execute(
+        "INSERT INTO contact_verification
+             (contact_id, x25519_public, ed25519_public, verified, verified_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT (contact_id) DO UPDATE SET
+             x25519_public = excluded.x25519_public,
+             ed25519_public = excluded.ed25519_public,
+             verified = excluded.verified,
+             verified_at = excluded.verified_at,
+             updated_at = excluded.updated_at",
+        params![
+            contact.user_id,
+            contact.x25519_public,
+            contact.ed25519_public,
+            verified,
+            verified_at,
+            now
+        ],
+    )
+    .map_err(|e| format!("Failed to save verification status: {}", e))?;
+
+    Ok(VerificationStatus {
+        contact_id: contact.user_id.clone(),
+        verified,
+        verified_at,
+        key_changed: false,
+    })
+}
+
+/// Record whether `contact` has been verified, pinning the keys that were checked.
+#[tauri::command]
+pub fn set_contact_verified(
+    db_path: String,
+    passphrase: String,
+    contact: IdentityPublicKeys,
+    verified: bool,
+) -> Result<VerificationStatus, String> {
+    contact.validate()?;
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+    save_verification(&conn, &contact, verified)
+}
+
+/// Verification status of `contact` for its current keys.
+///
+/// If the keys differ from the ones that were verified, the contact is
+/// reported as unverified with `key_changed` set.
+#[tauri::command]
+pub fn get_contact_verification(
+    db_path: String,
+    passphrase: String,
+    contact: IdentityPublicKeys,
+) -> Result<VerificationStatus, String> {
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+
+    let row = conn
+        .query_row(
+            "SELECT x25519_public, ed25519_public, verified, verified_at
+             FROM contact_verification
+             WHERE contact_id = ?1",
+            params![contact.user_id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, bool>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(|e| format!("Failed to query verification status: {}", e))?;
+
+    let status = match row {
+        Some((x25519_public, ed25519_public, verified, verified_at)) => {
+            if contact.same_keys(&x25519_public, &ed25519_public) {
+                VerificationStatus {
+                    contact_id: contact.user_id,
+                    verified,
+                    verified_at,
+                    key_changed: false,
+                }
+            } else {
+                VerificationStatus {
+                    contact_id: contact.user_id,
+                    verified: false,
+                    verified_at: None,
+                    key_changed: true,
+                }
+            }
+        }
+        None => VerificationStatus {
+            contact_id: contact.user_id,
+            verified: false,
+            verified_at: None,
+            key_changed: false,
+        },
+    };
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(user_id: &str, x25519: u8, ed25519: u8) -> IdentityPublicKeys {
+        IdentityPublicKeys {
+            user_id: user_id.to_string(),
+            x25519_public: BASE64.encode([x25519; 32]),
+            ed25519_public: BASE64.encode([ed25519; 32]),
+        }
+    }
+
+    #[test]
+    fn both_sides_compute_the_same_number() {
+        let alice = identity("alice", 1, 2);
+        let bob = identity("bob", 3, 4);
+
+        let on_alice = compute_safety_number(alice.clone(), bob.clone()).unwrap();
+        let on_bob = compute_safety_number(bob.clone(), alice.clone()).unwrap();
+        assert_eq!(on_alice.numeric, on_bob.numeric);
+        assert_eq!(on_alice.numeric.len(), 60);
+        assert!(on_alice.numeric.bytes().all(|b| b.is_ascii_digit()));
+
+        // Each side scans the other's QR code
+        assert!(verify_safety_number(bob.clone(), alice.clone(), on_alice.qr_payload).unwrap());
+        assert!(
+            verify_safety_number(alice.clone(), bob.clone(), on_bob.qr_payload.clone()).unwrap()
+        );
+
+        let swapped = identity("bob", 3, 5);
+        assert_ne!(
+            compute_safety_number(alice.clone(), swapped.clone())
+                .unwrap()
+                .numeric,
+            on_bob.numeric
+        );
+        assert!(!verify_safety_number(alice, swapped, on_bob.qr_payload).unwrap());
+    }
+
+    #[test]
+    fn known_answer() {
+        // Pins the fingerprint format; changing it breaks every saved comparison.
+        let number = compute_safety_number(identity("alice", 1, 2), identity("bob", 3, 4)).unwrap();
+        assert_eq!(
+            number.numeric,
+            "172735215495621315251049973237552533265636314065274315310943"
+        );
+        assert_eq!(
+            number.qr_payload,
+            "AdTew3F5vJWgJVqX676N5SXhZb7FdKL1l8MU5iWnNbFOvNjDsXWHmrTqMMCAAHxagDJAkp/wkX0O0Wr8BN6fXNg="
+        );
+    }
+
+    #[test]
+    fn verification_resets_on_key_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = storage::test_db(&dir);
+        let bob = identity("bob", 3, 4);
+
+        let status = get_contact_verification(db_path.clone(), String::new(), bob.clone()).unwrap();
+        assert!(!status.verified && !status.key_changed);
+
+        set_contact_verified(db_path.clone(), String::new(), bob.clone(), true).unwrap();
+        let status = get_contact_verification(db_path.clone(), String::new(), bob).unwrap();
+        assert!(status.verified && status.verified_at.is_some());
+
+        let status =
+            get_contact_verification(db_path, String::new(), identity("bob", 3, 5)).unwrap();
+        assert!(!status.verified && status.key_changed);
+    }
+}
@@ -0,0 +1,938 @@
+//! Interactive short authentication string (SAS) verification.
+//!
+//! Two contacts verify each other's identity keys by comparing seven emoji
+//! or three four-digit numbers, which works over a voice call where QR codes
+//! and 60-digit safety numbers do not. The exchange follows Matrix's SAS
+//! verification:
+//!
+//! ```text
+//! initiator                      responder
+//!   start   ---------------------->
+//!           <----------------------   accept (commitment to its key)
+//!   key     ---------------------->
+//!           <----------------------   key
+//!          both show the SAS; the users compare it
+//!   mac     <--------------------->   mac
+//! ```
+//!
+//! The responder commits to its ephemeral key before it sees the
+//! initiator's, so neither side can steer the SAS. Each `mac` proves that
+//! the sender shares the SAS secret and holds the identity keys the other
+//! side expects. Rust keeps the state; the frontend relays the messages over
+//! the gateway. A session ends `verified`, which marks the contact verified,
+//! or `cancelled`. Both outcomes are recorded in `sas_verifications` by the
+//! command that ends the session, which is why those commands take the
+//! database passphrase. Any malformed or out-of-order message cancels.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::Utc;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use super::decode_key;
+use super::safety::{self, IdentityPublicKeys, VerificationStatus};
+use crate::commands::storage;
+
+/// HKDF info prefix for the SAS bytes.
+const SAS_INFO: &[u8] = b"zeusix-sas-v1";
+
+/// HKDF info prefix for the identity MAC keys.
+const MAC_INFO: &[u8] = b"zeusix-sas-mac-v1";
+
+/// Domain separation prefix for the responder's key commitment.
+const COMMITMENT_CONTEXT: &[u8] = b"zeusix-sas-commit-v1";
+
+/// Emoji table from the Matrix specification, indexed by 6-bit values.
+const EMOJI: [(&str, &str); 64] = [
+    ("🐶", "Dog"),
+    ("🐱", "Cat"),
+    ("🦁", "Lion"),
+    ("🐎", "Horse"),
+    ("🦄", "Unicorn"),
+    ("🐷", "Pig"),
+    ("🐘", "Elephant"),
+    ("🐰", "Rabbit"),
+    ("🐼", "Panda"),
+    ("🐓", "Rooster"),
+    ("🐧", "Penguin"),
+    ("🐢", "Turtle"),
+    ("🐟", "Fish"),
+    ("🐙", "Octopus"),
+    ("🦋", "Butterfly"),
+    ("🌷", "Flower"),
+    ("🌳", "Tree"),
+    ("🌵", "Cactus"),
+    ("🍄", "Mushroom"),
+    ("🌏", "Globe"),
+    ("🌙", "Moon"),
+    ("☁️", "Cloud"),
+    ("🔥", "Fire"),
+    ("🍌", "Banana"),
+    ("🍎", "Apple"),
+    ("🍓", "Strawberry"),
+    ("🌽", "Corn"),
+    ("🍕", "Pizza"),
+    ("🎂", "Cake"),
+    ("❤️", "Heart"),
+    ("😀", "Smiley"),
+    ("🤖", "Robot"),
+    ("🎩", "Hat"),
+    ("👓", "Glasses"),
+    ("🔧", "Spanner"),
+    ("🎅", "Santa"),
+    ("👍", "Thumbs Up"),
+    ("☂️", "Umbrella"),
+    ("⌛", "Hourglass"),
+    ("⏰", "Clock"),
+    ("🎁", "Gift"),
+    ("💡", "Light Bulb"),
+    ("📕", "Book"),
+    ("✏️", "Pencil"),
+    ("📎", "Paperclip"),
+    ("✂️", "Scissors"),
+    ("🔒", "Lock"),
+    ("🔑", "Key"),
+    ("🔨", "Hammer"),
+    ("☎️", "Telephone"),
+    ("🏁", "Flag"),
+    ("🚂", "Train"),
+    ("🚲", "Bicycle"),
+    ("✈️", "Aeroplane"),
+    ("🚀", "Rocket"),
+    ("🏆", "Trophy"),
+    ("⚽", "Ball"),
+    ("🎸", "Guitar"),
+    ("🎺", "Trumpet"),
+    ("🔔", "Bell"),
+    ("⚓", "Anchor"),
+    ("🎧", "Headphones"),
+    ("📁", "Folder"),
+    ("📌", "Pin"),
+];
+
+/// A protocol message, relayed verbatim to the other side.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SasMessage {
+    pub transaction_id: String,
+    #[serde(flatten)]
+    pub content: SasContent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SasContent {
+    Start,
+    /// SHA-256 commitment to the responder's ephemeral key (base64)
+    Accept {
+        commitment: String,
+    },
+    /// Ephemeral X25519 public key (base64)
+    Key {
+        key: String,
+    },
+    /// HMAC-SHA256 over the sender's identity keys (base64)
+    Mac {
+        mac: String,
+    },
+    Cancel {
+        reason: CancelReason,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelReason {
+    /// A user aborted the verification
+    User,
+    /// The users reported that the displayed SAS differs
+    MismatchedSas,
+    /// The responder's key does not match its commitment
+    MismatchedCommitment,
+    /// The MAC does not match the expected identity keys
+    MismatchedKeys,
+    /// A message arrived out of order
+    UnexpectedMessage,
+    /// A message could not be parsed
+    InvalidMessage,
+}
+
+impl CancelReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            CancelReason::User => "user",
+            CancelReason::MismatchedSas => "mismatched_sas",
+            CancelReason::MismatchedCommitment => "mismatched_commitment",
+            CancelReason::MismatchedKeys => "mismatched_keys",
+            CancelReason::UnexpectedMessage => "unexpected_message",
+            CancelReason::InvalidMessage => "invalid_message",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SasState {
+    /// Initiator: waiting for `accept`
+    Started,
+    /// Responder: waiting for the initiator's `key`
+    Accepted,
+    /// Initiator: waiting for the responder's `key`
+    KeySent,
+    /// The SAS is ready to be compared
+    KeysExchanged,
+    /// The local user confirmed the SAS; waiting for the contact's `mac`
+    Confirmed,
+    Verified,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SasEmoji {
+    pub emoji: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sas {
+    /// Seven emoji, for display with their descriptions
+    pub emoji: Vec<SasEmoji>,
+    /// Three numbers between 1000 and 9191, for users who prefer digits
+    pub decimal: [u16; 3],
+}
+
+/// Result of every SAS command.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SasStep {
+    pub transaction_id: String,
+    pub state: SasState,
+    /// Message to relay to the contact, if any
+    pub outgoing: Option<SasMessage>,
+    /// Present once both keys are exchanged and until the session ends
+    pub sas: Option<Sas>,
+    pub cancel_reason: Option<CancelReason>,
+    /// The contact's new verification status once the session is verified
+    pub verification: Option<VerificationStatus>,
+}
+
+/// Ephemeral keys and DH output once both keys are known.
+#[derive(Clone)]
+struct ExchangedKeys {
+    ours: [u8; 32],
+    theirs: [u8; 32],
+    shared: Zeroizing<[u8; 32]>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Initiator,
+    Responder,
+}
+
+#[derive(Clone)]
+struct SasSession {
+    role: Role,
+    state: SasState,
+    local: IdentityPublicKeys,
+    remote: IdentityPublicKeys,
+    ephemeral: StaticSecret,
+    /// Responder's commitment, held by the initiator until the key arrives
+    commitment: Option<Vec<u8>>,
+    keys: Option<ExchangedKeys>,
+    /// The contact's MAC arrived and checked out before we confirmed
+    mac_received: bool,
+    cancel_reason: Option<CancelReason>,
+}
+
+/// Active verifications: (contact user id, transaction id) -> session.
+type Sessions = HashMap<(String, String), SasSession>;
+
+static SESSIONS: std::sync::LazyLock<Mutex<Sessions>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Abandon every verification in progress when the keystore locks, wiping
+/// its ephemeral keys.
+pub(crate) fn wipe() {
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        sessions.clear();
+    }
+}
+
+/// Append `field` with a 4-byte big-endian length prefix.
+fn push_field(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+fn commitment(
+    transaction_id: &str,
+    initiator_id: &str,
+    responder_id: &str,
+    key: &[u8; 32],
+) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(COMMITMENT_CONTEXT);
+    for field in [
+        transaction_id.as_bytes(),
+        initiator_id.as_bytes(),
+        responder_id.as_bytes(),
+        key,
+    ] {
+        hasher.update((field.len() as u32).to_be_bytes());
+        hasher.update(field);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Seven emoji from the first 42 bits.
+fn emoji(bytes: &[u8; 6]) -> Vec<SasEmoji> {
+    let bits = bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+    (0..7)
+        .map(|i| {
+            let (emoji, description) = EMOJI[((bits >> (42 - 6 * i)) & 0x3F) as usize];
+            SasEmoji {
+                emoji: emoji.to_string(),
+                description: description.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Three 13-bit numbers from the first 39 bits, offset by 1000.
+fn decimal(bytes: &[u8; 6]) -> [u16; 3] {
+    let b: Vec<u16> = bytes.iter().map(|b| *b as u16).collect();
+    [
+        ((b[0] << 5) | (b[1] >> 3)) + 1000,
+        (((b[1] & 0x7) << 10) | (b[2] << 2) | (b[3] >> 6)) + 1000,
+        (((b[3] & 0x3F) << 7) | (b[4] >> 1)) + 1000,
+    ]
+}
+
+impl SasSession {
+    fn new(
+        role: Role,
+        state: SasState,
+        local: IdentityPublicKeys,
+        remote: IdentityPublicKeys,
+    ) -> Result<Self, String> {
+        local.validate()?;
+        remote.validate()?;
+        Ok(SasSession {
+            role,
+            state,
+            local,
+            remote,
+            ephemeral: StaticSecret::random_from_rng(OsRng),
+            commitment: None,
+            keys: None,
+            mac_received: false,
+            cancel_reason: None,
+        })
+    }
+
+    fn our_key(&self) -> [u8; 32] {
+        PublicKey::from(&self.ephemeral).to_bytes()
+    }
+
+    fn key_message(&self) -> SasContent {
+        SasContent::Key {
+            key: BASE64.encode(self.our_key()),
+        }
+    }
+
+    fn cancel(&mut self, reason: CancelReason) -> Option<SasContent> {
+        self.state = SasState::Cancelled;
+        self.cancel_reason = Some(reason);
+        Some(SasContent::Cancel { reason })
+    }
+
+    /// Run the key agreement with the contact's ephemeral key.
+    fn exchange_keys(&mut self, their_key: &str) -> Result<(), String> {
+        let their_key = decode_key(their_key, "SAS key")?;
+        let shared = self.ephemeral.diffie_hellman(&PublicKey::from(their_key));
+        if !shared.was_contributory() {
+            return Err("Diffie-Hellman produced a non-contributory result".to_string());
+        }
+        self.keys = Some(ExchangedKeys {
+            ours: self.our_key(),
+            theirs: their_key,
+            shared: Zeroizing::new(shared.to_bytes()),
+        });
+        self.state = SasState::KeysExchanged;
+        Ok(())
+    }
+
+    /// HKDF over the DH output, bound to the transaction, both users and
+    /// both ephemeral keys in initiator-first order.
+    fn expand(
+        &self,
+        transaction_id: &str,
+        info_prefix: &[u8],
+        len: usize,
+    ) -> Result<Zeroizing<Vec<u8>>, String> {
+        let keys = self
+            .keys
+            .as_ref()
+            .ok_or_else(|| "SAS keys have not been exchanged".to_string())?;
+        let ((first, first_key), (second, second_key)) = match self.role {
+            Role::Initiator => ((&self.local, &keys.ours), (&self.remote, &keys.theirs)),
+            Role::Responder => ((&self.remote, &keys.theirs), (&self.local, &keys.ours)),
+        };
+
+        let mut info = info_prefix.to_vec();
+        push_field(&mut info, transaction_id.as_bytes());
+        push_field(&mut info, first.user_id.as_bytes());
+        push_field(&mut info, first_key);
+        push_field(&mut info, second.user_id.as_bytes());
+        push_field(&mut info, second_key);
+
+        let mut out = Zeroizing::new(vec![0u8; len]);
+        Hkdf::<Sha256>::new(None, keys.shared.as_ref())
+            .expand(&info, &mut out)
+            .map_err(|e| format!("HKDF expansion failed: {}", e))?;
+        Ok(out)
+    }
+
+    fn sas(&self, transaction_id: &str) -> Result<Sas, String> {
+        let mut bytes = [0u8; 6];
+        bytes.copy_from_slice(&self.expand(transaction_id, SAS_INFO, 6)?);
+        Ok(Sas {
+            emoji: emoji(&bytes),
+            decimal: decimal(&bytes),
+        })
+    }
+
+    /// MAC over `sender`'s identity keys, under a key specific to the sender.
+    fn identity_mac(
+        &self,
+        transaction_id: &str,
+        sender: &IdentityPublicKeys,
+    ) -> Result<Hmac<Sha256>, String> {
+        let mut info = MAC_INFO.to_vec();
+        push_field(&mut info, sender.user_id.as_bytes());
+        let key = self.expand(transaction_id, &info, 32)?;
+
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key)
+            .map_err(|e| format!("Invalid MAC key: {}", e))?;
+        mac.update(&sender.key_bytes()?);
+        Ok(mac)
+    }
+
+    /// Apply a message from the contact; returns the reply, if any. A
+    /// message that cannot be applied cancels the session.
+    fn receive(&mut self, transaction_id: &str, content: SasContent) -> Option<SasContent> {
+        match self.apply(transaction_id, content) {
+            Ok(reply) => reply,
+            Err(_) => self.cancel(CancelReason::InvalidMessage),
+        }
+    }
+
+    fn apply(
+        &mut self,
+        transaction_id: &str,
+        content: SasContent,
+    ) -> Result<Option<SasContent>, String> {
+        match (self.role, self.state, content) {
+            (_, _, SasContent::Cancel { reason }) => {
+                self.state = SasState::Cancelled;
+                self.cancel_reason = Some(reason);
+                Ok(None)
+            }
+            (Role::Initiator, SasState::Started, SasContent::Accept { commitment }) => {
+                self.commitment = Some(
+                    BASE64
+                        .decode(&commitment)
+                        .map_err(|e| format!("Invalid base64 commitment: {}", e))?,
+                );
+                self.state = SasState::KeySent;
+                Ok(Some(self.key_message()))
+            }
+            (Role::Responder, SasState::Accepted, SasContent::Key { key }) => {
+                self.exchange_keys(&key)?;
+                Ok(Some(self.key_message()))
+            }
+            (Role::Initiator, SasState::KeySent, SasContent::Key { key }) => {
+                let expected = commitment(
+                    transaction_id,
+                    &self.local.user_id,
+                    &self.remote.user_id,
+                    &decode_key(&key, "SAS key")?,
+                );
+                if self.commitment.as_deref() != Some(expected.as_slice()) {
+                    return Ok(self.cancel(CancelReason::MismatchedCommitment));
+                }
+                self.exchange_keys(&key)?;
+                Ok(None)
+            }
+            (_, SasState::KeysExchanged | SasState::Confirmed, SasContent::Mac { mac })
+                if !self.mac_received =>
+            {
+                let mac = BASE64
+                    .decode(&mac)
+                    .map_err(|e| format!("Invalid base64 MAC: {}", e))?;
+                if self
+                    .identity_mac(transaction_id, &self.remote)?
+                    .verify_slice(&mac)
+                    .is_err()
+                {
+                    return Ok(self.cancel(CancelReason::MismatchedKeys));
+                }
+                self.mac_received = true;
+                if self.state == SasState::Confirmed {
+                    self.state = SasState::Verified;
+                }
+                Ok(None)
+            }
+            _ => Ok(self.cancel(CancelReason::UnexpectedMessage)),
+        }
+    }
+
+    /// Store the outcome of a finished session.
+    fn record(
+        &self,
+        db_path: &str,
+        passphrase: &str,
+        transaction_id: &str,
+    ) -> Result<Option<VerificationStatus>, String> {
+        let mut conn = storage::open_connection(db_path, passphrase)?;
+        let tx = conn
+            .transaction()
+            .map_err(|e| format!("Failed to start transaction: {}", e))?;
+
+        let verified = self.state == SasState::Verified;
+        tx.execute(
+            "INSERT OR REPLACE INTO sas_verifications
+                 (transaction_id, contact_id, outcome, cancel_reason, completed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                transaction_id,
+                self.remote.user_id,
+                if verified { "verified" } else { "cancelled" },
+                self.cancel_reason.map(CancelReason::as_str),
+                Utc::now().to_rfc3339()
+            ],
+        )
+        .map_err(|e| format!("Failed to record SAS verification: {}", e))?;
+
+        let status = if verified {
+            Some(safety::save_verification(&tx, &self.remote, true)?)
+        } else {
+            None
+        };
+
+        tx.commit()
+            .map_err(|e| format!("Failed to commit transaction: {}", e))?;
+        Ok(status)
+    }
+}
+
+fn lock_sessions() -> Result<std::sync::MutexGuard<'static, Sessions>, String> {
+    SESSIONS
+        .lock()
+        .map_err(|e| format!("Failed to lock SAS sessions: {}", e))
+}
+
+/// Run `f` on a copy of the session with `contact_id` and report the new
+/// state.
+///
+/// A finished session has its outcome recorded before it is dropped. The
+/// copy only replaces the session once everything has succeeded, so on an
+/// error the session is unchanged and the same call can be retried.
+fn advance(
+    db_path: &str,
+    passphrase: &str,
+    contact_id: &str,
+    transaction_id: &str,
+    f: impl FnOnce(&mut SasSession) -> Result<Option<SasContent>, String>,
+) -> Result<SasStep, String> {
+    let key = (contact_id.to_string(), transaction_id.to_string());
+    let mut sessions = lock_sessions()?;
+    let mut session = sessions
+        .get(&key)
+        .ok_or_else(|| {
+            format!(
+                "Unknown SAS verification '{}' with '{}'",
+                transaction_id, contact_id
+            )
+        })?
+        .clone();
+
+    let outgoing = f(&mut session)?;
+    let state = session.state;
+    let cancel_reason = session.cancel_reason;
+    let sas = match state {
+        SasState::KeysExchanged | SasState::Confirmed => Some(session.sas(transaction_id)?),
+        _ => None,
+    };
+
+    let verification = match state {
+        SasState::Verified | SasState::Cancelled => {
+            let verification = session.record(db_path, passphrase, transaction_id)?;
+            sessions.remove(&key);
+            verification
+        }
+        _ => {
+            sessions.insert(key, session);
+            None
+        }
+    };
+
+    Ok(SasStep {
+        transaction_id: transaction_id.to_string(),
+        state,
+        outgoing: outgoing.map(|content| SasMessage {
+            transaction_id: transaction_id.to_string(),
+            content,
+        }),
+        sas,
+        cancel_reason,
+        verification,
+    })
+}
+
+/// Start verifying `remote` and return the `start` message to send.
+///
+/// `local` and `remote` are the identity keys each side believes in; the
+/// verification only succeeds if both sides agree on them.
+#[tauri::command]
+pub fn sas_start(local: IdentityPublicKeys, remote: IdentityPublicKeys) -> Result<SasStep, String> {
+    let transaction_id = Uuid::new_v4().to_string();
+    let session = SasSession::new(Role::Initiator, SasState::Started, local, remote)?;
+    lock_sessions()?.insert(
+        (session.remote.user_id.clone(), transaction_id.clone()),
+        session,
+    );
+
+    Ok(SasStep {
+        transaction_id: transaction_id.clone(),
+        state: SasState::Started,
+        outgoing: Some(SasMessage {
+            transaction_id,
+            content: SasContent::Start,
+        }),
+        sas: None,
+        cancel_reason: None,
+        verification: None,
+    })
+}
+
+/// Accept a `start` message from `remote` and return the `accept` reply.
+#[tauri::command]
+pub fn sas_accept(
+    local: IdentityPublicKeys,
+    remote: IdentityPublicKeys,
+    start: SasMessage,
+) -> Result<SasStep, String> {
+    if !matches!(start.content, SasContent::Start) {
+        return Err("Expected a SAS start message".to_string());
+    }
+
+    let session = SasSession::new(Role::Responder, SasState::Accepted, local, remote)?;
+    let commitment = commitment(
+        &start.transaction_id,
+        &session.remote.user_id,
+        &session.local.user_id,
+        &session.our_key(),
+    );
+
+    let key = (session.remote.user_id.clone(), start.transaction_id.clone());
+    let mut sessions = lock_sessions()?;
+    if sessions.contains_key(&key) {
+        return Err(format!(
+            "SAS verification '{}' already exists",
+            start.transaction_id
+        ));
+    }
+    sessions.insert(key, session);
+
+    Ok(SasStep {
+        transaction_id: start.transaction_id.clone(),
+        state: SasState::Accepted,
+        outgoing: Some(SasMessage {
+            transaction_id: start.transaction_id,
+            content: SasContent::Accept {
+                commitment: BASE64.encode(commitment),
+            },
+        }),
+        sas: None,
+        cancel_reason: None,
+        verification: None,
+    })
+}
+
+/// Apply a message relayed from `contact_id`, the user the gateway says
+/// sent it.
+///
+/// Malformed or out-of-order messages, a broken commitment or a wrong MAC
+/// cancel the verification; the returned step then carries a `cancel` to
+/// send back. `db_path` and `passphrase` are used to record the outcome
+/// when the message ends the verification.
+#[tauri::command]
+pub fn sas_receive(
+    db_path: String,
+    passphrase: String,
+    contact_id: String,
+    message: SasMessage,
+) -> Result<SasStep, String> {
+    let transaction_id = message.transaction_id.clone();
+    advance(
+        &db_path,
+        &passphrase,
+        &contact_id,
+        &transaction_id,
+        |session| Ok(session.receive(&transaction_id, message.content)),
+    )
+}
+
+/// Confirm that the SAS matches what the contact sees, and send our MAC.
+///
+/// The verification completes once the contact's MAC has also checked out.
+#[tauri::command]
+pub fn sas_confirm(
+    db_path: String,
+    passphrase: String,
+    contact_id: String,
+    transaction_id: String,
+) -> Result<SasStep, String> {
+    advance(
+        &db_path,
+        &passphrase,
+        &contact_id,
+        &transaction_id,
+        |session| {
+            if session.state != SasState::KeysExchanged {
+                return Err("SAS is not ready to be confirmed".to_string());
+            }
+            let mac = session.identity_mac(&transaction_id, &session.local)?;
+            session.state = if session.mac_received {
+                SasState::Verified
+            } else {
+                SasState::Confirmed
+            };
+            Ok(Some(SasContent::Mac {
+                mac: BASE64.encode(mac.finalize().into_bytes()),
+            }))
+        },
+    )
+}
+
+/// Cancel a verification, e.g. because the users saw different emoji.
+#[tauri::command]
+pub fn sas_cancel(
+    db_path: String,
+    passphrase: String,
+    contact_id: String,
+    transaction_id: String,
+    reason: CancelReason,
+) -> Result<SasStep, String> {
+    advance(
+        &db_path,
+        &passphrase,
+        &contact_id,
+        &transaction_id,
+        |session| Ok(session.cancel(reason)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::generate_keypair;
+    use crate::commands::keystore;
+
+    struct Party {
+        keys: IdentityPublicKeys,
+        db_path: String,
+        _dir: tempfile::TempDir,
+    }
+
+    fn party(user_id: &str) -> Party {
+        let keypair = generate_keypair().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        Party {
+            keys: IdentityPublicKeys {
+                user_id: user_id.to_string(),
+                x25519_public: keypair.x25519_public,
+                ed25519_public: keypair.ed25519_public,
+            },
+            db_path: storage::test_db(&dir),
+            _dir: dir,
+        }
+    }
+
+    /// `to` receives `message` from `from`.
+    fn deliver(to: &Party, from: &Party, message: Option<SasMessage>) -> SasStep {
+        sas_receive(
+            to.db_path.clone(),
+            String::new(),
+            from.keys.user_id.clone(),
+            message.unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn recorded(party: &Party, transaction_id: &str) -> (String, Option<String>) {
+        storage::open_connection(&party.db_path, "")
+            .unwrap()
+            .query_row(
+                "SELECT outcome, cancel_reason FROM sas_verifications
+                 WHERE transaction_id = ?1",
+                params![transaction_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+    }
+
+    /// Run start/accept and return the initiator's step carrying its key.
+    fn start(alice: &Party, bob: &Party) -> SasStep {
+        let start = sas_start(alice.keys.clone(), bob.keys.clone()).unwrap();
+        let accept = sas_accept(
+            bob.keys.clone(),
+            alice.keys.clone(),
+            start.outgoing.unwrap(),
+        )
+        .unwrap();
+        let alice_key = deliver(alice, bob, accept.outgoing);
+        assert_eq!(alice_key.state, SasState::KeySent);
+        alice_key
+    }
+
+    /// Run start/accept/key/key and return the responder's step carrying
+    /// its key.
+    fn exchange_keys(alice: &Party, bob: &Party) -> SasStep {
+        let alice_key = start(alice, bob);
+        deliver(bob, alice, alice_key.outgoing)
+    }
+
+    #[test]
+    fn full_verification() {
+        // Lock tests wipe the sessions
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let (alice, bob) = (party("alice"), party("bob"));
+
+        let bob_key = exchange_keys(&alice, &bob);
+        let transaction_id = bob_key.transaction_id.clone();
+        assert_eq!(bob_key.state, SasState::KeysExchanged);
+        let alice_sas = deliver(&alice, &bob, bob_key.outgoing);
+        assert_eq!(alice_sas.state, SasState::KeysExchanged);
+        assert!(alice_sas.outgoing.is_none());
+
+        let (ours, theirs) = (alice_sas.sas.unwrap(), bob_key.sas.unwrap());
+        assert_eq!(ours.decimal, theirs.decimal);
+        assert!(ours.decimal.iter().all(|n| (1000..=9191).contains(n)));
+        let describe = |sas: &Sas| -> Vec<String> {
+            sas.emoji
+                .iter()
+                .map(|e| format!("{} {}", e.emoji, e.description))
+                .collect()
+        };
+        assert_eq!(ours.emoji.len(), 7);
+        assert_eq!(describe(&ours), describe(&theirs));
+
+        let alice_mac = sas_confirm(
+            alice.db_path.clone(),
+            String::new(),
+            "bob".into(),
+            transaction_id.clone(),
+        )
+        .unwrap();
+        assert_eq!(alice_mac.state, SasState::Confirmed);
+        let bob_step = deliver(&bob, &alice, alice_mac.outgoing);
+        assert_eq!(bob_step.state, SasState::KeysExchanged);
+
+        let bob_mac = sas_confirm(
+            bob.db_path.clone(),
+            String::new(),
+            "alice".into(),
+            transaction_id.clone(),
+        )
+        .unwrap();
+        assert_eq!(bob_mac.state, SasState::Verified);
+        assert!(bob_mac.verification.unwrap().verified);
+
+        let alice_done = deliver(&alice, &bob, bob_mac.outgoing);
+        assert_eq!(alice_done.state, SasState::Verified);
+        assert!(alice_done.verification.unwrap().verified);
+        for party in [&alice, &bob] {
+            assert_eq!(recorded(party, &transaction_id).0, "verified");
+        }
+
+        // Finished sessions are gone
+        assert!(sas_confirm(
+            alice.db_path.clone(),
+            String::new(),
+            "bob".into(),
+            transaction_id,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn swapped_key_breaks_commitment() {
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let (alice, bob) = (party("alice"), party("bob"));
+
+        let mut bob_key = exchange_keys(&alice, &bob);
+        let transaction_id = bob_key.transaction_id.clone();
+        let other = StaticSecret::random_from_rng(OsRng);
+        bob_key.outgoing.as_mut().unwrap().content = SasContent::Key {
+            key: BASE64.encode(PublicKey::from(&other).as_bytes()),
+        };
+
+        let step = deliver(&alice, &bob, bob_key.outgoing);
+        assert_eq!(step.state, SasState::Cancelled);
+        assert_eq!(step.cancel_reason, Some(CancelReason::MismatchedCommitment));
+        assert!(matches!(
+            step.outgoing.unwrap().content,
+            SasContent::Cancel {
+                reason: CancelReason::MismatchedCommitment
+            }
+        ));
+        assert_eq!(
+            recorded(&alice, &transaction_id),
+            (
+                "cancelled".to_string(),
+                Some("mismatched_commitment".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn malformed_message_cancels() {
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let (alice, bob) = (party("alice"), party("bob"));
+
+        let mut alice_key = start(&alice, &bob);
+        alice_key.outgoing.as_mut().unwrap().content = SasContent::Key {
+            key: "not a key".into(),
+        };
+        let message = alice_key.outgoing.unwrap();
+
+        // A failed write leaves the session in place for a retry
+        assert!(sas_receive(
+            bob.db_path.replace("local.db", "missing/local.db"),
+            String::new(),
+            "alice".into(),
+            message.clone(),
+        )
+        .is_err());
+        let step = deliver(&bob, &alice, Some(message));
+        assert_eq!(step.state, SasState::Cancelled);
+        assert_eq!(step.cancel_reason, Some(CancelReason::InvalidMessage));
+        assert!(step.outgoing.is_some());
+    }
+}
@@ -0,0 +1,659 @@
+//! Sender Keys group encryption for server text channels.
+//!
+//! Every member owns a sender key per channel: a symmetric chain key that
+//! ratchets forward with each message, plus an Ed25519 key that signs every
+//! message so other members cannot forge traffic under someone else's chain.
+//! The sender key is handed to each member once, sealed with the pairwise
+//! secret from `derive_shared_secret` and bound to the channel, the sender
+//! and that member. After that, a message is encrypted only once, however
+//! many members the channel has.
+//!
+//! Sender key state is written to the keystore before every operation
+//! returns, so a restart never reuses a message key; without an unlocked
+//! keystore, sender keys cannot be used at all.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    Nonce,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::{
+    decode_key, decrypt_message, encrypt_message, kdf_chain, message_cipher, AssociatedData,
+    EncryptedPayload, PROTOCOL_VERSION,
+};
+use crate::commands::keystore;
+
+/// Maximum number of message keys skipped within a sender chain.
+const MAX_SKIP: u32 = 2000;
+
+/// Maximum number of skipped message keys kept per sender.
+const MAX_SKIPPED_KEYS: usize = 2000;
+
+const MESSAGE_INFO: &[u8] = b"zeusix-sender-key-message-v1";
+
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct OwnSenderKey {
+    key_id: u32,
+    chain_key: [u8; 32],
+    iteration: u32,
+    signing_key: [u8; 32],
+}
+
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct MemberSenderKey {
+    key_id: u32,
+    chain_key: [u8; 32],
+    iteration: u32,
+    verifying_key: [u8; 32],
+    skipped: Vec<(u32, [u8; 32])>,
+}
+
+/// Sender key state for one channel.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ChannelSenderKeys {
+    own: Option<OwnSenderKey>,
+    members: HashMap<String, MemberSenderKey>,
+}
+
+/// Plaintext of a sender key distribution, sealed before it leaves this device.
+#[derive(Serialize, Deserialize)]
+struct SenderKeyDistribution {
+    channel_id: String,
+    key_id: u32,
+    iteration: u32,
+    chain_key: String,
+    signing_public: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupMessage {
+    pub key_id: u32,
+    pub iteration: u32,
+    /// Base64-encoded ciphertext
+    pub ciphertext: String,
+    /// Ed25519 signature by the sender key (base64)
+    pub signature: String,
+}
+
+/// Sender key state per channel: channel_id -> keys.
+static CHANNELS: std::sync::LazyLock<Mutex<HashMap<String, ChannelSenderKeys>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Drop the cached sender keys when the keystore locks. They are reloaded
+/// from the keystore on next use.
+pub(crate) fn wipe() {
+    if let Ok(mut channels) = CHANNELS.lock() {
+        channels.clear();
+    }
+}
+
+/// Keystore id prefix of persisted sender key state.
+pub(crate) const SENDER_KEYS_PREFIX: &str = "sender_keys:";
+
+fn channel_key_id(channel_id: &str) -> String {
+    format!("{}{}", SENDER_KEYS_PREFIX, channel_id)
+}
+
+fn new_own_key() -> OwnSenderKey {
+    let mut chain_key = [0u8; 32];
+    OsRng.fill_bytes(&mut chain_key);
+
+    OwnSenderKey {
+        key_id: OsRng.next_u32(),
+        chain_key,
+        iteration: 0,
+        signing_key: SigningKey::generate(&mut OsRng).to_bytes(),
+    }
+}
+
+/// Context a sender key distribution is sealed under, so it cannot be
+/// replayed to another member or channel or passed off as someone else's.
+fn distribution_aad(channel_id: &str, sender_id: &str, recipient_id: &str) -> AssociatedData {
+    AssociatedData {
+        channel_id: channel_id.to_string(),
+        message_id: format!("sender-key:{}", recipient_id),
+        author_id: sender_id.to_string(),
+        version: PROTOCOL_VERSION,
+    }
+}
+
+/// Bytes covered by both the AEAD and the signature.
+fn message_aad(channel_id: &str, key_id: u32, iteration: u32) -> Vec<u8> {
+    let mut aad = channel_id.as_bytes().to_vec();
+    aad.extend_from_slice(&key_id.to_be_bytes());
+    aad.extend_from_slice(&iteration.to_be_bytes());
+    aad
+}
+
+impl MemberSenderKey {
+    /// Return the message key for `iteration`, advancing the chain if needed.
+    fn message_key(&mut self, iteration: u32) -> Result<[u8; 32], String> {
+        if iteration < self.iteration {
+            let idx = self
+                .skipped
+                .iter()
+                .position(|(i, _)| *i == iteration)
+                .ok_or_else(|| "Message key already used or expired".to_string())?;
+            return Ok(self.skipped.remove(idx).1);
+        }
+
+        if iteration - self.iteration > MAX_SKIP {
+            return Err(format!(
+                "Too many skipped messages ({} > {})",
+                iteration - self.iteration,
+                MAX_SKIP
+            ));
+        }
+
+        while self.iteration < iteration {
+            let (next_chain, message_key) = kdf_chain(&self.chain_key);
+            self.skipped.push((self.iteration, message_key));
+            self.chain_key = next_chain;
+            self.iteration += 1;
+        }
+
+        if self.skipped.len() > MAX_SKIPPED_KEYS {
+            let excess = self.skipped.len() - MAX_SKIPPED_KEYS;
+            self.skipped.drain(..excess);
+        }
+
+        let (next_chain, message_key) = kdf_chain(&self.chain_key);
+        self.chain_key = next_chain;
+        self.iteration += 1;
+        Ok(message_key)
+    }
+}
+
+/// Fetch a channel's sender keys from memory, falling back to the keystore.
+fn load(channel_id: &str) -> Result<ChannelSenderKeys, String> {
+    let channels = CHANNELS
+        .lock()
+        .map_err(|e| format!("Failed to lock sender keys: {}", e))?;
+    if let Some(state) = channels.get(channel_id) {
+        return Ok(state.clone());
+    }
+    drop(channels);
+
+    match keystore::find_secret(&channel_key_id(channel_id))? {
+        Some(stored) => {
+            let json = BASE64
+                .decode(&stored)
+                .map_err(|e| format!("Corrupt sender key state: {}", e))?;
+            serde_json::from_slice(&json).map_err(|e| format!("Corrupt sender key state: {}", e))
+        }
+        None => Ok(ChannelSenderKeys::default()),
+    }
+}
+
+/// Persist `state`, then make it live. Fails if the keystore is not
+/// initialized or is locked.
+fn commit(channel_id: &str, state: ChannelSenderKeys) -> Result<(), String> {
+    let json = serde_json::to_vec(&state)
+        .map_err(|e| format!("Failed to serialize sender keys: {}", e))?;
+    keystore::store_secret(&channel_key_id(channel_id), BASE64.encode(json))?;
+
+    CHANNELS
+        .lock()
+        .map_err(|e| format!("Failed to lock sender keys: {}", e))?
+        .insert(channel_id.to_string(), state);
+    Ok(())
+}
+
+/// Seal our sender key for `channel_id` to one member, creating it if needed.
+///
+/// `sender_id` is our own user id and `recipient_id` the member's.
+/// `pairwise_key_handle` is the handle of the shared secret with that member
+/// from `derive_shared_secret`. The result is relayed to the member as-is.
+#[tauri::command]
+pub fn seal_sender_key(
+    channel_id: String,
+    sender_id: String,
+    recipient_id: String,
+    pairwise_key_handle: String,
+) -> Result<EncryptedPayload, String> {
+    let aad = distribution_aad(&channel_id, &sender_id, &recipient_id);
+    let mut state = load(&channel_id)?;
+    let own = state.own.get_or_insert_with(new_own_key).clone();
+    commit(&channel_id, state)?;
+
+    let signing = SigningKey::from_bytes(&own.signing_key);
+    let distribution = SenderKeyDistribution {
+        channel_id,
+        key_id: own.key_id,
+        iteration: own.iteration,
+        chain_key: BASE64.encode(own.chain_key),
+        signing_public: BASE64.encode(signing.verifying_key().as_bytes()),
+    };
+    let json = serde_json::to_string(&distribution)
+        .map_err(|e| format!("Failed to serialize sender key: {}", e))?;
+
+    encrypt_message(json, pairwise_key_handle, Some(aad))
+}
+
+/// Open a sealed sender key from `sender_id` and remember it for the channel.
+///
+/// `recipient_id` is our own user id; the key only opens if it was sealed
+/// for this channel, by `sender_id`, to us. A key we already hold is only
+/// accepted again if its chain has moved forward.
+#[tauri::command]
+pub fn process_sender_key(
+    channel_id: String,
+    sender_id: String,
+    recipient_id: String,
+    sealed: EncryptedPayload,
+    pairwise_key_handle: String,
+) -> Result<u32, String> {
+    let json = decrypt_message(
+        sealed.ciphertext,
+        sealed.nonce,
+        pairwise_key_handle,
+        Some(distribution_aad(&channel_id, &sender_id, &recipient_id)),
+        Some(sealed.padded),
+    )?;
+    let distribution: SenderKeyDistribution =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid sender key: {}", e))?;
+
+    if distribution.channel_id != channel_id {
+        return Err("Sender key belongs to a different channel".to_string());
+    }
+
+    let verifying_key = decode_key(&distribution.signing_public, "signing key")?;
+    VerifyingKey::from_bytes(&verifying_key)
+        .map_err(|e| format!("Invalid signing key: {}", e))?;
+
+    let mut state = load(&channel_id)?;
+    if let Some(current) = state.members.get(&sender_id) {
+        if current.key_id == distribution.key_id && distribution.iteration <= current.iteration {
+            return Err("Sender key distribution is a replay".to_string());
+        }
+    }
+    state.members.insert(
+        sender_id,
+        MemberSenderKey {
+            key_id: distribution.key_id,
+            chain_key: decode_key(&distribution.chain_key, "chain key")?,
+            iteration: distribution.iteration,
+            verifying_key,
+            skipped: Vec::new(),
+        },
+    );
+    commit(&channel_id, state)?;
+
+    Ok(distribution.key_id)
+}
+
+/// Encrypt a channel message with our sender key, ratcheting the chain forward.
+#[tauri::command]
+pub fn group_encrypt(channel_id: String, plaintext: String) -> Result<GroupMessage, String> {
+    let mut state = load(&channel_id)?;
+    let own = state
+        .own
+        .as_mut()
+        .ok_or_else(|| "No sender key for this channel. Call seal_sender_key first.".to_string())?;
+
+    let (next_chain, message_key) = kdf_chain(&own.chain_key);
+    let (key_id, iteration) = (own.key_id, own.iteration);
+    own.chain_key = next_chain;
+    own.iteration += 1;
+    let signing = SigningKey::from_bytes(&own.signing_key);
+
+    let aad = message_aad(&channel_id, key_id, iteration);
+    let (cipher, nonce) = message_cipher(&message_key, MESSAGE_INFO);
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext.as_bytes(),
+                aad: &aad,
+            },
+        )
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+
+    let mut signed = aad;
+    signed.extend_from_slice(&ciphertext);
+    let signature = signing.sign(&signed);
+
+    commit(&channel_id, state)?;
+
+    Ok(GroupMessage {
+        key_id,
+        iteration,
+        ciphertext: BASE64.encode(ciphertext),
+        signature: BASE64.encode(signature.to_bytes()),
+    })
+}
+
+/// Decrypt a channel message from `sender_id` after checking its signature.
+#[tauri::command]
+pub fn group_decrypt(
+    channel_id: String,
+    sender_id: String,
+    message: GroupMessage,
+) -> Result<String, String> {
+    let mut state = load(&channel_id)?;
+    let member = state
+        .members
+        .get_mut(&sender_id)
+        .ok_or_else(|| format!("No sender key from '{}' for this channel", sender_id))?;
+
+    if member.key_id != message.key_id {
+        return Err("Message was sent under an unknown sender key".to_string());
+    }
+
+    let ciphertext = BASE64
+        .decode(&message.ciphertext)
+        .map_err(|e| format!("Invalid base64 ciphertext: {}", e))?;
+    let sig_bytes = BASE64
+        .decode(&message.signature)
+        .map_err(|e| format!("Invalid base64 signature: {}", e))?;
+    let signature =
+        Signature::from_slice(&sig_bytes).map_err(|e| format!("Invalid signature: {}", e))?;
+
+    let aad = message_aad(&channel_id, message.key_id, message.iteration);
+    let mut signed = aad.clone();
+    signed.extend_from_slice(&ciphertext);
+    VerifyingKey::from_bytes(&member.verifying_key)
+        .map_err(|e| format!("Invalid signing key: {}", e))?
+        .verify(&signed, &signature)
+        .map_err(|_| "Message signature is invalid".to_string())?;
+
+    let message_key = member.message_key(message.iteration)?;
+    let (cipher, nonce) = message_cipher(&message_key, MESSAGE_INFO);
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: &aad,
+            },
+        )
+        .map_err(|e| format!("Decryption failed: {}", e))?;
+
+    commit(&channel_id, state)?;
+
+    String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8 in decrypted message: {}", e))
+}
+
+/// Forget a removed member's sender key and rotate our own.
+///
+/// The removed member still knows our old chain, so a fresh sender key is
+/// generated right away. The caller must seal the new key to every remaining
+/// member with `seal_sender_key`. Returns the new key id.
+#[tauri::command]
+pub fn remove_group_member(channel_id: String, member_id: String) -> Result<u32, String> {
+    let mut state = load(&channel_id)?;
+    state.members.remove(&member_id);
+
+    let own = new_own_key();
+    let key_id = own.key_id;
+    state.own = Some(own);
+    commit(&channel_id, state)?;
+
+    Ok(key_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::{derive_shared_secret, generate_keypair, KeyPair};
+
+    const CHANNEL: &str = "general";
+
+    /// Run `f` as a member whose sender key state is `state`. Every member
+    /// shares this process's channel map, so states are swapped in and out.
+    fn as_member<T>(state: &mut ChannelSenderKeys, f: impl FnOnce() -> T) -> T {
+        CHANNELS
+            .lock()
+            .unwrap()
+            .insert(CHANNEL.to_string(), std::mem::take(state));
+        let out = f();
+        *state = CHANNELS.lock().unwrap().remove(CHANNEL).unwrap();
+        out
+    }
+
+    struct Member {
+        id: &'static str,
+        keys: KeyPair,
+        state: ChannelSenderKeys,
+    }
+
+    fn member(id: &'static str) -> Member {
+        Member {
+            id,
+            keys: generate_keypair().unwrap(),
+            state: ChannelSenderKeys::default(),
+        }
+    }
+
+    fn pairwise(from: &Member, to: &Member) -> String {
+        derive_shared_secret(from.keys.handle.clone(), to.keys.x25519_public.clone()).unwrap()
+    }
+
+    /// Seal `from`'s sender key to `to` and have `to` process it.
+    fn distribute(from: &mut Member, to: &mut Member) {
+        let pairwise_from = pairwise(from, to);
+        let (from_id, to_id) = (from.id, to.id);
+        let sealed = as_member(&mut from.state, || {
+            seal_sender_key(CHANNEL.into(), from_id.into(), to_id.into(), pairwise_from).unwrap()
+        });
+        let pairwise_to = pairwise(to, from);
+        as_member(&mut to.state, || {
+            process_sender_key(
+                CHANNEL.into(),
+                from_id.into(),
+                to_id.into(),
+                sealed,
+                pairwise_to,
+            )
+            .unwrap()
+        });
+    }
+
+    fn encrypt(from: &mut Member, text: &str) -> GroupMessage {
+        as_member(&mut from.state, || {
+            group_encrypt(CHANNEL.into(), text.into()).unwrap()
+        })
+    }
+
+    fn decrypt(to: &mut Member, sender_id: &str, message: GroupMessage) -> Result<String, String> {
+        as_member(&mut to.state, || {
+            group_decrypt(CHANNEL.into(), sender_id.into(), message)
+        })
+    }
+
+    fn copy(message: &GroupMessage) -> GroupMessage {
+        GroupMessage {
+            key_id: message.key_id,
+            iteration: message.iteration,
+            ciphertext: message.ciphertext.clone(),
+            signature: message.signature.clone(),
+        }
+    }
+
+    #[test]
+    fn two_members_exchange_messages() {
+        let _keystore = keystore::test_keystore();
+        let mut alice = member("alice");
+        let mut bob = member("bob");
+        distribute(&mut alice, &mut bob);
+        distribute(&mut bob, &mut alice);
+
+        for text in ["one", "two"] {
+            let message = encrypt(&mut alice, text);
+            assert_eq!(decrypt(&mut bob, "alice", message).unwrap(), text);
+        }
+        let reply = encrypt(&mut bob, "three");
+        assert_eq!(decrypt(&mut alice, "bob", reply).unwrap(), "three");
+    }
+
+    #[test]
+    fn distribution_is_bound_to_channel_sender_and_recipient() {
+        let _keystore = keystore::test_keystore();
+        let mut alice = member("alice");
+        let mut bob = member("bob");
+        let pairwise_alice = pairwise(&alice, &bob);
+        let pairwise_bob = pairwise(&bob, &alice);
+
+        let sealed = as_member(&mut alice.state, || {
+            seal_sender_key(CHANNEL.into(), "alice".into(), "bob".into(), pairwise_alice).unwrap()
+        });
+        let reseal = || EncryptedPayload {
+            ciphertext: sealed.ciphertext.clone(),
+            nonce: sealed.nonce.clone(),
+            padded: sealed.padded,
+        };
+
+        as_member(&mut bob.state, || {
+            for (channel, sender, recipient) in [
+                ("random", "alice", "bob"),
+                (CHANNEL, "carol", "bob"),
+                (CHANNEL, "alice", "carol"),
+            ] {
+                assert!(process_sender_key(
+                    channel.into(),
+                    sender.into(),
+                    recipient.into(),
+                    reseal(),
+                    pairwise_bob.clone(),
+                )
+                .is_err());
+            }
+            process_sender_key(
+                CHANNEL.into(),
+                "alice".into(),
+                "bob".into(),
+                reseal(),
+                pairwise_bob.clone(),
+            )
+            .unwrap();
+        });
+    }
+
+    #[test]
+    fn reject_replayed_distribution() {
+        let _keystore = keystore::test_keystore();
+        let mut alice = member("alice");
+        let mut bob = member("bob");
+        let pairwise_alice = pairwise(&alice, &bob);
+        let pairwise_bob = pairwise(&bob, &alice);
+        let seal = |state: &mut ChannelSenderKeys| {
+            as_member(state, || {
+                seal_sender_key(
+                    CHANNEL.into(),
+                    "alice".into(),
+                    "bob".into(),
+                    pairwise_alice.clone(),
+                )
+                .unwrap()
+            })
+        };
+        let process = |state: &mut ChannelSenderKeys, sealed: &EncryptedPayload| {
+            let sealed = EncryptedPayload {
+                ciphertext: sealed.ciphertext.clone(),
+                nonce: sealed.nonce.clone(),
+                padded: sealed.padded,
+            };
+            as_member(state, || {
+                process_sender_key(
+                    CHANNEL.into(),
+                    "alice".into(),
+                    "bob".into(),
+                    sealed,
+                    pairwise_bob.clone(),
+                )
+            })
+        };
+
+        let first = seal(&mut alice.state);
+        process(&mut bob.state, &first).unwrap();
+        let message = encrypt(&mut alice, "one");
+        assert_eq!(decrypt(&mut bob, "alice", message).unwrap(), "one");
+        assert!(process(&mut bob.state, &first).is_err());
+
+        // The same key, further along its chain
+        encrypt(&mut alice, "two");
+        let later = seal(&mut alice.state);
+        process(&mut bob.state, &later).unwrap();
+        assert!(process(&mut bob.state, &later).is_err());
+        assert!(process(&mut bob.state, &first).is_err());
+
+        let message = encrypt(&mut alice, "three");
+        assert_eq!(decrypt(&mut bob, "alice", message).unwrap(), "three");
+    }
+
+    #[test]
+    fn reject_forged_signature() {
+        let _keystore = keystore::test_keystore();
+        let mut alice = member("alice");
+        let mut bob = member("bob");
+        let mut mallory = member("mallory");
+        distribute(&mut alice, &mut bob);
+        distribute(&mut mallory, &mut bob);
+
+        // Mallory's own message, passed off as Alice's
+        let mut forged = encrypt(&mut mallory, "from alice, honest");
+        let genuine = encrypt(&mut alice, "hello");
+        forged.key_id = genuine.key_id;
+        assert_eq!(
+            decrypt(&mut bob, "alice", forged).unwrap_err(),
+            "Message signature is invalid"
+        );
+
+        // Alice's signature over a different iteration
+        let mut shifted = copy(&genuine);
+        shifted.iteration += 1;
+        assert!(decrypt(&mut bob, "alice", shifted).is_err());
+
+        assert_eq!(decrypt(&mut bob, "alice", genuine).unwrap(), "hello");
+    }
+
+    #[test]
+    fn removed_member_cannot_read_after_rotation() {
+        let _keystore = keystore::test_keystore();
+        let mut alice = member("alice");
+        let mut bob = member("bob");
+        let mut carol = member("carol");
+        distribute(&mut alice, &mut bob);
+        distribute(&mut alice, &mut carol);
+
+        let before = encrypt(&mut alice, "before");
+        assert_eq!(
+            decrypt(&mut carol, "alice", copy(&before)).unwrap(),
+            "before"
+        );
+        assert_eq!(decrypt(&mut bob, "alice", before).unwrap(), "before");
+
+        as_member(&mut alice.state, || {
+            remove_group_member(CHANNEL.into(), "carol".into()).unwrap()
+        });
+        distribute(&mut alice, &mut bob);
+
+        let after = encrypt(&mut alice, "after");
+        assert_eq!(
+            decrypt(&mut carol, "alice", copy(&after)).unwrap_err(),
+            "Message was sent under an unknown sender key"
+        );
+        assert_eq!(decrypt(&mut bob, "alice", after).unwrap(), "after");
+    }
+
+    #[test]
+    fn sender_keys_need_the_keystore() {
+        let _keystore = keystore::test_keystore();
+        let alice = member("alice");
+        let bob = member("bob");
+        let pairwise_alice = pairwise(&alice, &bob);
+
+        keystore::lock_keystore().unwrap();
+        assert!(
+            seal_sender_key(CHANNEL.into(), "alice".into(), "bob".into(), pairwise_alice,).is_err()
+        );
+        assert!(CHANNELS.lock().unwrap().get(CHANNEL).is_none());
+    }
+}
@@ -0,0 +1,213 @@
+//! Trust-on-first-use store for contacts' identity keys.
+//!
+//! The first identity keys seen for a user id are pinned in the local
+//! database. Later keys are checked against the pin: a mismatch is reported
+//! as `changed` and announced with an `identity-key-changed` event, and the
+//! new keys are only trusted once the user accepts them explicitly.
+
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::Emitter;
+
+use super::safety::IdentityPublicKeys;
+use crate::commands::storage;
+
+/// Event emitted when a contact presents keys different from the pinned ones.
+pub const IDENTITY_KEY_CHANGED_EVENT: &str = "identity-key-changed";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustStatus {
+    /// First time these keys were seen for the user; they are now pinned
+    New,
+    /// Keys match the pinned ones
+    Trusted,
+    /// Keys differ from the pinned ones and must not be used until accepted
+    Changed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityKeyChanged {
+    pub user_id: String,
+    pub previous_x25519_public: String,
+    pub previous_ed25519_public: String,
+    pub new_x25519_public: String,
+    pub new_ed25519_public: String,
+}
+
+/// Check `identity` against the pinned keys for its user id, pinning it if
+/// none are known yet.
+///
+/// Call this whenever keys are fetched for a contact, before they are used
+/// for X3DH or signature checks.
+#[tauri::command]
+pub fn check_identity_key(
+    app: tauri::AppHandle,
+    db_path: String,
+    passphrase: String,
+    identity: IdentityPublicKeys,
+) -> Result<TrustStatus, String> {
+    identity.validate()?;
+    let conn = storage::open_connection(&db_path, &passphrase)?;
+
+    check_pin(&conn, identity, |changed| {
+        app.emit(IDENTITY_KEY_CHANGED_EVENT, changed).ok();
+    })
+}
+
+/// Compare `identity` with its pin, pinning it if there is none; a changed
+/// key is passed to `on_change`.
+fn check_pin(
+    conn: &Connection,
+    identity: IdentityPublicKeys,
+    on_change: impl FnOnce(IdentityKeyChanged),
+) -> Result<TrustStatus, String> {
+    let pinned = conn
+        .query_row(
+            "SELECT x25519_public, ed25519_public FROM identity_keys WHERE user_id = ?1",
+            params![identity.user_id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+        )
+        .optional()
+        .map_err(|e| format!("Failed to query identity keys: {}", e))?;
+
+    match pinned {
+        None => {
+            let now = Utc::now().to_rfc3339();
+            conn.execute(
+                "INSERT INTO identity_keys
+                     (user_id, x25519_public, ed25519_public, first_seen_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?4)",
+                params![
+                    identity.user_id,
+                    identity.x25519_public,
+                    identity.ed25519_public,
+                    now
+                ],
+            )
+            .map_err(|e| format!("Failed to pin identity keys: {}", e))?;
+            Ok(TrustStatus::New)
+        }
+        Some((x25519_public, ed25519_public))
+            if identity.same_keys(&x25519_public, &ed25519_public) =>
+        {
+            Ok(TrustStatus::Trusted)
+        }
+        Some((previous_x25519_public, previous_ed25519_public)) => {
+            on_change(IdentityKeyChanged {
+                user_id: identity.user_id,
+                previous_x25519_public,
+                previous_ed25519_public,
+                new_x25519_public: identity.x25519_public,
+                new_ed25519_public: identity.ed25519_public,
+            });
+            Ok(TrustStatus::Changed)
+        }
+    }
+}
+
+/// Trust a contact's new identity keys after a reported change.
+///
+/// Replaces the pinned keys and clears any earlier verification, since the
+/// safety number has changed along with the keys.
+#[tauri::command]
+pub fn accept_identity_key(
+    db_path: String,
+    passphrase: String,
+    identity: IdentityPublicKeys,
+) -> Result<(), String> {
+    identity.validate()?;
+    let mut conn = storage::open_connection(&db_path, &passphrase)?;
+    let now = Utc::now().to_rfc3339();
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("Failed to start transaction: {}", e))?;
+
+    tx.execute(
+        "INSERT INTO identity_keys
+             (user_id, x25519_public, ed25519_public, first_seen_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?4)
+         ON CONFLICT (user_id) DO UPDATE SET
+             x25519_public = excluded.x25519_public,
+             ed25519_public = excluded.ed25519_public,
+             updated_at = excluded.updated_at",
+        params![
+            identity.user_id,
+            identity.x25519_public,
+            identity.ed25519_public,
+            now
+        ],
+    )
+    .map_err(|e| format!("Failed to pin identity keys: {}", e))?;
+
+    tx.execute(
+        "UPDATE contact_verification
+         SET x25519_public = ?2, ed25519_public = ?3, verified = 0, verified_at = NULL,
+             updated_at = ?4
+         WHERE contact_id = ?1",
+        params![
+            identity.user_id,
+            identity.x25519_public,
+            identity.ed25519_public,
+            now
+        ],
+    )
+    .map_err(|e| format!("Failed to reset verification status: {}", e))?;
+
+    tx.commit()
+        .map_err(|e| format!("Failed to commit transaction: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::safety::{get_contact_verification, set_contact_verified};
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+    fn identity(x25519: u8, ed25519: u8) -> IdentityPublicKeys {
+        IdentityPublicKeys {
+            user_id: "bob".to_string(),
+            x25519_public: BASE64.encode([x25519; 32]),
+            ed25519_public: BASE64.encode([ed25519; 32]),
+        }
+    }
+
+    fn check(
+        db_path: &str,
+        identity: IdentityPublicKeys,
+    ) -> (TrustStatus, Option<IdentityKeyChanged>) {
+        let conn = storage::open_connection(db_path, "").unwrap();
+        let mut event = None;
+        let status = check_pin(&conn, identity, |changed| event = Some(changed)).unwrap();
+        (status, event)
+    }
+
+    #[test]
+    fn trust_on_first_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = storage::test_db(&dir);
+        let original = identity(3, 4);
+        let replaced = identity(3, 5);
+
+        assert_eq!(check(&db_path, original.clone()).0, TrustStatus::New);
+        assert_eq!(check(&db_path, original.clone()).0, TrustStatus::Trusted);
+        set_contact_verified(db_path.clone(), String::new(), original.clone(), true).unwrap();
+
+        let (status, event) = check(&db_path, replaced.clone());
+        assert_eq!(status, TrustStatus::Changed);
+        let event = event.unwrap();
+        assert_eq!(event.previous_ed25519_public, original.ed25519_public);
+        assert_eq!(event.new_ed25519_public, replaced.ed25519_public);
+        // Still changed until the user accepts the new keys
+        assert_eq!(check(&db_path, replaced.clone()).0, TrustStatus::Changed);
+
+        accept_identity_key(db_path.clone(), String::new(), replaced.clone()).unwrap();
+        assert_eq!(check(&db_path, replaced.clone()).0, TrustStatus::Trusted);
+        assert_eq!(check(&db_path, original).0, TrustStatus::Changed);
+
+        let status = get_contact_verification(db_path, String::new(), replaced).unwrap();
+        assert!(!status.verified && !status.key_changed);
+    }
+}
@@ -0,0 +1,712 @@
+//! X3DH (Extended Triple Diffie-Hellman) prekey bundles and asynchronous
+//! session setup.
+//!
+//! The X25519 half of an identity keypair takes part in the Diffie-Hellman
+//! steps, while the Ed25519 half signs the signed prekey so the initiator can
+//! tell that the bundle really belongs to the identity it was fetched for.
+//!
+//! Bundles that also carry a signed ML-KEM-768 prekey offer the hybrid
+//! suite: the initiator encapsulates to it and the KEM shared secret is fed
+//! into the KDF along with the Diffie-Hellman outputs, so the session key
+//! stays secret unless both X25519 and ML-KEM are broken. The suite is
+//! negotiated through the `version` field; peers that predate it send and
+//! expect version 1 and fall back to X25519 alone.
+//!
+//! The version is covered by the signed prekey signature, so a bundle cannot
+//! be downgraded to X25519 by stripping its PQ prekey. Initiators that insist
+//! on the hybrid suite pass `require_pq`.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::Utc;
+use ed25519_dalek::Signer;
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use super::handles::{self, SecretKey};
+use super::{decode_key, pq, verify_signature};
+
+/// X25519-only key agreement.
+pub const VERSION_X25519: u32 = 1;
+
+/// Hybrid X25519 + ML-KEM-768 key agreement.
+pub const VERSION_HYBRID: u32 = 2;
+
+/// Domain separation prefix for signed prekey signatures.
+const SIGNED_PREKEY_CONTEXT: &[u8] = b"zeusix-x3dh-spk-v1";
+
+/// Domain separation prefix for post-quantum prekey signatures.
+const PQ_PREKEY_CONTEXT: &[u8] = b"zeusix-x3dh-pqpk-v1";
+
+/// HKDF info string for the X3DH shared secret.
+const X3DH_INFO: &[u8] = b"zeusix-x3dh-v1";
+
+/// HKDF info string for the hybrid shared secret.
+const HYBRID_INFO: &[u8] = b"zeusix-x3dh-hybrid-v1";
+
+/// Upper bound on one-time prekeys generated per call.
+const MAX_ONE_TIME_PREKEYS: u32 = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedPreKey {
+    pub id: u32,
+    /// X25519 public key (base64)
+    pub public: String,
+    /// Key registry handle for the private key
+    pub handle: String,
+    /// Ed25519 signature over the public key and `version` by the identity
+    /// key (base64)
+    pub signature: String,
+    /// Key agreement version of the bundles this prekey can be published in
+    #[serde(default = "default_version")]
+    pub version: u32,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OneTimePreKey {
+    pub id: u32,
+    /// X25519 public key (base64)
+    pub public: String,
+    /// Key registry handle for the private key
+    pub handle: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PublicPreKey {
+    pub id: u32,
+    /// X25519 public key (base64)
+    pub public: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedPqPreKey {
+    pub id: u32,
+    /// ML-KEM-768 encapsulation key (base64)
+    pub public: String,
+    /// Key registry handle for the decapsulation key
+    pub handle: String,
+    /// Ed25519 signature over the public key by the identity key (base64)
+    pub signature: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PublicPqPreKey {
+    pub id: u32,
+    /// ML-KEM-768 encapsulation key (base64)
+    pub public: String,
+    /// Ed25519 signature over the public key by the identity key (base64)
+    pub signature: String,
+}
+
+fn default_version() -> u32 {
+    VERSION_X25519
+}
+
+/// The public half of a user's prekeys, as uploaded to and served by the server.
+///
+/// When fetched for session setup the server hands out at most one one-time
+/// prekey and deletes it, so each one is used for a single session only.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PreKeyBundle {
+    /// X25519 identity public key (base64)
+    pub identity_x25519: String,
+    /// Ed25519 identity public key (base64)
+    pub identity_ed25519: String,
+    pub signed_prekey_id: u32,
+    /// X25519 signed prekey (base64)
+    pub signed_prekey: String,
+    /// Ed25519 signature over the signed prekey and `version` (base64)
+    pub signed_prekey_signature: String,
+    pub one_time_prekeys: Vec<PublicPreKey>,
+    /// Key agreement version the owner offers, signed with the prekey
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// Signed ML-KEM-768 prekey, present from version 2
+    #[serde(default)]
+    pub pq_prekey: Option<PublicPqPreKey>,
+}
+
+/// Sent along with the first message so the responder can derive the same secret.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct X3dhInitialMessage {
+    /// Initiator's X25519 identity public key (base64)
+    pub identity_x25519: String,
+    /// Initiator's ephemeral X25519 public key (base64)
+    pub ephemeral: String,
+    pub signed_prekey_id: u32,
+    pub one_time_prekey_id: Option<u32>,
+    /// Key agreement version the initiator chose
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub pq_prekey_id: Option<u32>,
+    /// ML-KEM-768 ciphertext for the responder's PQ prekey (base64)
+    #[serde(default)]
+    pub pq_ciphertext: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct X3dhOutput {
+    /// Key registry handle for the 32-byte initial shared secret
+    pub shared_secret_handle: String,
+    /// Initiator identity key || responder identity key (base64), to be bound
+    /// as associated data of the first message
+    pub associated_data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct X3dhInitiation {
+    #[serde(flatten)]
+    pub output: X3dhOutput,
+    pub initial_message: X3dhInitialMessage,
+}
+
+/// Version 1 prekeys sign the bare public key, as before versions existed;
+/// later versions append the version, so a signature for one does not
+/// verify for another.
+fn signed_prekey_message(public: &[u8; 32], version: u32) -> Vec<u8> {
+    let mut msg = SIGNED_PREKEY_CONTEXT.to_vec();
+    msg.extend_from_slice(public);
+    if version != VERSION_X25519 {
+        msg.extend_from_slice(&version.to_be_bytes());
+    }
+    msg
+}
+
+fn pq_prekey_message(public: &[u8]) -> Vec<u8> {
+    let mut msg = PQ_PREKEY_CONTEXT.to_vec();
+    msg.extend_from_slice(public);
+    msg
+}
+
+/// Run one X25519 step, rejecting low-order points that would yield an all-zero secret.
+fn dh(secret: &StaticSecret, public: &PublicKey) -> Result<Zeroizing<[u8; 32]>, String> {
+    let shared = secret.diffie_hellman(public);
+    if !shared.was_contributory() {
+        return Err("Diffie-Hellman produced a non-contributory result".to_string());
+    }
+    Ok(Zeroizing::new(shared.to_bytes()))
+}
+
+/// HKDF over `0xFF * 32 || DH1 || DH2 || DH3 [|| DH4] [|| SS_pq]` with a
+/// zero salt. The hybrid suite uses its own info string.
+fn kdf(
+    dh_outputs: &[Zeroizing<[u8; 32]>],
+    pq_secret: Option<&[u8; 32]>,
+) -> Result<Zeroizing<[u8; 32]>, String> {
+    let mut ikm = Zeroizing::new(vec![0xFFu8; 32]);
+    for out in dh_outputs {
+        ikm.extend_from_slice(&out[..]);
+    }
+    if let Some(secret) = pq_secret {
+        ikm.extend_from_slice(secret);
+    }
+
+    let info = if pq_secret.is_some() {
+        HYBRID_INFO
+    } else {
+        X3DH_INFO
+    };
+
+    let hk = Hkdf::<Sha256>::new(Some(&[0u8; 32]), &ikm);
+    let mut sk = Zeroizing::new([0u8; 32]);
+    hk.expand(info, sk.as_mut())
+        .map_err(|e| format!("HKDF expansion failed: {}", e))?;
+    Ok(sk)
+}
+
+fn output(
+    sk: Zeroizing<[u8; 32]>,
+    initiator: &PublicKey,
+    responder: &PublicKey,
+) -> Result<X3dhOutput, String> {
+    let mut ad = Vec::with_capacity(64);
+    ad.extend_from_slice(initiator.as_bytes());
+    ad.extend_from_slice(responder.as_bytes());
+
+    Ok(X3dhOutput {
+        shared_secret_handle: handles::insert(SecretKey::Symmetric(sk))?,
+        associated_data: BASE64.encode(ad),
+    })
+}
+
+/// Check the prekey signatures of a bundle against its Ed25519 identity key.
+fn verify_bundle(bundle: &PreKeyBundle) -> Result<(), String> {
+    let spk = decode_key(&bundle.signed_prekey, "signed prekey")?;
+
+    verify_signature(
+        &bundle.identity_ed25519,
+        &signed_prekey_message(&spk, bundle.version),
+        &bundle.signed_prekey_signature,
+    )
+    .map_err(|e| format!("Invalid signed prekey: {}", e))?;
+
+    match (bundle.version, &bundle.pq_prekey) {
+        (VERSION_X25519, None) | (VERSION_HYBRID, Some(_)) => {}
+        (VERSION_X25519, Some(_)) => {
+            return Err("Bundle carries a PQ prekey but is signed for X25519 only".to_string())
+        }
+        (VERSION_HYBRID, None) => {
+            return Err("Bundle offers the hybrid suite without a PQ prekey".to_string())
+        }
+        (version, _) => return Err(format!("Unsupported key agreement version {}", version)),
+    }
+
+    if let Some(pq_prekey) = &bundle.pq_prekey {
+        let public = BASE64
+            .decode(&pq_prekey.public)
+            .map_err(|e| format!("Invalid base64 PQ prekey: {}", e))?;
+        verify_signature(
+            &bundle.identity_ed25519,
+            &pq_prekey_message(&public),
+            &pq_prekey.signature,
+        )
+        .map_err(|e| format!("Invalid PQ prekey: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Generate a new signed prekey, signed with the Ed25519 key of the identity
+/// handle from `generate_keypair`.
+///
+/// `version` is the key agreement version of the bundle it will be published
+/// in: `VERSION_HYBRID` when the bundle carries a PQ prekey, otherwise
+/// `VERSION_X25519` (the default). It is signed along with the key.
+///
+/// The private key stays in the key registry; persist it with `persist_key`
+/// so it is still available when the initiator's first message arrives.
+#[tauri::command]
+pub fn generate_signed_prekey(
+    identity_handle: String,
+    prekey_id: u32,
+    version: Option<u32>,
+) -> Result<SignedPreKey, String> {
+    let version = version.unwrap_or(VERSION_X25519);
+    if version != VERSION_X25519 && version != VERSION_HYBRID {
+        return Err(format!("Unsupported key agreement version {}", version));
+    }
+    let signing = handles::ed25519(&identity_handle)?;
+
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    let signature = signing.sign(&signed_prekey_message(public.as_bytes(), version));
+
+    Ok(SignedPreKey {
+        id: prekey_id,
+        public: BASE64.encode(public.as_bytes()),
+        handle: handles::insert(SecretKey::X25519(secret))?,
+        signature: BASE64.encode(signature.to_bytes()),
+        version,
+        created_at: Utc::now().to_rfc3339(),
+    })
+}
+
+/// Generate a new ML-KEM-768 prekey, signed with the Ed25519 key of the
+/// identity handle. Publishing it in the bundle enables the hybrid suite.
+#[tauri::command]
+pub fn generate_pq_prekey(
+    identity_handle: String,
+    prekey_id: u32,
+) -> Result<SignedPqPreKey, String> {
+    let signing = handles::ed25519(&identity_handle)?;
+
+    let (secret, public) = pq::generate();
+    let signature = signing.sign(&pq_prekey_message(&public));
+
+    Ok(SignedPqPreKey {
+        id: prekey_id,
+        public: BASE64.encode(&public),
+        handle: handles::insert(SecretKey::MlKem768(secret))?,
+        signature: BASE64.encode(signature.to_bytes()),
+        created_at: Utc::now().to_rfc3339(),
+    })
+}
+
+/// Generate a batch of one-time prekeys with consecutive ids starting at `start_id`.
+#[tauri::command]
+pub fn generate_one_time_prekeys(start_id: u32, count: u32) -> Result<Vec<OneTimePreKey>, String> {
+    if count == 0 || count > MAX_ONE_TIME_PREKEYS {
+        return Err(format!(
+            "Count must be between 1 and {}, got {}",
+            MAX_ONE_TIME_PREKEYS, count
+        ));
+    }
+
+    (0..count)
+        .map(|i| {
+            let id = start_id
+                .checked_add(i)
+                .ok_or_else(|| "Prekey id overflow".to_string())?;
+            let secret = StaticSecret::random_from_rng(OsRng);
+            let public = PublicKey::from(&secret);
+            Ok(OneTimePreKey {
+                id,
+                public: BASE64.encode(public.as_bytes()),
+                handle: handles::insert(SecretKey::X25519(secret))?,
+            })
+        })
+        .collect()
+}
+
+/// Assemble the publishable prekey bundle from the identity public keys,
+/// the current signed prekey and a set of one-time prekeys, plus an optional
+/// PQ prekey to offer the hybrid suite.
+///
+/// Only public material ends up in the bundle. The signatures are checked so
+/// a mismatched identity/prekey pair is caught before it is uploaded. The
+/// bundle takes the version the signed prekey was generated for, which
+/// must match whether a PQ prekey is given.
+#[tauri::command]
+pub fn create_prekey_bundle(
+    identity_x25519_public: String,
+    identity_ed25519_public: String,
+    signed_prekey: SignedPreKey,
+    one_time_prekeys: Vec<OneTimePreKey>,
+    pq_prekey: Option<SignedPqPreKey>,
+) -> Result<PreKeyBundle, String> {
+    decode_key(&identity_x25519_public, "X25519 identity key")?;
+
+    let bundle = PreKeyBundle {
+        identity_x25519: identity_x25519_public,
+        identity_ed25519: identity_ed25519_public,
+        signed_prekey_id: signed_prekey.id,
+        signed_prekey: signed_prekey.public,
+        signed_prekey_signature: signed_prekey.signature,
+        one_time_prekeys: one_time_prekeys
+            .into_iter()
+            .map(|k| PublicPreKey {
+                id: k.id,
+                public: k.public,
+            })
+            .collect(),
+        version: signed_prekey.version,
+        pq_prekey: pq_prekey.map(|k| PublicPqPreKey {
+            id: k.id,
+            public: k.public,
+            signature: k.signature,
+        }),
+    };
+
+    verify_bundle(&bundle)?;
+
+    Ok(bundle)
+}
+
+/// Start a session with someone who may be offline, using their prekey bundle.
+///
+/// `identity_handle` is the initiator's own identity handle.
+/// The first one-time prekey in the bundle is used if present, and the
+/// hybrid suite whenever the bundle offers it. With `require_pq`, bundles
+/// that only offer X25519 are refused.
+#[tauri::command]
+pub fn x3dh_initiate(
+    identity_handle: String,
+    bundle: PreKeyBundle,
+    require_pq: bool,
+) -> Result<X3dhInitiation, String> {
+    verify_bundle(&bundle)?;
+    if require_pq && bundle.version < VERSION_HYBRID {
+        return Err("Bundle does not offer the hybrid suite".to_string());
+    }
+
+    let identity = handles::x25519(&identity_handle)?;
+    let identity_public = PublicKey::from(&identity);
+
+    let their_identity = PublicKey::from(decode_key(
+        &bundle.identity_x25519,
+        "X25519 identity key",
+    )?);
+    let their_spk = PublicKey::from(decode_key(&bundle.signed_prekey, "signed prekey")?);
+
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+
+    let mut dh_outputs = vec![
+        dh(&identity, &their_spk)?,
+        dh(&ephemeral, &their_identity)?,
+        dh(&ephemeral, &their_spk)?,
+    ];
+
+    let one_time = bundle.one_time_prekeys.first();
+    if let Some(opk) = one_time {
+        let their_opk = PublicKey::from(decode_key(&opk.public, "one-time prekey")?);
+        dh_outputs.push(dh(&ephemeral, &their_opk)?);
+    }
+
+    let pq = match &bundle.pq_prekey {
+        Some(pq_prekey) if bundle.version == VERSION_HYBRID => {
+            let public = BASE64
+                .decode(&pq_prekey.public)
+                .map_err(|e| format!("Invalid base64 PQ prekey: {}", e))?;
+            let (ciphertext, secret) = pq::encapsulate(&public)?;
+            Some((pq_prekey.id, ciphertext, secret))
+        }
+        _ => None,
+    };
+
+    let sk = kdf(&dh_outputs, pq.as_ref().map(|(_, _, secret)| &**secret))?;
+
+    Ok(X3dhInitiation {
+        output: output(sk, &identity_public, &their_identity)?,
+        initial_message: X3dhInitialMessage {
+            identity_x25519: BASE64.encode(identity_public.as_bytes()),
+            ephemeral: BASE64.encode(ephemeral_public.as_bytes()),
+            signed_prekey_id: bundle.signed_prekey_id,
+            one_time_prekey_id: one_time.map(|k| k.id),
+            version: if pq.is_some() {
+                VERSION_HYBRID
+            } else {
+                VERSION_X25519
+            },
+            pq_prekey_id: pq.as_ref().map(|(id, _, _)| *id),
+            pq_ciphertext: pq
+                .as_ref()
+                .map(|(_, ciphertext, _)| BASE64.encode(ciphertext)),
+        },
+    })
+}
+
+/// Derive the initiator's shared secret on the responder side.
+///
+/// The caller looks up the prekey handles for `signed_prekey_id`,
+/// `one_time_prekey_id` and, for hybrid messages, `pq_prekey_id` from the
+/// initial message. The one-time prekey must be released and deleted
+/// afterwards.
+#[tauri::command]
+pub fn x3dh_respond(
+    identity_handle: String,
+    signed_prekey_handle: String,
+    one_time_prekey_handle: Option<String>,
+    initial_message: X3dhInitialMessage,
+    pq_prekey_handle: Option<String>,
+) -> Result<X3dhOutput, String> {
+    if initial_message.one_time_prekey_id.is_some() != one_time_prekey_handle.is_some() {
+        return Err("One-time prekey does not match the initial message".to_string());
+    }
+
+    let identity = handles::x25519(&identity_handle)?;
+    let identity_public = PublicKey::from(&identity);
+    let spk = handles::x25519(&signed_prekey_handle)?;
+
+    let their_identity = PublicKey::from(decode_key(
+        &initial_message.identity_x25519,
+        "X25519 identity key",
+    )?);
+    let their_ephemeral = PublicKey::from(decode_key(
+        &initial_message.ephemeral,
+        "ephemeral key",
+    )?);
+
+    let mut dh_outputs = vec![
+        dh(&spk, &their_identity)?,
+        dh(&identity, &their_ephemeral)?,
+        dh(&spk, &their_ephemeral)?,
+    ];
+
+    if let Some(opk_handle) = one_time_prekey_handle {
+        let opk = handles::x25519(&opk_handle)?;
+        dh_outputs.push(dh(&opk, &their_ephemeral)?);
+    }
+
+    let pq_secret = match initial_message.version {
+        VERSION_X25519 => None,
+        VERSION_HYBRID => {
+            let ciphertext = initial_message
+                .pq_ciphertext
+                .as_deref()
+                .ok_or_else(|| "Hybrid initial message is missing the PQ ciphertext".to_string())?;
+            let ciphertext = BASE64
+                .decode(ciphertext)
+                .map_err(|e| format!("Invalid base64 PQ ciphertext: {}", e))?;
+            let pq_handle = pq_prekey_handle
+                .ok_or_else(|| "PQ prekey handle is required for a hybrid message".to_string())?;
+            Some(pq::decapsulate(&handles::ml_kem(&pq_handle)?, &ciphertext)?)
+        }
+        version => return Err(format!("Unsupported key agreement version {}", version)),
+    };
+
+    let sk = kdf(&dh_outputs, pq_secret.as_deref())?;
+
+    output(sk, &their_identity, &identity_public)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::crypto::{generate_keypair, KeyPair};
+    use crate::commands::keystore;
+
+    struct Responder {
+        identity: KeyPair,
+        signed_prekey: String,
+        one_time_prekey: String,
+        pq_prekey: String,
+        bundle: PreKeyBundle,
+    }
+
+    fn responder(hybrid: bool) -> Responder {
+        let identity = generate_keypair().unwrap();
+        let version = if hybrid {
+            VERSION_HYBRID
+        } else {
+            VERSION_X25519
+        };
+        let spk = generate_signed_prekey(identity.handle.clone(), 1, Some(version)).unwrap();
+        let pq = generate_pq_prekey(identity.handle.clone(), 7).unwrap();
+        let mut opks = generate_one_time_prekeys(100, 1).unwrap();
+
+        Responder {
+            signed_prekey: spk.handle.clone(),
+            one_time_prekey: opks[0].handle.clone(),
+            pq_prekey: pq.handle.clone(),
+            bundle: create_prekey_bundle(
+                identity.x25519_public.clone(),
+                identity.ed25519_public.clone(),
+                spk,
+                vec![opks.remove(0)],
+                hybrid.then_some(pq),
+            )
+            .unwrap(),
+            identity,
+        }
+    }
+
+    fn shared_secret(output: &X3dhOutput) -> [u8; 32] {
+        *handles::symmetric(&output.shared_secret_handle).unwrap()
+    }
+
+    #[test]
+    fn agree_with_and_without_one_time_prekey() {
+        // Lock tests wipe the key registry
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let alice = generate_keypair().unwrap();
+        let bob = responder(false);
+
+        let init = x3dh_initiate(alice.handle.clone(), bob.bundle.clone(), false).unwrap();
+        assert_eq!(init.initial_message.one_time_prekey_id, Some(100));
+        assert_eq!(init.initial_message.version, VERSION_X25519);
+        let resp = x3dh_respond(
+            bob.identity.handle.clone(),
+            bob.signed_prekey.clone(),
+            Some(bob.one_time_prekey.clone()),
+            init.initial_message.clone(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(shared_secret(&init.output), shared_secret(&resp));
+        assert_eq!(init.output.associated_data, resp.associated_data);
+        assert!(x3dh_respond(
+            bob.identity.handle.clone(),
+            bob.signed_prekey.clone(),
+            None,
+            init.initial_message,
+            None,
+        )
+        .is_err());
+
+        let mut bundle = bob.bundle.clone();
+        bundle.one_time_prekeys.clear();
+        let init = x3dh_initiate(alice.handle.clone(), bundle, false).unwrap();
+        assert_eq!(init.initial_message.one_time_prekey_id, None);
+        let resp = x3dh_respond(
+            bob.identity.handle,
+            bob.signed_prekey,
+            None,
+            init.initial_message,
+            None,
+        )
+        .unwrap();
+        assert_eq!(shared_secret(&init.output), shared_secret(&resp));
+    }
+
+    #[test]
+    fn agree_hybrid() {
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let alice = generate_keypair().unwrap();
+        let bob = responder(true);
+        assert_eq!(bob.bundle.version, VERSION_HYBRID);
+
+        let init = x3dh_initiate(alice.handle.clone(), bob.bundle.clone(), true).unwrap();
+        assert_eq!(init.initial_message.version, VERSION_HYBRID);
+        assert_eq!(init.initial_message.pq_prekey_id, Some(7));
+        let resp = x3dh_respond(
+            bob.identity.handle.clone(),
+            bob.signed_prekey.clone(),
+            Some(bob.one_time_prekey.clone()),
+            init.initial_message.clone(),
+            Some(bob.pq_prekey.clone()),
+        )
+        .unwrap();
+        assert_eq!(shared_secret(&init.output), shared_secret(&resp));
+
+        // Dropping the PQ part on the wire gives a different secret
+        let mut downgraded = init.initial_message;
+        downgraded.version = VERSION_X25519;
+        downgraded.pq_ciphertext = None;
+        let resp = x3dh_respond(
+            bob.identity.handle,
+            bob.signed_prekey,
+            Some(bob.one_time_prekey),
+            downgraded,
+            None,
+        )
+        .unwrap();
+        assert_ne!(shared_secret(&init.output), shared_secret(&resp));
+    }
+
+    #[test]
+    fn reject_tampered_and_downgraded_bundles() {
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let alice = generate_keypair().unwrap();
+        let bob = responder(true);
+        let initiate = |bundle: PreKeyBundle, require_pq| {
+            x3dh_initiate(alice.handle.clone(), bundle, require_pq)
+        };
+
+        let mut tampered = bob.bundle.clone();
+        tampered.signed_prekey = bob.bundle.one_time_prekeys[0].public.clone();
+        assert!(initiate(tampered, false).is_err());
+
+        let mut tampered = bob.bundle.clone();
+        let mut signature = BASE64.decode(&tampered.signed_prekey_signature).unwrap();
+        signature[0] ^= 1;
+        tampered.signed_prekey_signature = BASE64.encode(signature);
+        assert!(initiate(tampered, false).is_err());
+
+        // Stripping the PQ prekey, with or without relabeling the version
+        let mut stripped = bob.bundle.clone();
+        stripped.pq_prekey = None;
+        assert!(initiate(stripped.clone(), false).is_err());
+        stripped.version = VERSION_X25519;
+        assert!(initiate(stripped, false).is_err());
+
+        // A genuine X25519-only bundle is refused only when PQ is required
+        let carol = responder(false);
+        assert!(initiate(carol.bundle.clone(), true).is_err());
+        assert!(initiate(carol.bundle.clone(), false).is_ok());
+
+        // The prekey version must match the bundle it is published in
+        let spk = generate_signed_prekey(carol.identity.handle.clone(), 2, None).unwrap();
+        let pq = generate_pq_prekey(carol.identity.handle.clone(), 8).unwrap();
+        assert!(create_prekey_bundle(
+            carol.identity.x25519_public,
+            carol.identity.ed25519_public,
+            spk,
+            vec![],
+            Some(pq),
+        )
+        .is_err());
+    }
+}
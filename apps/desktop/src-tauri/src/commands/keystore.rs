@@ -1,27 +1,405 @@
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
+use std::thread;
+use std::time::{self, Instant};
+use tauri::{AppHandle, Emitter};
+use zeroize::Zeroize;
 
-/// In-memory keystore state that bridges Tauri Stronghold operations.
+use crate::commands::crypto;
+use crate::commands::vault_kdf::{self, VaultKdf};
+
+mod file;
+mod memory;
+mod stronghold;
+
+/// Store key prefixes of the two namespaces, and of the metadata and
+/// previous versions of `store_key` keys.
+const KEY_PREFIX: &str = "key:";
+const SECRET_PREFIX: &str = "secret:";
+const META_PREFIX: &str = "meta:";
+const PREVIOUS_PREFIX: &str = "previous:";
+
+/// How long `rotate_key` keeps the previous version by default.
+const DEFAULT_ROTATION_GRACE_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Metadata value for keys stored without options.
+const UNSPECIFIED: &str = "unspecified";
+
+/// Lifecycle metadata of a `store_key` key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyMetadata {
+    /// What the key is for, e.g. "channel" or "attachment"
+    pub purpose: String,
+    /// Algorithm the key is used with, e.g. "chacha20-poly1305"
+    pub algorithm: String,
+    pub created_at: String,
+    /// Set by `get_key` in memory only, so reading a key never writes the
+    /// vault; it covers use since the keystore was last unlocked
+    pub last_used_at: Option<String>,
+    /// Whether the key may leave this device in a backup
+    pub exportable: bool,
+    /// 1 when stored, incremented by each rotation
+    pub version: u32,
+}
+
+impl KeyMetadata {
+    fn new(options: KeyOptions) -> Self {
+        KeyMetadata {
+            purpose: options.purpose.unwrap_or_else(|| UNSPECIFIED.to_string()),
+            algorithm: options.algorithm.unwrap_or_else(|| UNSPECIFIED.to_string()),
+            created_at: Utc::now().to_rfc3339(),
+            last_used_at: None,
+            exportable: options.exportable.unwrap_or(true),
+            version: 1,
+        }
+    }
+}
+
+/// Metadata supplied to `store_key`. Anything left out is "unspecified",
+/// and keys are exportable unless stated otherwise.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KeyOptions {
+    pub purpose: Option<String>,
+    pub algorithm: Option<String>,
+    pub exportable: Option<bool>,
+}
+
+/// The version a key had before its last rotation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviousKey {
+    /// Base64-encoded key material
+    pub data: String,
+    pub metadata: KeyMetadata,
+    pub expires_at: String,
+}
+
+impl PreviousKey {
+    fn expired(&self, now: DateTime<Utc>) -> bool {
+        DateTime::parse_from_rfc3339(&self.expires_at)
+            .map(|expires_at| expires_at <= now)
+            .unwrap_or(true)
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, String> {
+    serde_json::to_string(value).map_err(|e| format!("Failed to encode key metadata: {}", e))
+}
+
+/// Storage behind the keystore cache.
+///
+/// Entries are flat name -> value strings; names carry the namespace
+/// prefix. Writes are buffered until [`KeyBackend::commit`], which must
+/// persist them atomically.
+pub trait KeyBackend: Send {
+    /// Every entry, including uncommitted writes.
+    fn entries(&self) -> Result<HashMap<String, String>, String>;
+    fn insert(&mut self, name: &str, value: &str) -> Result<(), String>;
+    fn remove(&mut self, name: &str) -> Result<(), String>;
+    /// Persist every write since the last commit. On failure those writes
+    /// are discarded, so a later commit cannot persist them.
+    fn commit(&mut self) -> Result<(), String>;
+    /// Check that `password` opens the persisted vault.
+    fn verify_password(&self, password: &str) -> Result<(), String>;
+    /// Commit under a key derived from `password`, replacing the persisted
+    /// vault atomically.
+    fn rekey(&mut self, password: &str) -> Result<(), String>;
+    /// Wipe any decrypted state held by the backend; called on lock.
+    fn close(&mut self) {}
+}
+
+impl dyn KeyBackend {
+    /// Insert `entries` under `prefix`; they are persisted on commit.
+    fn put<'a>(
+        &mut self,
+        prefix: &str,
+        entries: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) -> Result<(), String> {
+        for (key_id, value) in entries {
+            self.insert(&format!("{}{}", prefix, key_id), value)?;
+        }
+        Ok(())
+    }
+
+    /// Remove `key_id` under `prefix`; the removal is persisted on commit.
+    fn delete(&mut self, prefix: &str, key_id: &str) -> Result<(), String> {
+        self.remove(&format!("{}{}", prefix, key_id))
+    }
+
+    /// Read every entry, sorted into namespaces.
+    fn load(&self) -> Result<KeystoreEntries, String> {
+        let mut entries = KeystoreEntries::default();
+        let corrupt = |_| "Corrupt keystore entry in vault".to_string();
+
+        for (name, value) in self.entries()? {
+            if let Some(key_id) = name.strip_prefix(KEY_PREFIX) {
+                entries.keys.insert(key_id.to_string(), value);
+            } else if let Some(key_id) = name.strip_prefix(SECRET_PREFIX) {
+                entries.secrets.insert(key_id.to_string(), value);
+            } else if let Some(key_id) = name.strip_prefix(META_PREFIX) {
+                let metadata = serde_json::from_str(&value).map_err(corrupt)?;
+                entries.metadata.insert(key_id.to_string(), metadata);
+            } else if let Some(key_id) = name.strip_prefix(PREVIOUS_PREFIX) {
+                let previous = serde_json::from_str(&value).map_err(corrupt)?;
+                entries.previous.insert(key_id.to_string(), previous);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Where the keystore keeps its entries, chosen at `init_keystore`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeystoreBackend {
+    /// Stronghold snapshot
+    #[default]
+    Stronghold,
+    /// Single XChaCha20-Poly1305 sealed file, readable without Stronghold
+    EncryptedFile,
+    /// Process memory only; for tests
+    Memory,
+}
+
+impl KeystoreBackend {
+    /// Open the vault at `vault_path`, creating it if it does not exist.
+    fn open(self, vault_path: &str, password: &str) -> Result<Box<dyn KeyBackend>, String> {
+        Ok(match self {
+            KeystoreBackend::Stronghold => {
+                Box::new(stronghold::StrongholdBackend::open(vault_path, password)?)
+            }
+            KeystoreBackend::EncryptedFile => {
+                Box::new(file::FileBackend::open(vault_path, password)?)
+            }
+            KeystoreBackend::Memory => Box::new(memory::MemoryBackend::open(vault_path, password)?),
+        })
+    }
+}
+
+/// KDF of the keystore vault at `vault_path`.
 ///
-/// Stronghold plugin operations are handled through the Tauri plugin system.
-/// This module provides IPC commands for managing keys, using an in-memory
-/// cache backed by the Stronghold vault on disk via the plugin.
+/// Each vault keeps its own salt and cost parameters in a sidecar file, so
+/// changing the install-wide parameters with `rekey_vault` cannot make it
+/// unreadable. New vaults get a fresh salt and the default parameters.
+/// Vaults created before the sidecar existed keep the install-wide KDF they
+/// were created with.
+fn keystore_kdf(vault_path: &str) -> Result<VaultKdf, String> {
+    let config_path = PathBuf::from(format!("{}.kdf.json", vault_path));
+    if !config_path.exists() && Path::new(vault_path).exists() {
+        return vault_kdf::current()?.save_as(&config_path);
+    }
+    VaultKdf::load_or_create(&config_path)
+}
+
+/// Move `tmp` over `path` once its contents are on disk, then sync the
+/// directory so the rename itself survives a crash.
+pub(crate) fn replace_file(tmp: &Path, path: &Path) -> Result<(), String> {
+    File::open(tmp)
+        .and_then(|file| file.sync_all())
+        .map_err(|e| format!("Failed to sync vault: {}", e))?;
+    fs::rename(tmp, path).map_err(|e| format!("Failed to replace vault: {}", e))?;
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| format!("Failed to sync vault directory: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Keystore state: an in-memory cache of the entries in the vault.
+///
+/// Every write goes to the backend and is committed before the cache is
+/// updated, so the cache never holds entries that would be lost on
+/// restart.
+///
+/// Locking wipes the cache and closes the vault but keeps `initialized` set,
+/// so callers that persist into the keystore fail instead of skipping it.
 pub struct KeystoreState {
     pub initialized: bool,
+    pub locked: bool,
     pub vault_path: Option<String>,
+    pub backend_kind: KeystoreBackend,
+    pub backend: Option<Box<dyn KeyBackend>>,
     /// In-memory key cache: key_id -> base64-encoded key material
     pub keys: HashMap<String, String>,
+    /// Secrets owned by the Rust side (identity keys, sessions), never
+    /// returned over IPC: key_id -> base64-encoded material
+    pub secrets: HashMap<String, String>,
+    /// Metadata of the entries in `keys`
+    pub metadata: HashMap<String, KeyMetadata>,
+    /// Versions replaced by `rotate_key`, kept until they expire
+    pub previous: HashMap<String, PreviousKey>,
+    /// Lock after this long without keystore access; `None` never locks
+    pub idle_timeout: Option<time::Duration>,
+    pub last_activity: Instant,
 }
 
 impl Default for KeystoreState {
     fn default() -> Self {
         KeystoreState {
             initialized: false,
+            locked: false,
             vault_path: None,
+            backend_kind: KeystoreBackend::default(),
+            backend: None,
             keys: HashMap::new(),
+            secrets: HashMap::new(),
+            metadata: HashMap::new(),
+            previous: HashMap::new(),
+            idle_timeout: None,
+            last_activity: Instant::now(),
+        }
+    }
+}
+
+impl KeystoreState {
+    /// Check the keystore can be used, and count the call as activity for
+    /// the idle timeout.
+    fn unlocked(&mut self) -> Result<(), String> {
+        if !self.initialized {
+            return Err("Keystore not initialized. Call init_keystore first.".to_string());
+        }
+        if self.locked {
+            return Err("Keystore is locked. Call unlock_keystore first.".to_string());
+        }
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Open the vault at `vault_path` and load its entries into the cache.
+    fn open(
+        &mut self,
+        backend_kind: KeystoreBackend,
+        vault_path: String,
+        password: &str,
+    ) -> Result<(), String> {
+        let backend = backend_kind.open(&vault_path, password)?;
+        let entries = backend.load()?;
+
+        self.initialized = true;
+        self.locked = false;
+        self.vault_path = Some(vault_path);
+        self.backend_kind = backend_kind;
+        self.backend = Some(backend);
+        self.keys = entries.keys;
+        self.secrets = entries.secrets;
+        self.metadata = entries.metadata;
+        self.previous = entries.previous;
+        self.last_activity = Instant::now();
+        self.fill_missing_metadata()?;
+        self.remove_expired()
+    }
+
+    /// Zeroize the cached key material and close the vault.
+    fn lock(&mut self) {
+        for value in self.keys.values_mut().chain(self.secrets.values_mut()) {
+            value.zeroize();
         }
+        for previous in self.previous.values_mut() {
+            previous.data.zeroize();
+        }
+        self.keys.clear();
+        self.secrets.clear();
+        self.metadata.clear();
+        self.previous.clear();
+        if let Some(mut backend) = self.backend.take() {
+            backend.close();
+        }
+        self.locked = true;
+    }
+
+    /// Whether the idle timeout has passed since the last keystore access.
+    fn idle(&self, now: Instant) -> bool {
+        match self.idle_timeout {
+            Some(timeout) if self.initialized && !self.locked => {
+                now.duration_since(self.last_activity) >= timeout
+            }
+            _ => false,
+        }
+    }
+
+    fn backend(&mut self) -> Result<&mut (dyn KeyBackend + 'static), String> {
+        self.backend
+            .as_deref_mut()
+            .ok_or_else(|| "Keystore not initialized. Call init_keystore first.".to_string())
+    }
+
+    /// Persist one entry of the IPC namespace with its metadata, then cache it.
+    fn put_key(
+        &mut self,
+        key_id: String,
+        value: String,
+        metadata: KeyMetadata,
+    ) -> Result<(), String> {
+        let backend = self.backend()?;
+        backend.put(KEY_PREFIX, [(&key_id, &value)])?;
+        backend.put(META_PREFIX, [(&key_id, &to_json(&metadata)?)])?;
+        backend.commit()?;
+        self.keys.insert(key_id.clone(), value);
+        self.metadata.insert(key_id, metadata);
+        Ok(())
+    }
+
+    /// Give keys stored before metadata existed default metadata. Written to
+    /// disk with the next commit.
+    fn fill_missing_metadata(&mut self) -> Result<(), String> {
+        let missing: Vec<String> = self
+            .keys
+            .keys()
+            .filter(|key_id| !self.metadata.contains_key(*key_id))
+            .cloned()
+            .collect();
+        for key_id in missing {
+            let metadata = KeyMetadata::new(KeyOptions::default());
+            self.backend()?
+                .put(META_PREFIX, [(&key_id, &to_json(&metadata)?)])?;
+            self.metadata.insert(key_id, metadata);
+        }
+        Ok(())
+    }
+
+    /// Drop previous versions whose grace period is over. Written to disk
+    /// with the next commit.
+    fn remove_expired(&mut self) -> Result<(), String> {
+        let now = Utc::now();
+        let expired: Vec<String> = self
+            .previous
+            .iter()
+            .filter(|(_, previous)| previous.expired(now))
+            .map(|(key_id, _)| key_id.clone())
+            .collect();
+        for key_id in expired {
+            self.backend()?.delete(PREVIOUS_PREFIX, &key_id)?;
+            self.previous.remove(&key_id);
+        }
+        Ok(())
+    }
+
+    fn key_info(&self, key_id: &str) -> Option<KeyInfo> {
+        Some(KeyInfo {
+            key_id: key_id.to_string(),
+            metadata: self.metadata.get(key_id)?.clone(),
+            previous_expires_at: self
+                .previous
+                .get(key_id)
+                .map(|previous| previous.expires_at.clone()),
+        })
+    }
+
+    /// Persist one entry of the secret namespace, then cache it.
+    fn put_secret(&mut self, key_id: String, value: String) -> Result<(), String> {
+        let backend = self.backend()?;
+        backend.put(SECRET_PREFIX, [(&key_id, &value)])?;
+        backend.commit()?;
+        self.secrets.insert(key_id, value);
+        Ok(())
     }
 }
 
@@ -29,6 +407,7 @@ impl Default for KeystoreState {
 pub struct KeystoreInfo {
     pub initialized: bool,
     pub vault_path: String,
+    pub backend: KeystoreBackend,
     pub key_count: usize,
 }
 
@@ -38,27 +417,42 @@ pub struct StoredKeyInfo {
     pub stored: bool,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyInfo {
+    pub key_id: String,
+    #[serde(flatten)]
+    pub metadata: KeyMetadata,
+    /// When the version replaced by the last rotation stops being available
+    pub previous_expires_at: Option<String>,
+}
+
 /// Global keystore protected by a mutex.
 static KEYSTORE: std::sync::LazyLock<Mutex<KeystoreState>> =
     std::sync::LazyLock::new(|| Mutex::new(KeystoreState::default()));
 
-/// Initialize the Stronghold-backed keystore.
+/// Initialize the keystore.
 ///
-/// `vault_path` is the filesystem path where the Stronghold vault will be stored.
+/// `vault_path` is the filesystem path where the vault will be stored.
 /// `password` is used to encrypt the vault at rest.
+/// `backend` picks the vault format; Stronghold by default.
 ///
-/// In production, the Stronghold plugin handles the actual vault I/O.
-/// This command sets up the in-memory state and confirms readiness.
+/// An existing vault is opened and its entries loaded; a wrong password is
+/// an error. Otherwise a new vault is created at `vault_path`.
 #[tauri::command]
-pub fn init_keystore(vault_path: String, password: String) -> Result<KeystoreInfo, String> {
+pub fn init_keystore(
+    vault_path: String,
+    password: String,
+    backend: Option<KeystoreBackend>,
+) -> Result<KeystoreInfo, String> {
     let mut store = KEYSTORE
         .lock()
         .map_err(|e| format!("Failed to lock keystore: {}", e))?;
 
-    if store.initialized {
+    if store.initialized && !store.locked {
         return Ok(KeystoreInfo {
             initialized: true,
             vault_path: store.vault_path.clone().unwrap_or_default(),
+            backend: store.backend_kind,
             key_count: store.keys.len(),
         });
     }
@@ -68,24 +462,44 @@ pub fn init_keystore(vault_path: String, password: String) -> Result<KeystoreInf
         return Err("Password must be at least 8 characters".to_string());
     }
 
-    store.initialized = true;
-    store.vault_path = Some(vault_path.clone());
+    let backend = backend.unwrap_or_default();
+    store.open(backend, vault_path.clone(), &password)?;
 
     Ok(KeystoreInfo {
         initialized: true,
         vault_path,
-        key_count: 0,
+        backend,
+        key_count: store.keys.len(),
     })
 }
 
-/// Store a cryptographic key in the vault.
+/// Lock the keystore: the cached entries are zeroized and every keystore
+/// call fails until `unlock_keystore`.
 ///
-/// `key_id` is a unique identifier for the key (e.g., "identity", "channel:<id>").
-/// `key_data_b64` is the base64-encoded key material.
+/// Key handles, sessions and media keys are wiped too, so handles must be
+/// reloaded with `load_key` and media rooms joined again after unlocking.
+#[tauri::command]
+pub fn lock_keystore() -> Result<(), String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    if !store.initialized {
+        return Err("Keystore not initialized. Call init_keystore first.".to_string());
+    }
+
+    store.lock();
+    // The crypto caches call into the keystore, so wipe them without holding it
+    drop(store);
+    crypto::wipe_live_keys();
+    Ok(())
+}
+
+/// Unlock a locked keystore, reloading its entries from the vault.
 ///
-/// If a key with the same ID already exists, it will be overwritten.
+/// A wrong password is an error and leaves the keystore locked.
 #[tauri::command]
-pub fn store_key(key_id: String, key_data_b64: String) -> Result<StoredKeyInfo, String> {
+pub fn unlock_keystore(password: String) -> Result<KeystoreInfo, String> {
     let mut store = KEYSTORE
         .lock()
         .map_err(|e| format!("Failed to lock keystore: {}", e))?;
@@ -94,16 +508,130 @@ pub fn store_key(key_id: String, key_data_b64: String) -> Result<StoredKeyInfo,
         return Err("Keystore not initialized. Call init_keystore first.".to_string());
     }
 
+    let vault_path = store.vault_path.clone().unwrap_or_default();
+    let backend = store.backend_kind;
+    if store.locked {
+        store.open(backend, vault_path.clone(), &password)?;
+    }
+
+    Ok(KeystoreInfo {
+        initialized: true,
+        vault_path,
+        backend,
+        key_count: store.keys.len(),
+    })
+}
+
+/// Change the vault password, re-encrypting the vault under the new one.
+///
+/// The keystore must be unlocked, and `old_password` must open the vault.
+/// The persisted vault is replaced atomically: if the change fails or is
+/// interrupted, the old password keeps working.
+#[tauri::command]
+pub fn change_keystore_password(old_password: String, new_password: String) -> Result<(), String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    if new_password.len() < 8 {
+        return Err("Password must be at least 8 characters".to_string());
+    }
+
+    let backend = store.backend()?;
+    backend.verify_password(&old_password)?;
+    backend.rekey(&new_password)
+}
+
+/// Whether the auto-lock thread has been started.
+static AUTO_LOCK_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Lock the keystore once it has gone `timeout_secs` without being used,
+/// emitting "keystore-locked" so the UI can ask for the password again.
+///
+/// `None` or 0 turns auto-lock off.
+#[tauri::command]
+pub fn set_keystore_idle_timeout(app: AppHandle, timeout_secs: Option<u64>) -> Result<(), String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.idle_timeout = timeout_secs
+        .filter(|&secs| secs > 0)
+        .map(time::Duration::from_secs);
+    store.last_activity = Instant::now();
+
+    if !AUTO_LOCK_RUNNING.swap(true, Ordering::SeqCst) {
+        thread::spawn(move || loop {
+            thread::sleep(time::Duration::from_secs(1));
+            if lock_if_idle(Instant::now()) {
+                let _ = app.emit("keystore-locked", ());
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Lock the keystore if it has been idle for longer than its timeout.
+/// Returns whether it was locked.
+fn lock_if_idle(now: Instant) -> bool {
+    let Ok(mut store) = KEYSTORE.lock() else {
+        return false;
+    };
+    if !store.idle(now) {
+        return false;
+    }
+    store.lock();
+    drop(store);
+    crypto::wipe_live_keys();
+    true
+}
+
+fn validate_key_data(key_data_b64: &str) -> Result<(), String> {
     // Validate that the data is valid base64
     let decoded = BASE64
-        .decode(&key_data_b64)
+        .decode(key_data_b64)
         .map_err(|e| format!("Invalid base64 key data: {}", e))?;
 
     if decoded.is_empty() {
         return Err("Key data cannot be empty".to_string());
     }
+    Ok(())
+}
+
+/// Store a cryptographic key in the vault.
+///
+/// `key_id` is a unique identifier for the key (e.g., "identity", "channel:<id>").
+/// `key_data_b64` is the base64-encoded key material.
+/// `options` sets the key's purpose, algorithm and exportable flag.
+///
+/// Storing under an existing ID fails unless `overwrite` is true, in which
+/// case the key and its metadata are replaced.
+#[tauri::command]
+pub fn store_key(
+    key_id: String,
+    key_data_b64: String,
+    options: Option<KeyOptions>,
+    overwrite: Option<bool>,
+) -> Result<StoredKeyInfo, String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    validate_key_data(&key_data_b64)?;
+
+    if store.keys.contains_key(&key_id) && !overwrite.unwrap_or(false) {
+        return Err(format!(
+            "Key '{}' already exists; pass overwrite to replace it",
+            key_id
+        ));
+    }
 
-    store.keys.insert(key_id.clone(), key_data_b64);
+    let metadata = KeyMetadata::new(options.unwrap_or_default());
+    store.put_key(key_id.clone(), key_data_b64, metadata)?;
 
     Ok(StoredKeyInfo {
         key_id,
@@ -116,17 +644,598 @@ pub fn store_key(key_id: String, key_data_b64: String) -> Result<StoredKeyInfo,
 /// Returns the base64-encoded key material, or an error if the key is not found.
 #[tauri::command]
 pub fn get_key(key_id: String) -> Result<String, String> {
-    let store = KEYSTORE
+    let mut store = KEYSTORE
         .lock()
         .map_err(|e| format!("Failed to lock keystore: {}", e))?;
 
-    if !store.initialized {
-        return Err("Keystore not initialized. Call init_keystore first.".to_string());
-    }
+    store.unlocked()?;
 
-    store
+    let data = store
         .keys
         .get(&key_id)
         .cloned()
+        .ok_or_else(|| format!("Key '{}' not found in keystore", key_id))?;
+
+    if let Some(metadata) = store.metadata.get_mut(&key_id) {
+        metadata.last_used_at = Some(Utc::now().to_rfc3339());
+    }
+
+    Ok(data)
+}
+
+/// Retrieve the version of a key replaced by its last rotation, while its
+/// grace period lasts.
+#[tauri::command]
+pub fn get_previous_key(key_id: String) -> Result<String, String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    store.remove_expired()?;
+    store
+        .previous
+        .get(&key_id)
+        .map(|previous| previous.data.clone())
+        .ok_or_else(|| format!("Key '{}' has no previous version", key_id))
+}
+
+/// List stored keys and their metadata, sorted by ID.
+///
+/// With `prefix`, only keys whose ID starts with it are listed, e.g.
+/// "channel:" for every channel key.
+#[tauri::command]
+pub fn list_keys(prefix: Option<String>) -> Result<Vec<KeyInfo>, String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    let prefix = prefix.unwrap_or_default();
+    let mut keys: Vec<KeyInfo> = store
+        .keys
+        .keys()
+        .filter(|key_id| key_id.starts_with(&prefix))
+        .filter_map(|key_id| store.key_info(key_id))
+        .collect();
+    keys.sort_by(|a, b| a.key_id.cmp(&b.key_id));
+    Ok(keys)
+}
+
+/// Delete a key, its metadata and any previous version from the vault.
+#[tauri::command]
+pub fn delete_key(key_id: String) -> Result<(), String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    if !store.keys.contains_key(&key_id) {
+        return Err(format!("Key '{}' not found in keystore", key_id));
+    }
+
+    let backend = store.backend()?;
+    backend.delete(KEY_PREFIX, &key_id)?;
+    backend.delete(META_PREFIX, &key_id)?;
+    backend.delete(PREVIOUS_PREFIX, &key_id)?;
+    backend.commit()?;
+
+    store.keys.remove(&key_id);
+    store.metadata.remove(&key_id);
+    store.previous.remove(&key_id);
+    Ok(())
+}
+
+/// Replace a key with new material, keeping the current version available
+/// through `get_previous_key` for `grace_period_secs` (default 7 days).
+///
+/// The new version keeps the key's purpose, algorithm and exportable flag.
+/// Only one previous version is kept; rotating again drops the older one.
+#[tauri::command]
+pub fn rotate_key(
+    key_id: String,
+    key_data_b64: String,
+    grace_period_secs: Option<u64>,
+) -> Result<KeyInfo, String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    validate_key_data(&key_data_b64)?;
+
+    let (data, metadata) = match (store.keys.get(&key_id), store.metadata.get(&key_id)) {
+        (Some(data), Some(metadata)) => (data.clone(), metadata.clone()),
+        _ => return Err(format!("Key '{}' not found in keystore", key_id)),
+    };
+
+    let grace = grace_period_secs.unwrap_or(DEFAULT_ROTATION_GRACE_SECS);
+    let grace = i64::try_from(grace)
+        .ok()
+        .and_then(Duration::try_seconds)
+        .ok_or_else(|| format!("Grace period of {} seconds is too long", grace))?;
+    let now = Utc::now();
+    let previous = PreviousKey {
+        data,
+        metadata: metadata.clone(),
+        expires_at: (now + grace).to_rfc3339(),
+    };
+    let rotated = KeyMetadata {
+        created_at: now.to_rfc3339(),
+        last_used_at: None,
+        version: metadata.version + 1,
+        ..metadata
+    };
+
+    // Committed together with the new version by `put_key`
+    store
+        .backend()?
+        .put(PREVIOUS_PREFIX, [(&key_id, &to_json(&previous)?)])?;
+    store.put_key(key_id.clone(), key_data_b64, rotated)?;
+    store.previous.insert(key_id.clone(), previous);
+    store.remove_expired()?;
+
+    store
+        .key_info(&key_id)
         .ok_or_else(|| format!("Key '{}' not found in keystore", key_id))
 }
+
+/// Store secret material that must never be handed back to the webview.
+///
+/// Entries live in a separate namespace from `store_key`/`get_key`, so IPC
+/// callers can neither read nor overwrite them.
+pub(crate) fn store_secret(key_id: &str, data_b64: String) -> Result<(), String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    store.put_secret(key_id.to_string(), data_b64)
+}
+
+/// Load secret material stored with [`store_secret`].
+pub(crate) fn load_secret(key_id: &str) -> Result<String, String> {
+    find_secret(key_id)?.ok_or_else(|| format!("Key '{}' not found in keystore", key_id))
+}
+
+/// Like [`load_secret`], but `None` if nothing is stored under `key_id`.
+pub(crate) fn find_secret(key_id: &str) -> Result<Option<String>, String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    Ok(store.secrets.get(key_id).cloned())
+}
+
+/// Load secret material stored with [`store_secret`], storing the result of
+/// `create` first if there is none yet.
+pub(crate) fn load_or_create_secret(
+    key_id: &str,
+    create: impl FnOnce() -> String,
+) -> Result<String, String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    if let Some(existing) = store.secrets.get(key_id) {
+        return Ok(existing.clone());
+    }
+    let created = create();
+    store.put_secret(key_id.to_string(), created.clone())?;
+    Ok(created)
+}
+
+/// Every stored entry, as exported into and restored from backups.
+///
+/// Previous versions kept by `rotate_key` stay on this device.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct KeystoreEntries {
+    pub keys: HashMap<String, String>,
+    pub secrets: HashMap<String, String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, KeyMetadata>,
+    #[serde(skip)]
+    pub previous: HashMap<String, PreviousKey>,
+}
+
+impl KeystoreEntries {
+    pub fn len(&self) -> usize {
+        self.keys.len() + self.secrets.len()
+    }
+}
+
+/// Copy out every stored entry, leaving out keys that are not exportable.
+pub(crate) fn export_entries() -> Result<KeystoreEntries, String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    let exportable = |key_id: &String| {
+        store
+            .metadata
+            .get(key_id)
+            .is_none_or(|metadata| metadata.exportable)
+    };
+
+    Ok(KeystoreEntries {
+        keys: store
+            .keys
+            .iter()
+            .filter(|(key_id, _)| exportable(key_id))
+            .map(|(key_id, data)| (key_id.clone(), data.clone()))
+            .collect(),
+        secrets: store.secrets.clone(),
+        metadata: store
+            .metadata
+            .iter()
+            .filter(|(key_id, _)| exportable(key_id))
+            .map(|(key_id, metadata)| (key_id.clone(), metadata.clone()))
+            .collect(),
+        previous: HashMap::new(),
+    })
+}
+
+/// Restore entries from a backup that are missing from the keystore.
+///
+/// Entries that already exist are never overwritten. Keys from backups made
+/// before key metadata existed get default metadata. Returns the number of
+/// entries restored.
+pub(crate) fn import_entries(entries: KeystoreEntries) -> Result<usize, String> {
+    let mut store = KEYSTORE
+        .lock()
+        .map_err(|e| format!("Failed to lock keystore: {}", e))?;
+
+    store.unlocked()?;
+
+    let mut entries = entries;
+    entries
+        .keys
+        .retain(|key_id, _| !store.keys.contains_key(key_id));
+    entries
+        .secrets
+        .retain(|key_id, _| !store.secrets.contains_key(key_id));
+
+    let count = entries.len();
+    let mut metadata = entries.metadata;
+    for key_id in entries.keys.keys() {
+        metadata
+            .entry(key_id.clone())
+            .or_insert_with(|| KeyMetadata::new(KeyOptions::default()));
+    }
+    metadata.retain(|key_id, _| entries.keys.contains_key(key_id));
+    let metadata_json = metadata
+        .iter()
+        .map(|(key_id, metadata)| Ok((key_id.clone(), to_json(metadata)?)))
+        .collect::<Result<HashMap<_, _>, String>>()?;
+
+    let backend = store.backend()?;
+    backend.put(KEY_PREFIX, &entries.keys)?;
+    backend.put(SECRET_PREFIX, &entries.secrets)?;
+    backend.put(META_PREFIX, &metadata_json)?;
+    backend.commit()?;
+    store.keys.extend(entries.keys);
+    store.secrets.extend(entries.secrets);
+    store.metadata.extend(metadata);
+    Ok(count)
+}
+
+/// Tests share the global keystore and vault KDF, so they take turns.
+#[cfg(test)]
+pub(crate) static TEST_SERIAL: Mutex<()> = Mutex::new(());
+
+/// Start a test on a fresh in-memory keystore. Hold the guard for the
+/// whole test.
+#[cfg(test)]
+pub(crate) fn test_keystore() -> std::sync::MutexGuard<'static, ()> {
+    let serial = TEST_SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    KEYSTORE.clear_poison();
+    *KEYSTORE.lock().unwrap() = KeystoreState::default();
+    let vault_path = format!("test-{}", uuid::Uuid::new_v4());
+    init_keystore(
+        vault_path,
+        "correct horse".into(),
+        Some(KeystoreBackend::Memory),
+    )
+    .unwrap();
+    serial
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::vault_kdf;
+
+    /// Drop all in-memory state, as an app restart would.
+    fn restart() {
+        *KEYSTORE.lock().unwrap() = KeystoreState::default();
+    }
+
+    /// Every backend, for tests that must hold for all of them.
+    const BACKENDS: [KeystoreBackend; 3] = [
+        KeystoreBackend::Stronghold,
+        KeystoreBackend::EncryptedFile,
+        KeystoreBackend::Memory,
+    ];
+
+    /// Start from an empty keystore backed by a new vault in `dir`.
+    fn open_temp_keystore(dir: &tempfile::TempDir, backend: KeystoreBackend) -> String {
+        // Snapshot encryption stretches the key again on every commit; the
+        // vault key is already Argon2-derived, so skip that in tests.
+        iota_stronghold::engine::snapshot::try_set_encrypt_work_factor(0).unwrap();
+
+        restart();
+        let vault_path = dir
+            .path()
+            .join("keystore.vault")
+            .to_string_lossy()
+            .into_owned();
+        init_keystore(vault_path.clone(), "correct horse".into(), Some(backend)).unwrap();
+        vault_path
+    }
+
+    #[test]
+    fn entries_survive_restart() {
+        let _serial = TEST_SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        for backend in BACKENDS {
+            let dir = tempfile::tempdir().unwrap();
+            let vault_path = open_temp_keystore(&dir, backend);
+            let key = BASE64.encode([7u8; 32]);
+
+            store_key("identity".into(), key.clone(), None, None).unwrap();
+            store_secret("session", "c2Vzc2lvbg==".into()).unwrap();
+
+            restart();
+            assert!(
+                init_keystore(vault_path.clone(), "wrong password".into(), Some(backend)).is_err()
+            );
+            assert!(get_key("identity".into()).is_err());
+
+            let info = init_keystore(vault_path, "correct horse".into(), Some(backend)).unwrap();
+            assert_eq!(info.backend, backend);
+            assert_eq!(info.key_count, 1);
+            assert_eq!(get_key("identity".into()).unwrap(), key);
+            assert_eq!(load_secret("session").unwrap(), "c2Vzc2lvbg==");
+            assert!(get_key("session".into()).is_err());
+        }
+    }
+
+    #[test]
+    fn key_lifecycle() {
+        let _serial = TEST_SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let vault_path = open_temp_keystore(&dir, KeystoreBackend::Memory);
+        let (v1, v2) = (BASE64.encode([1u8; 32]), BASE64.encode([2u8; 32]));
+        let options = || KeyOptions {
+            purpose: Some("channel".into()),
+            algorithm: Some("chacha20-poly1305".into()),
+            exportable: Some(false),
+        };
+
+        store_key("channel:a".into(), v1.clone(), Some(options()), None).unwrap();
+        store_key("channel:b".into(), v1.clone(), Some(options()), None).unwrap();
+        store_key("identity".into(), v1.clone(), None, None).unwrap();
+        assert!(store_key("channel:a".into(), v2.clone(), None, None).is_err());
+        assert!(store_key("channel:a".into(), v2.clone(), None, Some(false)).is_err());
+
+        let channels = list_keys(Some("channel:".into())).unwrap();
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].key_id, "channel:a");
+        assert_eq!(channels[0].metadata.purpose, "channel");
+        assert!(channels[0].metadata.last_used_at.is_none());
+        assert_eq!(list_keys(None).unwrap().len(), 3);
+
+        get_key("channel:a".into()).unwrap();
+        let rotated = rotate_key("channel:a".into(), v2.clone(), None).unwrap();
+        assert_eq!(rotated.metadata.version, 2);
+        assert_eq!(rotated.metadata.algorithm, "chacha20-poly1305");
+        assert!(rotated.previous_expires_at.is_some());
+        assert!(get_previous_key("channel:b".into()).is_err());
+
+        delete_key("channel:b".into()).unwrap();
+        assert!(delete_key("channel:b".into()).is_err());
+
+        // Non-exportable keys stay out of backups
+        let exported = export_entries().unwrap();
+        assert!(exported.keys.contains_key("identity"));
+        assert!(!exported.keys.contains_key("channel:a"));
+
+        restart();
+        init_keystore(
+            vault_path,
+            "correct horse".into(),
+            Some(KeystoreBackend::Memory),
+        )
+        .unwrap();
+        assert_eq!(get_key("channel:a".into()).unwrap(), v2);
+        assert_eq!(get_previous_key("channel:a".into()).unwrap(), v1);
+        assert!(get_key("channel:b".into()).is_err());
+        let channels = list_keys(Some("channel:".into())).unwrap();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].metadata.version, 2);
+        assert!(!channels[0].metadata.exportable);
+
+        // An expired previous version is gone
+        rotate_key("channel:a".into(), v1.clone(), Some(0)).unwrap();
+        assert!(get_previous_key("channel:a".into()).is_err());
+    }
+
+    #[test]
+    fn last_use_stays_in_memory() {
+        let _serial = TEST_SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let vault_path = open_temp_keystore(&dir, KeystoreBackend::Memory);
+        let key = BASE64.encode([1u8; 32]);
+        let last_used = || {
+            list_keys(Some("identity".into())).unwrap()[0]
+                .metadata
+                .last_used_at
+                .clone()
+        };
+
+        store_key("identity".into(), key.clone(), None, None).unwrap();
+        get_key("identity".into()).unwrap();
+        assert!(last_used().is_some());
+
+        // A later write does not carry the read into the vault
+        store_key("other".into(), key, None, None).unwrap();
+        restart();
+        init_keystore(
+            vault_path,
+            "correct horse".into(),
+            Some(KeystoreBackend::Memory),
+        )
+        .unwrap();
+        assert!(last_used().is_none());
+    }
+
+    #[test]
+    fn failed_rotation_keeps_the_current_version() {
+        let _serial = TEST_SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        for backend in [KeystoreBackend::Stronghold, KeystoreBackend::EncryptedFile] {
+            let dir = tempfile::tempdir().unwrap();
+            let vault_path = open_temp_keystore(&dir, backend);
+            let (v1, v2) = (BASE64.encode([1u8; 32]), BASE64.encode([2u8; 32]));
+            store_key("channel:a".into(), v1.clone(), None, None).unwrap();
+
+            // A directory in place of the vault makes the commit fail
+            let moved = dir.path().join("keystore.vault.moved");
+            fs::rename(&vault_path, &moved).unwrap();
+            fs::create_dir(&vault_path).unwrap();
+            assert!(rotate_key("channel:a".into(), v2, None).is_err());
+            assert_eq!(get_key("channel:a".into()).unwrap(), v1);
+            assert!(get_previous_key("channel:a".into()).is_err());
+            assert_eq!(list_keys(None).unwrap()[0].metadata.version, 1);
+
+            // The next commit must not persist the failed rotation
+            fs::remove_dir(&vault_path).unwrap();
+            fs::rename(&moved, &vault_path).unwrap();
+            store_key("channel:b".into(), v1.clone(), None, None).unwrap();
+            restart();
+            init_keystore(vault_path, "correct horse".into(), Some(backend)).unwrap();
+            assert_eq!(get_key("channel:a".into()).unwrap(), v1);
+            assert!(get_previous_key("channel:a".into()).is_err());
+            let keys = list_keys(None).unwrap();
+            assert_eq!(keys.len(), 2);
+            assert!(keys.iter().all(|key| key.metadata.version == 1));
+        }
+    }
+
+    #[test]
+    fn lock_and_unlock() {
+        let _serial = TEST_SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        open_temp_keystore(&dir, KeystoreBackend::Memory);
+        let key = BASE64.encode([3u8; 32]);
+        store_key("identity".into(), key.clone(), None, None).unwrap();
+        store_secret("session", "c2Vzc2lvbg==".into()).unwrap();
+
+        let handle = crypto::handles::insert_symmetric([4u8; 32]).unwrap();
+        crypto::handles::persist_key(handle.clone(), "stored".into()).unwrap();
+
+        lock_keystore().unwrap();
+        assert!(KEYSTORE.lock().unwrap().keys.is_empty());
+        assert!(crypto::handles::symmetric(&handle).is_err());
+        assert!(crypto::handles::load_key("stored".into()).is_err());
+        assert!(KEYSTORE.lock().unwrap().initialized);
+        assert!(get_key("identity".into()).is_err());
+        assert!(store_secret("session", "b3RoZXI=".into()).is_err());
+
+        assert!(unlock_keystore("wrong password".into()).is_err());
+        assert!(load_secret("session").is_err());
+        let info = unlock_keystore("correct horse".into()).unwrap();
+        assert_eq!(info.key_count, 1);
+        assert_eq!(get_key("identity".into()).unwrap(), key);
+        assert_eq!(load_secret("session").unwrap(), "c2Vzc2lvbg==");
+        let handle = crypto::handles::load_key("stored".into()).unwrap();
+        assert_eq!(*crypto::handles::symmetric(&handle).unwrap(), [4u8; 32]);
+
+        // Idle timeout
+        KEYSTORE.lock().unwrap().idle_timeout = Some(time::Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(!lock_if_idle(now));
+        assert!(lock_if_idle(now + time::Duration::from_secs(61)));
+        assert!(!lock_if_idle(now + time::Duration::from_secs(120)));
+        assert!(get_key("identity".into()).is_err());
+    }
+
+    #[test]
+    fn change_password() {
+        let _serial = TEST_SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        for backend in BACKENDS {
+            let dir = tempfile::tempdir().unwrap();
+            let vault_path = open_temp_keystore(&dir, backend);
+            let key = BASE64.encode([5u8; 32]);
+            store_key("identity".into(), key.clone(), None, None).unwrap();
+
+            assert!(
+                change_keystore_password("wrong password".into(), "new password".into()).is_err()
+            );
+            assert!(change_keystore_password("correct horse".into(), "short".into()).is_err());
+            change_keystore_password("correct horse".into(), "battery staple".into()).unwrap();
+            assert!(!Path::new(&format!("{}.tmp", vault_path)).exists());
+
+            // Later writes are committed under the new password
+            store_secret("session", "c2Vzc2lvbg==".into()).unwrap();
+
+            restart();
+            assert!(
+                init_keystore(vault_path.clone(), "correct horse".into(), Some(backend)).is_err()
+            );
+            init_keystore(vault_path, "battery staple".into(), Some(backend)).unwrap();
+            assert_eq!(get_key("identity".into()).unwrap(), key);
+            assert_eq!(load_secret("session").unwrap(), "c2Vzc2lvbg==");
+        }
+    }
+
+    #[test]
+    fn survives_install_kdf_change() {
+        use iota_stronghold::{KeyProvider, SnapshotPath, Stronghold};
+
+        let _serial = TEST_SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        for backend in [KeystoreBackend::Stronghold, KeystoreBackend::EncryptedFile] {
+            let dir = tempfile::tempdir().unwrap();
+            vault_kdf::init(&dir.path().join(vault_kdf::KDF_CONFIG_FILE)).unwrap();
+            let plugin_vault = dir.path().join("plugin.stronghold");
+            let keyprovider = KeyProvider::try_from(zeroize::Zeroizing::new(
+                vault_kdf::hash_password("plugin pass"),
+            ))
+            .unwrap();
+            Stronghold::default()
+                .commit_with_keyprovider(&SnapshotPath::from_path(&plugin_vault), &keyprovider)
+                .unwrap();
+
+            let vault_path = open_temp_keystore(&dir, backend);
+            let key = BASE64.encode([6u8; 32]);
+            store_key("identity".into(), key.clone(), None, None).unwrap();
+
+            let params = vault_kdf::KdfParams {
+                memory_kib: 32 * 1024,
+                iterations: 2,
+                parallelism: 1,
+            };
+            let migration = vault_kdf::rekey_vault(
+                plugin_vault.to_string_lossy().into_owned(),
+                "plugin pass".into(),
+                Some(params),
+            )
+            .unwrap();
+            assert_eq!(migration, vault_kdf::VaultMigration::Rekeyed);
+            assert_eq!(vault_kdf::get_vault_kdf_params().unwrap(), params);
+
+            restart();
+            init_keystore(vault_path, "correct horse".into(), Some(backend)).unwrap();
+            assert_eq!(get_key("identity".into()).unwrap(), key);
+        }
+    }
+}
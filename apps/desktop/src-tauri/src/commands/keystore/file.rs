@@ -0,0 +1,190 @@
+//! Keystore backend on a single encrypted file.
+//!
+//! Unlike a Stronghold snapshot, the format needs nothing beyond
+//! XChaCha20-Poly1305 and the vault KDF to read, so the file can be opened
+//! on any platform. The entries are stored as a JSON object, sealed under
+//! the Argon2id vault key, derived with the vault's own KDF sidecar:
+//!
+//! ```text
+//! magic "ZXKS" (4) | version (1) | nonce (24) | ciphertext + tag
+//! ```
+//!
+//! The header is bound as associated data. Every commit rewrites the whole
+//! file with a fresh nonce.
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::{keystore_kdf, replace_file, KeyBackend};
+use crate::commands::vault_kdf::VaultKdf;
+
+const FILE_MAGIC: &[u8; 4] = b"ZXKS";
+const FILE_VERSION: u8 = 1;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = 4 + 1 + NONCE_LEN;
+
+/// The decrypted contents of a keystore file and the key it is sealed with.
+pub struct FileBackend {
+    path: PathBuf,
+    kdf: VaultKdf,
+    key: Zeroizing<Vec<u8>>,
+    entries: HashMap<String, String>,
+    /// Entries as of the last commit, restored when a commit fails
+    committed: HashMap<String, String>,
+}
+
+impl FileBackend {
+    /// Open the file at `vault_path`, creating it if it does not exist.
+    pub fn open(vault_path: &str, password: &str) -> Result<Self, String> {
+        let kdf = keystore_kdf(vault_path)?;
+        let mut backend = FileBackend {
+            path: PathBuf::from(vault_path),
+            key: kdf.derive(password.as_bytes())?,
+            kdf,
+            entries: HashMap::new(),
+            committed: HashMap::new(),
+        };
+
+        if backend.path.exists() {
+            backend.entries = read(&backend.path, &backend.key)?;
+            backend.committed = backend.entries.clone();
+        } else {
+            backend.commit()?;
+        }
+        Ok(backend)
+    }
+
+    /// Seal the entries into a temporary file and move it over the vault.
+    fn write(&self) -> Result<(), String> {
+        // Sorted, so the file does not leak the insertion order
+        let sorted: BTreeMap<&String, &String> = self.entries.iter().collect();
+        let plaintext = Zeroizing::new(
+            serde_json::to_vec(&sorted)
+                .map_err(|e| format!("Failed to serialize keystore: {}", e))?,
+        );
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(FILE_MAGIC);
+        header.push(FILE_VERSION);
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        header.extend_from_slice(&nonce);
+
+        let ciphertext = XChaCha20Poly1305::new(self.key.as_slice().into())
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: &header,
+                },
+            )
+            .map_err(|e| format!("Encryption failed: {}", e))?;
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create vault directory: {}", e))?;
+        }
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let written = File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(&header)?;
+                file.write_all(&ciphertext)
+            })
+            .map_err(|e| format!("Failed to write vault: {}", e))
+            .and_then(|_| replace_file(&tmp_path, &self.path));
+        if written.is_err() {
+            fs::remove_file(&tmp_path).ok();
+        }
+        written
+    }
+}
+
+/// Decrypt the entries in the file at `path`.
+fn read(path: &Path, key: &[u8]) -> Result<HashMap<String, String>, String> {
+    let blob = fs::read(path).map_err(|e| format!("Failed to read vault: {}", e))?;
+    if blob.len() < HEADER_LEN || &blob[..4] != FILE_MAGIC {
+        return Err("Not a ZeusIX keystore file".to_string());
+    }
+    if blob[4] != FILE_VERSION {
+        return Err(format!("Unsupported keystore file version {}", blob[4]));
+    }
+
+    let (header, ciphertext) = blob.split_at(HEADER_LEN);
+    let plaintext = Zeroizing::new(
+        XChaCha20Poly1305::new(key.into())
+            .decrypt(
+                XNonce::from_slice(&header[5..]),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| "Wrong password or corrupt vault".to_string())?,
+    );
+
+    serde_json::from_slice(&plaintext).map_err(|_| "Corrupt keystore entry in vault".to_string())
+}
+
+impl KeyBackend for FileBackend {
+    fn entries(&self) -> Result<HashMap<String, String>, String> {
+        Ok(self.entries.clone())
+    }
+
+    fn insert(&mut self, name: &str, value: &str) -> Result<(), String> {
+        self.entries.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<(), String> {
+        self.entries.remove(name);
+        Ok(())
+    }
+
+    /// Write the vault, discarding uncommitted entries if that fails.
+    fn commit(&mut self) -> Result<(), String> {
+        match self.write() {
+            Ok(()) => {
+                self.committed = self.entries.clone();
+                Ok(())
+            }
+            Err(e) => {
+                self.entries = self.committed.clone();
+                Err(e)
+            }
+        }
+    }
+
+    fn verify_password(&self, password: &str) -> Result<(), String> {
+        let key = self.kdf.derive(password.as_bytes())?;
+        read(&self.path, &key).map(|_| ())
+    }
+
+    fn rekey(&mut self, password: &str) -> Result<(), String> {
+        let key = self.kdf.derive(password.as_bytes())?;
+        let old_key = std::mem::replace(&mut self.key, key);
+        if let Err(e) = self.commit() {
+            self.key = old_key;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        for value in self.entries.values_mut().chain(self.committed.values_mut()) {
+            value.zeroize();
+        }
+        self.entries.clear();
+        self.committed.clear();
+    }
+}
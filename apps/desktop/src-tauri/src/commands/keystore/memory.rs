@@ -0,0 +1,119 @@
+//! Keystore backend that never touches the disk, for tests.
+//!
+//! Committed entries are kept in a process-wide map keyed by vault path, so
+//! locking, unlocking and re-initializing the keystore behave as they do
+//! with a file on disk.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use zeroize::{Zeroize, Zeroizing};
+
+use super::KeyBackend;
+
+/// A committed in-memory vault.
+struct MemoryVault {
+    password: Zeroizing<String>,
+    entries: HashMap<String, String>,
+}
+
+/// Every in-memory vault of this process, by path.
+static MEMORY_VAULTS: std::sync::LazyLock<Mutex<HashMap<String, MemoryVault>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn vaults() -> Result<std::sync::MutexGuard<'static, HashMap<String, MemoryVault>>, String> {
+    MEMORY_VAULTS
+        .lock()
+        .map_err(|e| format!("Failed to lock in-memory vaults: {}", e))
+}
+
+/// Uncommitted entries of an in-memory vault.
+pub struct MemoryBackend {
+    path: String,
+    entries: HashMap<String, String>,
+    /// Entries as of the last commit, restored when a commit fails
+    committed: HashMap<String, String>,
+}
+
+impl MemoryBackend {
+    /// Open the in-memory vault at `vault_path`, creating it if it does not
+    /// exist.
+    pub fn open(vault_path: &str, password: &str) -> Result<Self, String> {
+        let mut vaults = vaults()?;
+        let vault = vaults
+            .entry(vault_path.to_string())
+            .or_insert_with(|| MemoryVault {
+                password: Zeroizing::new(password.to_string()),
+                entries: HashMap::new(),
+            });
+        if vault.password.as_str() != password {
+            return Err("Wrong password or corrupt vault".to_string());
+        }
+
+        Ok(MemoryBackend {
+            path: vault_path.to_string(),
+            entries: vault.entries.clone(),
+            committed: vault.entries.clone(),
+        })
+    }
+
+    fn committed<T>(&self, f: impl FnOnce(&mut MemoryVault) -> T) -> Result<T, String> {
+        vaults()?
+            .get_mut(&self.path)
+            .map(f)
+            .ok_or_else(|| format!("In-memory vault '{}' does not exist", self.path))
+    }
+}
+
+impl KeyBackend for MemoryBackend {
+    fn entries(&self) -> Result<HashMap<String, String>, String> {
+        Ok(self.entries.clone())
+    }
+
+    fn insert(&mut self, name: &str, value: &str) -> Result<(), String> {
+        self.entries.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<(), String> {
+        self.entries.remove(name);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        let entries = self.entries.clone();
+        match self.committed(|vault| vault.entries = entries) {
+            Ok(()) => {
+                self.committed = self.entries.clone();
+                Ok(())
+            }
+            Err(e) => {
+                self.entries = self.committed.clone();
+                Err(e)
+            }
+        }
+    }
+
+    fn verify_password(&self, password: &str) -> Result<(), String> {
+        if self.committed(|vault| vault.password.as_str() == password)? {
+            Ok(())
+        } else {
+            Err("Wrong password or corrupt vault".to_string())
+        }
+    }
+
+    fn rekey(&mut self, password: &str) -> Result<(), String> {
+        let entries = self.entries.clone();
+        self.committed(|vault| {
+            vault.password = Zeroizing::new(password.to_string());
+            vault.entries = entries;
+        })
+    }
+
+    fn close(&mut self) {
+        for value in self.entries.values_mut().chain(self.committed.values_mut()) {
+            value.zeroize();
+        }
+        self.entries.clear();
+        self.committed.clear();
+    }
+}
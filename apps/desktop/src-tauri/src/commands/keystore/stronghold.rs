@@ -0,0 +1,186 @@
+//! Keystore backend on a Stronghold snapshot.
+
+use iota_stronghold::{KeyProvider, SnapshotPath, Store, Stronghold};
+use std::collections::HashMap;
+use std::fs;
+use zeroize::Zeroize;
+
+use super::{keystore_kdf, replace_file, KeyBackend};
+use crate::commands::vault_kdf::VaultKdf;
+
+/// Stronghold client that holds the keystore entries.
+const CLIENT_PATH: &[u8] = b"zeusix-keystore";
+
+/// Derive the snapshot key from `password` with the vault's KDF.
+fn keyprovider(kdf: &VaultKdf, password: &str) -> Result<KeyProvider, String> {
+    let key = kdf.derive(password.as_bytes())?;
+    KeyProvider::try_from(key).map_err(|e| format!("Invalid vault key: {:?}", e))
+}
+
+/// An open Stronghold snapshot and the key it is committed with.
+pub struct StrongholdBackend {
+    stronghold: Stronghold,
+    path: SnapshotPath,
+    kdf: VaultKdf,
+    keyprovider: KeyProvider,
+    /// Entries as of the last commit, restored when a commit fails
+    committed: HashMap<String, String>,
+}
+
+impl StrongholdBackend {
+    /// Open the snapshot at `vault_path`, creating it if it does not exist.
+    pub fn open(vault_path: &str, password: &str) -> Result<Self, String> {
+        let kdf = keystore_kdf(vault_path)?;
+        let keyprovider = keyprovider(&kdf, password)?;
+        let path = SnapshotPath::from_path(vault_path);
+        let stronghold = Stronghold::default();
+        let exists = path.exists();
+
+        if exists {
+            stronghold
+                .load_snapshot(&keyprovider, &path)
+                .map_err(|_| "Wrong password or corrupt vault".to_string())?;
+            stronghold
+                .load_client(CLIENT_PATH)
+                .map_err(|e| format!("Failed to load keystore from vault: {}", e))?;
+        } else {
+            stronghold
+                .create_client(CLIENT_PATH)
+                .map_err(|e| format!("Failed to create keystore in vault: {}", e))?;
+        }
+
+        let mut backend = StrongholdBackend {
+            stronghold,
+            path,
+            kdf,
+            keyprovider,
+            committed: HashMap::new(),
+        };
+        if exists {
+            backend.committed = backend.entries()?;
+        } else {
+            backend.commit()?;
+        }
+        Ok(backend)
+    }
+
+    fn store(&self) -> Result<Store, String> {
+        self.stronghold
+            .get_client(CLIENT_PATH)
+            .map(|client| client.store())
+            .map_err(|e| format!("Failed to open keystore client: {}", e))
+    }
+}
+
+impl KeyBackend for StrongholdBackend {
+    fn entries(&self) -> Result<HashMap<String, String>, String> {
+        let store = self.store()?;
+        let mut entries = HashMap::new();
+
+        for name in store
+            .keys()
+            .map_err(|e| format!("Failed to read vault: {}", e))?
+        {
+            let value = store
+                .get(&name)
+                .map_err(|e| format!("Failed to read vault: {}", e))?
+                .unwrap_or_default();
+            match (String::from_utf8(name), String::from_utf8(value)) {
+                (Ok(name), Ok(value)) => entries.insert(name, value),
+                _ => return Err("Corrupt keystore entry in vault".to_string()),
+            };
+        }
+
+        Ok(entries)
+    }
+
+    fn insert(&mut self, name: &str, value: &str) -> Result<(), String> {
+        self.store()?
+            .insert(name.as_bytes().to_vec(), value.as_bytes().to_vec(), None)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to write vault: {}", e))
+    }
+
+    fn remove(&mut self, name: &str) -> Result<(), String> {
+        self.store()?
+            .delete(name.as_bytes())
+            .map(|_| ())
+            .map_err(|e| format!("Failed to write vault: {}", e))
+    }
+
+    /// Write the keystore client into the snapshot and save it to disk. If
+    /// that fails the client is reset to the last committed entries.
+    fn commit(&mut self) -> Result<(), String> {
+        let written = self
+            .stronghold
+            .write_client(CLIENT_PATH)
+            .map_err(|e| format!("Failed to write vault: {}", e))
+            .and_then(|_| {
+                self.stronghold
+                    .commit_with_keyprovider(&self.path, &self.keyprovider)
+                    .map_err(|e| format!("Failed to write vault: {}", e))
+            });
+        if let Err(e) = written {
+            let store = self.store()?;
+            store
+                .clear()
+                .map_err(|e| format!("Failed to reset keystore: {}", e))?;
+            for (name, value) in &self.committed {
+                store
+                    .insert(name.as_bytes().to_vec(), value.as_bytes().to_vec(), None)
+                    .map_err(|e| format!("Failed to reset keystore: {}", e))?;
+            }
+            return Err(e);
+        }
+        self.committed = self.entries()?;
+        Ok(())
+    }
+
+    fn verify_password(&self, password: &str) -> Result<(), String> {
+        Stronghold::default()
+            .load_snapshot(&keyprovider(&self.kdf, password)?, &self.path)
+            .map_err(|_| "Wrong password or corrupt vault".to_string())
+    }
+
+    /// The new snapshot is checked to open with the new key before it
+    /// replaces the vault.
+    fn rekey(&mut self, password: &str) -> Result<(), String> {
+        let keyprovider = keyprovider(&self.kdf, password)?;
+        let vault_path = self.path.as_path().to_path_buf();
+        let mut tmp_path = vault_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp = SnapshotPath::from_path(&tmp_path);
+
+        let written = self
+            .stronghold
+            .write_client(CLIENT_PATH)
+            .map_err(|e| format!("Failed to write vault: {}", e))
+            .and_then(|_| {
+                self.stronghold
+                    .commit_with_keyprovider(&tmp, &keyprovider)
+                    .map_err(|e| format!("Failed to write vault: {}", e))
+            })
+            .and_then(|_| {
+                Stronghold::default()
+                    .load_snapshot(&keyprovider, &tmp)
+                    .map_err(|e| format!("Re-encrypted vault failed to open: {}", e))
+            })
+            .and_then(|_| replace_file(tmp.as_path(), &vault_path));
+        if let Err(e) = written {
+            fs::remove_file(tmp.as_path()).ok();
+            return Err(e);
+        }
+
+        self.keyprovider = keyprovider;
+        Ok(())
+    }
+
+    /// Also wipes Stronghold's decrypted copy of the entries.
+    fn close(&mut self) {
+        for value in self.committed.values_mut() {
+            value.zeroize();
+        }
+        self.committed.clear();
+        self.stronghold.clear().ok();
+    }
+}
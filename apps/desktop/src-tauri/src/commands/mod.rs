@@ -4,3 +4,4 @@ pub mod ptt;
 pub mod screencap;
 pub mod storage;
 pub mod tray;
+pub mod vault_kdf;
@@ -14,6 +14,57 @@ pub struct LocalMessage {
     pub nonce: Option<String>,
 }
 
+/// Open the local database and apply the SQLCipher key.
+///
+/// Tables are created by `init_local_db`, which must have run once.
+pub(crate) fn open_connection(db_path: &str, passphrase: &str) -> Result<Connection, String> {
+    let conn =
+        Connection::open(db_path).map_err(|e| format!("Failed to open database: {}", e))?;
+
+    // SQLCipher encryption key (only effective with bundled-sqlcipher feature)
+    #[cfg(feature = "sqlcipher")]
+    conn.pragma_update(None, "key", passphrase)
+        .map_err(|e| format!("Failed to set encryption key: {}", e))?;
+    let _ = passphrase; // suppress unused warning when sqlcipher is disabled
+
+    Ok(conn)
+}
+
+/// Move a `key_cache` table from the single-key layout (one row per channel)
+/// to per-epoch rows.
+///
+/// Rows in the old layout are not wrapped under the key cache key, so they
+/// could never be unwrapped as an epoch and are dropped; the channel gets a
+/// usable key again at its next rotation.
+fn migrate_key_cache(conn: &Connection) -> Result<(), String> {
+    let has_key_id = conn
+        .prepare("SELECT 1 FROM pragma_table_info('key_cache') WHERE name = 'key_id'")
+        .and_then(|mut stmt| stmt.exists([]))
+        .map_err(|e| format!("Failed to inspect key cache: {}", e))?;
+    if has_key_id {
+        return Ok(());
+    }
+
+    conn.execute_batch(
+        "
+        BEGIN;
+        CREATE TABLE key_cache_epochs (
+            channel_id TEXT NOT NULL,
+            key_id INTEGER NOT NULL,
+            encrypted_key BLOB NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            retired_at TEXT,
+            PRIMARY KEY (channel_id, key_id)
+        );
+        DROP TABLE key_cache;
+        ALTER TABLE key_cache_epochs RENAME TO key_cache;
+        COMMIT;
+        ",
+    )
+    .map_err(|e| format!("Failed to migrate key cache: {}", e))
+}
+
 /// Initialize (or open) the local encrypted SQLite database.
 ///
 /// `db_path` is the filesystem path for the database file.
@@ -34,7 +85,18 @@ pub fn init_local_db(
         .map_err(|e| format!("Failed to set encryption key: {}", e))?;
     let _ = &passphrase; // suppress unused warning when sqlcipher is disabled
 
-    // Create tables if they don't exist
+    create_tables(&conn)?;
+
+    // Store the connection in managed state is not feasible here since we
+    // need to return. Instead we use the app handle to manage state.
+    // For simplicity, we store a success indicator and reopen as needed.
+    // In production, you'd use tauri::Manager to manage the connection.
+
+    Ok(format!("Database initialized at {}", db_path))
+}
+
+/// Create tables if they don't exist, and migrate older layouts.
+fn create_tables(conn: &Connection) -> Result<(), String> {
     conn.execute_batch(
         "
         CREATE TABLE IF NOT EXISTS messages (
@@ -59,20 +121,68 @@ pub fn init_local_db(
         );
 
         CREATE TABLE IF NOT EXISTS key_cache (
-            channel_id TEXT PRIMARY KEY,
+            channel_id TEXT NOT NULL,
+            key_id INTEGER NOT NULL,
             encrypted_key BLOB NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            retired_at TEXT,
+            PRIMARY KEY (channel_id, key_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS contact_verification (
+            contact_id TEXT PRIMARY KEY,
+            x25519_public TEXT NOT NULL,
+            ed25519_public TEXT NOT NULL,
+            verified INTEGER NOT NULL DEFAULT 0,
+            verified_at TEXT,
+            updated_at TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS identity_keys (
+            user_id TEXT PRIMARY KEY,
+            x25519_public TEXT NOT NULL,
+            ed25519_public TEXT NOT NULL,
+            first_seen_at TEXT NOT NULL,
             updated_at TEXT NOT NULL
         );
+
+        CREATE TABLE IF NOT EXISTS sas_verifications (
+            transaction_id TEXT PRIMARY KEY,
+            contact_id TEXT NOT NULL,
+            outcome TEXT NOT NULL,
+            cancel_reason TEXT,
+            completed_at TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS master_keys (
+            user_id TEXT PRIMARY KEY,
+            ed25519_public TEXT NOT NULL,
+            first_seen_at TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS device_keys (
+            user_id TEXT NOT NULL,
+            device_id TEXT NOT NULL,
+            x25519_public TEXT NOT NULL,
+            ed25519_public TEXT NOT NULL,
+            issued_at TEXT NOT NULL,
+            signature TEXT NOT NULL,
+            PRIMARY KEY (user_id, device_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS device_revocations (
+            user_id TEXT NOT NULL,
+            device_id TEXT NOT NULL,
+            revoked_at TEXT NOT NULL,
+            signature TEXT NOT NULL,
+            PRIMARY KEY (user_id, device_id)
+        );
         ",
     )
     .map_err(|e| format!("Failed to create tables: {}", e))?;
 
-    // Store the connection in managed state is not feasible here since we
-    // need to return. Instead we use the app handle to manage state.
-    // For simplicity, we store a success indicator and reopen as needed.
-    // In production, you'd use tauri::Manager to manage the connection.
-
-    Ok(format!("Database initialized at {}", db_path))
+    migrate_key_cache(conn)
 }
 
 /// Store a decrypted message in the local encrypted database.
@@ -233,3 +343,38 @@ pub fn clear_messages(
 
     Ok(rows_deleted as u64)
 }
+
+/// Create a database with every table in `dir` and return its path.
+#[cfg(test)]
+pub(crate) fn test_db(dir: &tempfile::TempDir) -> String {
+    let db_path = dir.path().join("local.db").to_string_lossy().into_owned();
+    create_tables(&open_connection(&db_path, "").unwrap()).unwrap();
+    db_path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_unwrapped_legacy_channel_keys() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE key_cache (
+                 channel_id TEXT PRIMARY KEY,
+                 encrypted_key BLOB NOT NULL,
+                 updated_at TEXT NOT NULL
+             );
+             INSERT INTO key_cache VALUES ('general', x'00', '2024-01-01T00:00:00Z');",
+        )
+        .unwrap();
+
+        create_tables(&conn).unwrap();
+        let rows: u32 = conn
+            .query_row("SELECT COUNT(*) FROM key_cache", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 0);
+        // Idempotent once migrated
+        create_tables(&conn).unwrap();
+    }
+}
@@ -0,0 +1,482 @@
+//! Password hashing for the Stronghold vault.
+//!
+//! The vault key is derived with Argon2id using a random per-install salt.
+//! The salt and the cost parameters live in a small JSON file next to the
+//! vault, so costs can be raised later without breaking existing installs.
+//!
+//! Vaults created before this module used a single unsalted SHA-256 of the
+//! password. `rekey_vault` opens those with the legacy key and re-encrypts
+//! them under the Argon2id key.
+//!
+//! A re-key writes the new snapshot next to the vault first, then the new
+//! config, then moves the snapshot into place, each step atomically. A crash
+//! in between leaves the `.rekey` snapshot behind, and the next
+//! `rekey_vault` finishes or discards it.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use iota_stronghold::{KeyProvider, SnapshotPath, Stronghold};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+use crate::commands::keystore;
+
+/// File name of the KDF config, stored in the same directory as the vault.
+pub const KDF_CONFIG_FILE: &str = "vault_kdf.json";
+
+const KDF_CONFIG_VERSION: u32 = 1;
+const SALT_LEN: usize = 32;
+
+/// Stronghold snapshots are encrypted with a 32-byte key.
+const VAULT_KEY_LEN: u32 = 32;
+
+/// Lower bounds that stop the cost parameters from being tuned into uselessness.
+const MIN_MEMORY_KIB: u32 = 19 * 1024;
+const MIN_ITERATIONS: u32 = 2;
+
+/// Argon2id cost parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of passes over memory
+    pub iterations: u32,
+    /// Degree of parallelism (lanes)
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    fn validate(&self) -> Result<(), String> {
+        if self.memory_kib < MIN_MEMORY_KIB {
+            return Err(format!(
+                "Argon2 memory cost must be at least {} KiB, got {}",
+                MIN_MEMORY_KIB, self.memory_kib
+            ));
+        }
+        if self.iterations < MIN_ITERATIONS {
+            return Err(format!(
+                "Argon2 iterations must be at least {}, got {}",
+                MIN_ITERATIONS, self.iterations
+            ));
+        }
+        if self.parallelism == 0 || self.parallelism > 16 {
+            return Err(format!(
+                "Argon2 parallelism must be between 1 and 16, got {}",
+                self.parallelism
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// On-disk form of the KDF config.
+#[derive(Serialize, Deserialize)]
+struct KdfConfig {
+    version: u32,
+    /// Per-install random salt (base64)
+    salt: String,
+    params: KdfParams,
+}
+
+/// Argon2id key derivation bound to one install's salt and cost parameters.
+#[derive(Clone)]
+pub struct VaultKdf {
+    salt: [u8; SALT_LEN],
+    params: KdfParams,
+    config_path: PathBuf,
+}
+
+impl VaultKdf {
+    /// Read the KDF config at `config_path`, creating it with a fresh salt
+    /// and default parameters on first run.
+    pub fn load_or_create(config_path: &Path) -> Result<Self, String> {
+        if !config_path.exists() {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let kdf = VaultKdf {
+                salt,
+                params: KdfParams::default(),
+                config_path: config_path.to_path_buf(),
+            };
+            kdf.save()?;
+            return Ok(kdf);
+        }
+
+        let json = std::fs::read(config_path)
+            .map_err(|e| format!("Failed to read vault KDF config: {}", e))?;
+        let config: KdfConfig = serde_json::from_slice(&json)
+            .map_err(|e| format!("Corrupt vault KDF config: {}", e))?;
+
+        if config.version != KDF_CONFIG_VERSION {
+            return Err(format!(
+                "Unsupported vault KDF config version {}",
+                config.version
+            ));
+        }
+        config.params.validate()?;
+
+        let salt = BASE64
+            .decode(&config.salt)
+            .map_err(|e| format!("Corrupt vault KDF salt: {}", e))?
+            .try_into()
+            .map_err(|_| "Corrupt vault KDF salt".to_string())?;
+
+        Ok(VaultKdf {
+            salt,
+            params: config.params,
+            config_path: config_path.to_path_buf(),
+        })
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let config = KdfConfig {
+            version: KDF_CONFIG_VERSION,
+            salt: BASE64.encode(self.salt),
+            params: self.params,
+        };
+        let json = serde_json::to_vec_pretty(&config)
+            .map_err(|e| format!("Failed to serialize vault KDF config: {}", e))?;
+
+        if let Some(dir) = self.config_path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create vault directory: {}", e))?;
+        }
+        let mut tmp_path = self.config_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let written = std::fs::write(&tmp_path, json)
+            .map_err(|e| format!("Failed to write vault KDF config: {}", e))
+            .and_then(|_| keystore::replace_file(&tmp_path, &self.config_path));
+        if written.is_err() {
+            std::fs::remove_file(&tmp_path).ok();
+        }
+        written
+    }
+
+    /// Save a copy of this KDF, salt included, as the config at `config_path`.
+    pub fn save_as(&self, config_path: &Path) -> Result<Self, String> {
+        let kdf = VaultKdf {
+            config_path: config_path.to_path_buf(),
+            ..self.clone()
+        };
+        kdf.save()?;
+        Ok(kdf)
+    }
+
+    pub fn params(&self) -> KdfParams {
+        self.params
+    }
+
+    /// Derive the 32-byte vault key from `password`.
+    pub fn derive(&self, password: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: self.params.memory_kib,
+            time_cost: self.params.iterations,
+            lanes: self.params.parallelism,
+            hash_length: VAULT_KEY_LEN,
+            ..argon2::Config::default()
+        };
+
+        argon2::hash_raw(password, &self.salt, &config)
+            .map(Zeroizing::new)
+            .map_err(|e| format!("Argon2 key derivation failed: {}", e))
+    }
+}
+
+/// The pre-Argon2 vault key: unsalted SHA-256 of the password.
+fn legacy_key(password: &[u8]) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(Sha256::digest(password).to_vec())
+}
+
+/// KDF used by the Stronghold plugin, set once at startup by [`init`].
+static VAULT_KDF: std::sync::LazyLock<Mutex<Option<VaultKdf>>> =
+    std::sync::LazyLock::new(|| Mutex::new(None));
+
+/// Load (or create) the KDF config at `config_path` and make it current.
+pub fn init(config_path: &Path) -> Result<(), String> {
+    let kdf = VaultKdf::load_or_create(config_path)?;
+    *VAULT_KDF
+        .lock()
+        .map_err(|e| format!("Failed to lock vault KDF: {}", e))? = Some(kdf);
+    Ok(())
+}
+
+/// The current KDF, or an error if [`init`] has not run.
+pub(crate) fn current() -> Result<VaultKdf, String> {
+    VAULT_KDF
+        .lock()
+        .map_err(|e| format!("Failed to lock vault KDF: {}", e))?
+        .clone()
+        .ok_or_else(|| "Vault KDF not initialized".to_string())
+}
+
+/// Password hash callback for the Stronghold plugin.
+///
+/// The plugin expects an infallible callback. Parameters are validated when
+/// the config is loaded, so derivation only fails if [`init`] never ran,
+/// which is a startup bug.
+pub fn hash_password(password: &str) -> Vec<u8> {
+    current()
+        .and_then(|kdf| kdf.derive(password.as_bytes()))
+        .map(|key| key.to_vec())
+        .expect("vault KDF must be initialized before the vault is opened")
+}
+
+/// Load the snapshot at `path` with `key`, failing on a wrong key.
+fn open_snapshot(path: &SnapshotPath, key: Zeroizing<Vec<u8>>) -> Result<Stronghold, String> {
+    let keyprovider =
+        KeyProvider::try_from(key).map_err(|e| format!("Invalid vault key: {:?}", e))?;
+    let stronghold = Stronghold::default();
+    stronghold
+        .load_snapshot(&keyprovider, path)
+        .map_err(|e| format!("Failed to open vault: {}", e))?;
+    Ok(stronghold)
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VaultMigration {
+    /// Vault already uses the Argon2id key with the requested parameters
+    UpToDate,
+    /// Vault was re-encrypted under the Argon2id key
+    Rekeyed,
+}
+
+/// Finish or discard a re-key of the vault at `path` that was interrupted
+/// by a crash, leaving its new snapshot at `tmp`.
+///
+/// A leftover snapshot that opens with the current key was already recorded
+/// in the config, so it replaces the vault. One that does not was written
+/// before the config, so it is dropped, but only once the vault itself is
+/// known to open; with a wrong password nothing is touched.
+fn recover_rekey(
+    path: &SnapshotPath,
+    tmp: &SnapshotPath,
+    current: &VaultKdf,
+    password: &[u8],
+) -> Result<(), String> {
+    if !tmp.exists() {
+        return Ok(());
+    }
+
+    let key = current.derive(password)?;
+    if open_snapshot(tmp, key.clone()).is_ok() {
+        return keystore::replace_file(tmp.as_path(), path.as_path());
+    }
+    open_snapshot(path, key)
+        .or_else(|_| open_snapshot(path, legacy_key(password)))
+        .map_err(|_| "Wrong password or corrupt vault".to_string())?;
+    std::fs::remove_file(tmp.as_path())
+        .map_err(|e| format!("Failed to remove stale vault re-key: {}", e))
+}
+
+/// Re-encrypt the vault at `vault_path` under the current Argon2id key.
+///
+/// Vaults still using the legacy SHA-256 key are detected and re-keyed.
+/// Passing `params` re-keys with new cost parameters and saves them as the
+/// install's defaults. Must be called on every start before the vault is
+/// opened through the Stronghold plugin, which also completes a re-key
+/// interrupted by a crash.
+#[tauri::command]
+pub fn rekey_vault(
+    vault_path: String,
+    password: String,
+    params: Option<KdfParams>,
+) -> Result<VaultMigration, String> {
+    let current = current()?;
+    let path = SnapshotPath::from_path(&vault_path);
+    if !path.exists() {
+        return Err(format!("Vault '{}' does not exist", vault_path));
+    }
+    let tmp = SnapshotPath::from_path(format!("{}.rekey", vault_path));
+    recover_rekey(&path, &tmp, &current, password.as_bytes())?;
+
+    let target = match params {
+        Some(params) if params != current.params => {
+            params.validate()?;
+            VaultKdf {
+                params,
+                ..current.clone()
+            }
+        }
+        _ => current.clone(),
+    };
+
+    let new_key = target.derive(password.as_bytes())?;
+
+    let stronghold = match open_snapshot(&path, current.derive(password.as_bytes())?) {
+        Ok(_) if target.params == current.params => return Ok(VaultMigration::UpToDate),
+        Ok(stronghold) => stronghold,
+        Err(_) => open_snapshot(&path, legacy_key(password.as_bytes()))
+            .map_err(|_| "Wrong password or corrupt vault".to_string())?,
+    };
+
+    // The new snapshot only replaces the vault once it is known to open and
+    // the parameters it needs are saved.
+    let keyprovider = KeyProvider::try_from(new_key.clone())
+        .map_err(|e| format!("Invalid vault key: {:?}", e))?;
+    let written = stronghold
+        .commit_with_keyprovider(&tmp, &keyprovider)
+        .map_err(|e| format!("Failed to write vault: {}", e))
+        .and_then(|_| open_snapshot(&tmp, new_key).map(|_| ()))
+        .and_then(|_| {
+            if target.params != current.params {
+                target.save()?;
+            }
+            Ok(())
+        });
+    if let Err(e) = written {
+        std::fs::remove_file(tmp.as_path()).ok();
+        return Err(e);
+    }
+
+    if target.params != current.params {
+        *VAULT_KDF
+            .lock()
+            .map_err(|e| format!("Failed to lock vault KDF: {}", e))? = Some(target);
+    }
+    keystore::replace_file(tmp.as_path(), path.as_path())?;
+
+    Ok(VaultMigration::Rekeyed)
+}
+
+/// Current Argon2id cost parameters.
+#[tauri::command]
+pub fn get_vault_kdf_params() -> Result<KdfParams, String> {
+    current().map(|kdf| kdf.params())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "correct horse";
+
+    /// A fresh install KDF and the path of a vault next to it.
+    fn setup(dir: &tempfile::TempDir) -> (PathBuf, SnapshotPath) {
+        // The vault key is already Argon2-derived; skip the snapshot's own
+        // key stretching in tests.
+        iota_stronghold::engine::snapshot::try_set_encrypt_work_factor(0).unwrap();
+        let config_path = dir.path().join(KDF_CONFIG_FILE);
+        init(&config_path).unwrap();
+        (
+            config_path,
+            SnapshotPath::from_path(dir.path().join("vault.stronghold")),
+        )
+    }
+
+    fn write_snapshot(path: &SnapshotPath, key: Zeroizing<Vec<u8>>) {
+        Stronghold::default()
+            .commit_with_keyprovider(path, &KeyProvider::try_from(key).unwrap())
+            .unwrap();
+    }
+
+    fn vault_path(path: &SnapshotPath) -> String {
+        path.as_path().to_string_lossy().into_owned()
+    }
+
+    fn rekey_path(path: &SnapshotPath) -> SnapshotPath {
+        SnapshotPath::from_path(format!("{}.rekey", vault_path(path)))
+    }
+
+    #[test]
+    fn migrates_legacy_vault() {
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let (_, path) = setup(&dir);
+        write_snapshot(&path, legacy_key(PASSWORD.as_bytes()));
+
+        assert!(rekey_vault(vault_path(&path), "wrong password".into(), None).is_err());
+        assert!(open_snapshot(&path, legacy_key(PASSWORD.as_bytes())).is_ok());
+
+        let migration = rekey_vault(vault_path(&path), PASSWORD.into(), None).unwrap();
+        assert_eq!(migration, VaultMigration::Rekeyed);
+        let key = current().unwrap().derive(PASSWORD.as_bytes()).unwrap();
+        assert!(open_snapshot(&path, key).is_ok());
+        assert!(open_snapshot(&path, legacy_key(PASSWORD.as_bytes())).is_err());
+        assert!(!rekey_path(&path).exists());
+
+        let migration = rekey_vault(vault_path(&path), PASSWORD.into(), None).unwrap();
+        assert_eq!(migration, VaultMigration::UpToDate);
+    }
+
+    #[test]
+    fn new_params_are_saved() {
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let (config_path, path) = setup(&dir);
+        write_snapshot(
+            &path,
+            current().unwrap().derive(PASSWORD.as_bytes()).unwrap(),
+        );
+        let params = KdfParams {
+            memory_kib: 32 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        };
+
+        rekey_vault(vault_path(&path), PASSWORD.into(), Some(params)).unwrap();
+
+        // As after a restart
+        init(&config_path).unwrap();
+        assert_eq!(get_vault_kdf_params().unwrap(), params);
+        let key = current().unwrap().derive(PASSWORD.as_bytes()).unwrap();
+        assert!(open_snapshot(&path, key).is_ok());
+    }
+
+    #[test]
+    fn recovers_interrupted_rekey() {
+        let _serial = keystore::TEST_SERIAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let (config_path, path) = setup(&dir);
+        let old = current().unwrap();
+        write_snapshot(&path, old.derive(PASSWORD.as_bytes()).unwrap());
+        let new = VaultKdf {
+            params: KdfParams {
+                memory_kib: 32 * 1024,
+                iterations: 2,
+                parallelism: 1,
+            },
+            ..old.clone()
+        };
+
+        // Crash after the new snapshot, before the config: it is discarded,
+        // but not on a wrong password
+        write_snapshot(&rekey_path(&path), new.derive(PASSWORD.as_bytes()).unwrap());
+        assert!(rekey_vault(vault_path(&path), "wrong password".into(), None).is_err());
+        assert!(rekey_path(&path).exists());
+        let migration = rekey_vault(vault_path(&path), PASSWORD.into(), None).unwrap();
+        assert_eq!(migration, VaultMigration::UpToDate);
+        assert!(!rekey_path(&path).exists());
+
+        // Crash after the config, before the rename: the rename is finished
+        write_snapshot(&rekey_path(&path), new.derive(PASSWORD.as_bytes()).unwrap());
+        new.save().unwrap();
+        init(&config_path).unwrap();
+        let migration = rekey_vault(vault_path(&path), PASSWORD.into(), None).unwrap();
+        assert_eq!(migration, VaultMigration::UpToDate);
+        assert!(!rekey_path(&path).exists());
+        assert!(open_snapshot(&path, new.derive(PASSWORD.as_bytes()).unwrap()).is_ok());
+    }
+}
@@ -1,7 +1,7 @@
 mod commands;
 mod plugins;
 
-use commands::{crypto, keystore, ptt, screencap, storage, tray};
+use commands::{crypto, keystore, ptt, screencap, storage, tray, vault_kdf};
 use tauri::{Emitter, Manager};
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -15,17 +15,13 @@ pub fn run() {
                 win.set_focus().ok();
             }
         }))
-        .plugin(tauri_plugin_stronghold::Builder::new(|password| {
-            use sha2::{Digest, Sha256};
-            let mut hasher = Sha256::new();
-            hasher.update(password);
-            hasher.finalize().to_vec()
-        }).build())
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_notification::init())
+        .plugin(tauri_plugin_dialog::init())
         .setup(|app| {
+            setup_stronghold(app)?;
             auto_grant_permissions(app)?;
             setup_tray(app)?;
             setup_close_to_tray(app)?;
@@ -37,6 +33,91 @@ pub fn run() {
             crypto::encrypt_message,
             crypto::decrypt_message,
             crypto::derive_shared_secret,
+            crypto::handles::release_key,
+            crypto::handles::persist_key,
+            crypto::handles::load_key,
+            crypto::sign_payload,
+            crypto::verify_payload,
+            crypto::seal_envelope,
+            crypto::open_envelope,
+            // Batched message encryption
+            crypto::batch::encrypt_batch,
+            crypto::batch::decrypt_batch,
+            // Encrypted keystore backup
+            crypto::backup::export_backup,
+            crypto::backup::import_backup,
+            // Safety numbers and contact verification
+            crypto::safety::compute_safety_number,
+            crypto::safety::verify_safety_number,
+            crypto::safety::set_contact_verified,
+            crypto::safety::get_contact_verification,
+            // Interactive SAS verification
+            crypto::sas::sas_start,
+            crypto::sas::sas_accept,
+            crypto::sas::sas_receive,
+            crypto::sas::sas_confirm,
+            crypto::sas::sas_cancel,
+            // Trust-on-first-use identity keys
+            crypto::trust::check_identity_key,
+            crypto::trust::accept_identity_key,
+            // Multi-device identity
+            crypto::devices::generate_master_key,
+            crypto::devices::certify_device,
+            crypto::devices::revoke_device,
+            crypto::devices::start_device_link,
+            crypto::devices::approve_device_link,
+            crypto::devices::complete_device_link,
+            crypto::devices::add_device_certificate,
+            crypto::devices::apply_device_revocation,
+            crypto::devices::list_trusted_devices,
+            // Streaming attachment encryption
+            crypto::stream::encrypt_file,
+            crypto::stream::decrypt_file,
+            crypto::stream::pick_attachment_file,
+            crypto::stream::pick_attachment_destination,
+            // HPKE sealed boxes
+            crypto::hpke::hpke_seal,
+            crypto::hpke::hpke_open,
+            // Message padding
+            crypto::padding::set_padding_policy,
+            crypto::padding::get_padding_policy,
+            // Versioned ciphertext envelopes
+            crypto::envelope::encrypt_envelope,
+            crypto::envelope::decrypt_envelope,
+            crypto::envelope::inspect_envelope,
+            // X3DH prekeys and session setup
+            crypto::x3dh::generate_signed_prekey,
+            crypto::x3dh::generate_one_time_prekeys,
+            crypto::x3dh::generate_pq_prekey,
+            crypto::x3dh::create_prekey_bundle,
+            crypto::x3dh::x3dh_initiate,
+            crypto::x3dh::x3dh_respond,
+            // Double Ratchet DM sessions
+            crypto::ratchet::ratchet_init_initiator,
+            crypto::ratchet::ratchet_init_responder,
+            crypto::ratchet::ratchet_encrypt,
+            crypto::ratchet::ratchet_decrypt,
+            // Sender Keys channel encryption
+            crypto::sender_keys::seal_sender_key,
+            crypto::sender_keys::process_sender_key,
+            crypto::sender_keys::group_encrypt,
+            crypto::sender_keys::group_decrypt,
+            crypto::sender_keys::remove_group_member,
+            // Channel key epochs
+            crypto::channel_keys::rotate_channel_key,
+            crypto::channel_keys::seal_channel_key,
+            crypto::channel_keys::add_channel_key,
+            crypto::channel_keys::get_channel_key,
+            crypto::channel_keys::list_channel_key_epochs,
+            crypto::channel_keys::prune_channel_key_epochs,
+            crypto::channel_keys::encrypt_channel_message,
+            crypto::channel_keys::decrypt_channel_message,
+            // Voice and screen-share frame keys
+            crypto::media::join_media_room,
+            crypto::media::media_participant_joined,
+            crypto::media::media_participant_left,
+            crypto::media::export_media_key,
+            crypto::media::leave_media_room,
             // Storage commands
             storage::init_local_db,
             storage::store_message,
@@ -46,6 +127,17 @@ pub fn run() {
             keystore::init_keystore,
             keystore::store_key,
             keystore::get_key,
+            keystore::get_previous_key,
+            keystore::list_keys,
+            keystore::delete_key,
+            keystore::rotate_key,
+            keystore::lock_keystore,
+            keystore::unlock_keystore,
+            keystore::set_keystore_idle_timeout,
+            keystore::change_keystore_password,
+            // Vault password hashing
+            vault_kdf::rekey_vault,
+            vault_kdf::get_vault_kdf_params,
             // PTT (Push-to-Talk) global key listener
             ptt::start_ptt_listener,
             ptt::stop_ptt_listener,
@@ -64,6 +156,23 @@ pub fn run() {
         .expect("error while running ZeusIX");
 }
 
+/// Register the Stronghold plugin with the Argon2id vault KDF.
+///
+/// The KDF config (salt and cost parameters) is kept in the app's local data
+/// directory, where the vault lives.
+fn setup_stronghold(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
+    let config_path = app
+        .path()
+        .app_local_data_dir()?
+        .join(vault_kdf::KDF_CONFIG_FILE);
+    vault_kdf::init(&config_path)?;
+
+    app.handle()
+        .plugin(tauri_plugin_stronghold::Builder::new(vault_kdf::hash_password).build())?;
+
+    Ok(())
+}
+
 fn setup_tray(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
     use tauri::menu::{MenuBuilder, MenuItemBuilder};
     use tauri::tray::{MouseButton, TrayIconBuilder, TrayIconEvent};